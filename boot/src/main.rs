@@ -4,7 +4,7 @@ use std::{
     time::Duration,
 };
 
-const RUN_ARGS: &[&str] = &["--no-reboot", "-s", "-serial", "stdio"];
+const RUN_ARGS: &[&str] = &["--no-reboot", "-s", "-serial", "stdio", "-smp", "4"];
 const TEST_ARGS: &[&str] = &[
     "-device",
     "isa-debug-exit,iobase=0xf4,iosize=0x04",
@@ -13,6 +13,8 @@ const TEST_ARGS: &[&str] = &[
     "-display",
     "none",
     "--no-reboot",
+    "-smp",
+    "4",
 ];
 const TEST_TIMEOUT_SECS: u64 = 10;
 
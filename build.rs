@@ -0,0 +1,94 @@
+//! Builds the programs that the integration tests load:
+//!
+//! - the assembly programs in `tests/programs`, assembled with the GNU
+//!   binutils of the host into the `TEST_PROGRAMS_DIR` directory
+//! - the example programs of the `user` crate, in the `USER_PROGRAMS_DIR`
+//!   directory
+//!
+//! Only the `user-programs` feature, which the tests loading them require,
+//! builds them, so that other builds need neither binutils nor a nested
+//! cargo run.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn main() {
+    if env::var_os("CARGO_FEATURE_USER_PROGRAMS").is_none() {
+        return;
+    }
+
+    let manifest_dir = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap());
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    build_test_programs(&manifest_dir, &out_dir);
+    build_user_programs(&manifest_dir, &out_dir);
+}
+
+fn build_test_programs(manifest_dir: &Path, out_dir: &Path) {
+    let source_dir = manifest_dir.join("tests").join("programs");
+    let target_dir = out_dir.join("programs");
+    fs::create_dir_all(&target_dir).expect("failed to create the test program directory");
+
+    for entry in fs::read_dir(&source_dir).expect("failed to read tests/programs") {
+        let source = entry.unwrap().path();
+        if source
+            .extension()
+            .map_or(true, |extension| extension != "S")
+        {
+            continue;
+        }
+        let program = target_dir.join(source.file_stem().unwrap());
+        let object = program.with_extension("o");
+        run(Command::new("as")
+            .arg("--64")
+            .arg("-o")
+            .arg(&object)
+            .arg(&source));
+        run(Command::new("ld")
+            .args(&["-static", "-nostdlib", "-z", "noexecstack", "-T"])
+            .arg(source_dir.join("link.ld"))
+            .arg("-o")
+            .arg(program.with_extension("elf"))
+            .arg(&object));
+    }
+
+    println!("cargo:rustc-env=TEST_PROGRAMS_DIR={}", target_dir.display());
+    println!("cargo:rerun-if-changed={}", source_dir.display());
+}
+
+fn build_user_programs(manifest_dir: &Path, out_dir: &Path) {
+    let user_dir = manifest_dir.join("user");
+    // a separate target directory, since the outer build holds the lock on
+    // the workspace's one
+    let target_dir = out_dir.join("user");
+    let cargo = env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+
+    run(Command::new(cargo)
+        .current_dir(&user_dir)
+        .arg("build")
+        .arg("--release")
+        .arg("--bins")
+        .arg("--target")
+        .arg(user_dir.join("x86_64-user.json"))
+        .arg("-Zbuild-std=core,compiler_builtins,alloc")
+        .arg("-Zbuild-std-features=compiler-builtins-mem")
+        .arg("--target-dir")
+        .arg(&target_dir)
+        // the flags of the kernel build are not meant for user programs
+        .env_remove("RUSTFLAGS")
+        .env_remove("CARGO_ENCODED_RUSTFLAGS"));
+
+    println!(
+        "cargo:rustc-env=USER_PROGRAMS_DIR={}",
+        target_dir.join("x86_64-user").join("release").display()
+    );
+    println!("cargo:rerun-if-changed={}", user_dir.display());
+}
+
+fn run(command: &mut Command) {
+    let status = command
+        .status()
+        .unwrap_or_else(|error| panic!("failed to run {:?}: {}", command, error));
+    assert!(status.success(), "{:?} failed", command);
+}
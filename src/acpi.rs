@@ -0,0 +1,124 @@
+pub mod madt;
+
+use crate::memory::phys_to_virt;
+use alloc::vec::Vec;
+use core::{mem, ptr, slice};
+use spin::Once;
+use x86_64::PhysAddr;
+
+/// The Root System Description Pointer, as found by the bootloader.
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    // the fields below are only valid for revision 2 and later
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+/// The header shared by all System Description Tables.
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    InvalidRsdpSignature,
+    InvalidRsdpChecksum,
+    InvalidRootTable,
+    AlreadyInitialized,
+}
+
+/// Physical addresses of all tables referenced by the RSDT/XSDT.
+static TABLES: Once<Vec<PhysAddr>> = Once::new();
+
+/// Parses the root tables pointed to by the given RSDP address and records
+/// the location of every System Description Table.
+///
+/// This function is unsafe because the caller must guarantee that the given
+/// address really is the RSDP passed by the bootloader and that the complete
+/// physical memory is mapped (see `memory::init`).
+pub unsafe fn init(rsdp_addr: PhysAddr) -> Result<(), AcpiError> {
+    if TABLES.is_completed() {
+        return Err(AcpiError::AlreadyInitialized);
+    }
+
+    let rsdp: Rsdp = ptr::read_unaligned(phys_to_virt(rsdp_addr).as_ptr());
+    if &rsdp.signature != b"RSD PTR " {
+        return Err(AcpiError::InvalidRsdpSignature);
+    }
+    // the checksum of ACPI 1.0 covers only the first 20 bytes
+    if !checksum_ok(rsdp_addr, 20) {
+        return Err(AcpiError::InvalidRsdpChecksum);
+    }
+
+    // prefer the XSDT with its 64-bit pointers if the firmware provides one
+    let (root_addr, entry_size) = if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+        (PhysAddr::new(rsdp.xsdt_address), mem::size_of::<u64>())
+    } else {
+        (
+            PhysAddr::new(rsdp.rsdt_address as u64),
+            mem::size_of::<u32>(),
+        )
+    };
+
+    let root = read_header(root_addr);
+    let root_length = root.length as usize;
+    if root_length < mem::size_of::<SdtHeader>() || !checksum_ok(root_addr, root_length) {
+        return Err(AcpiError::InvalidRootTable);
+    }
+
+    let entries_start = phys_to_virt(root_addr + mem::size_of::<SdtHeader>());
+    let entry_count = (root_length - mem::size_of::<SdtHeader>()) / entry_size;
+    let tables = (0..entry_count)
+        .map(|i| {
+            let entry_ptr = entries_start.as_ptr::<u8>().add(i * entry_size);
+            let addr = if entry_size == mem::size_of::<u64>() {
+                ptr::read_unaligned(entry_ptr as *const u64)
+            } else {
+                ptr::read_unaligned(entry_ptr as *const u32) as u64
+            };
+            PhysAddr::new(addr)
+        })
+        .collect();
+    TABLES.call_once(|| tables);
+
+    Ok(())
+}
+
+/// Returns the physical address of the first table with the given signature
+/// whose checksum is valid, or `None` if ACPI is not initialized or there is
+/// no such table.
+pub fn find_table(signature: &[u8; 4]) -> Option<PhysAddr> {
+    TABLES.get()?.iter().copied().find(|&addr| {
+        let header = read_header(addr);
+        &header.signature == signature && checksum_ok(addr, header.length as usize)
+    })
+}
+
+/// Reads the System Description Table header at the given physical address.
+pub fn read_header(addr: PhysAddr) -> SdtHeader {
+    unsafe { ptr::read_unaligned(phys_to_virt(addr).as_ptr()) }
+}
+
+/// All bytes of an ACPI structure (including its checksum field) must sum to zero.
+fn checksum_ok(addr: PhysAddr, length: usize) -> bool {
+    let bytes = unsafe { slice::from_raw_parts(phys_to_virt(addr).as_ptr::<u8>(), length) };
+    bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+}
@@ -0,0 +1,75 @@
+use super::{find_table, read_header, SdtHeader};
+use crate::memory::phys_to_virt;
+use alloc::vec::Vec;
+use core::{mem, ptr};
+use x86_64::PhysAddr;
+
+const ENTRY_LOCAL_APIC: u8 = 0;
+const ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE: u8 = 5;
+
+const LOCAL_APIC_ENABLED: u32 = 1 << 0;
+
+/// A processor described by a Processor Local APIC entry of the MADT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Processor {
+    /// The ID of the processor object in the ACPI namespace.
+    pub acpi_id: u8,
+    pub apic_id: u8,
+    /// Disabled processors are unusable unless they are marked as online
+    /// capable, which would require ACPI hot-plug support.
+    pub enabled: bool,
+}
+
+/// The contents of the Multiple APIC Description Table that the kernel uses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Madt {
+    /// The physical address of the local APIC registers of every processor.
+    pub local_apic_address: PhysAddr,
+    /// All processors in the order of the table, which lists the bootstrap
+    /// processor first.
+    pub processors: Vec<Processor>,
+}
+
+/// Reads the MADT (signature `APIC`). Returns `None` if ACPI is not
+/// initialized or there is no such table.
+pub fn parse() -> Option<Madt> {
+    let table = find_table(b"APIC")?;
+    let length = read_header(table).length as usize;
+    let read = |offset: usize| phys_to_virt(table + offset).as_ptr::<u8>();
+
+    // the header is followed by the local APIC address and a flags field
+    let entries_start = mem::size_of::<SdtHeader>() + 8;
+    let mut madt = Madt {
+        local_apic_address: PhysAddr::new(unsafe {
+            ptr::read_unaligned(read(mem::size_of::<SdtHeader>()) as *const u32)
+        } as u64),
+        processors: Vec::new(),
+    };
+
+    // every entry starts with its type and length
+    let mut offset = entries_start;
+    while offset + 2 <= length {
+        let (kind, entry_length) = unsafe { (*read(offset), *read(offset + 1) as usize) };
+        if entry_length < 2 || offset + entry_length > length {
+            break;
+        }
+        match kind {
+            ENTRY_LOCAL_APIC if entry_length >= 8 => {
+                let flags = unsafe { ptr::read_unaligned(read(offset + 4) as *const u32) };
+                madt.processors.push(Processor {
+                    acpi_id: unsafe { *read(offset + 2) },
+                    apic_id: unsafe { *read(offset + 3) },
+                    enabled: flags & LOCAL_APIC_ENABLED != 0,
+                });
+            }
+            ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE if entry_length >= 12 => {
+                let address = unsafe { ptr::read_unaligned(read(offset + 4) as *const u64) };
+                madt.local_apic_address = PhysAddr::new(address);
+            }
+            _ => {}
+        }
+        offset += entry_length;
+    }
+
+    Some(madt)
+}
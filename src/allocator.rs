@@ -23,21 +23,47 @@ pub struct Locked<A> {
 }
 
 pub const HEAP_START: usize = 0x_4444_4444_0000; // start address of the kernel heap
-pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
+pub const HEAP_SIZE: usize = 256 * 1024; // 256 KiB, which also holds the boot stacks of the APs
+
+pub const STACK_HEAP_START: usize = 0x_4444_8888_0000; // start address of the kernel thread stacks
+pub const STACK_HEAP_SIZE: usize = 1024 * 1024; // 1 MiB
 
 #[global_allocator]
 static ALLOCATOR: Locked<FixedSizeBlockAllocator> = Locked::new(FixedSizeBlockAllocator::empty());
 
+/// Allocates the kernel thread stacks, so that they neither crowd out the
+/// kernel heap nor need a heap large enough for them.
+pub static STACK_ALLOCATOR: LockedHeap = LockedHeap::empty();
+
 pub fn init_heap(
     mapper: &mut impl Mapper<Size4KiB>,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    map_region(HEAP_START, HEAP_SIZE, mapper, frame_allocator)?;
+    map_region(STACK_HEAP_START, STACK_HEAP_SIZE, mapper, frame_allocator)?;
+
+    unsafe {
+        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+        STACK_ALLOCATOR
+            .lock()
+            .init(STACK_HEAP_START, STACK_HEAP_SIZE);
+    }
+
+    Ok(())
+}
+
+fn map_region(
+    start: usize,
+    size: usize,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
 ) -> Result<(), MapToError<Size4KiB>> {
     let page_range = {
-        let heap_start = VirtAddr::new(HEAP_START as u64);
-        let heap_end = heap_start + HEAP_SIZE - 1u64;
-        let heap_start_page = Page::containing_address(heap_start);
-        let heap_end_page = Page::containing_address(heap_end);
-        Page::range_inclusive(heap_start_page, heap_end_page)
+        let region_start = VirtAddr::new(start as u64);
+        let region_end = region_start + size - 1u64;
+        let start_page = Page::containing_address(region_start);
+        let end_page = Page::containing_address(region_end);
+        Page::range_inclusive(start_page, end_page)
     };
 
     for page in page_range {
@@ -47,11 +73,6 @@ pub fn init_heap(
         let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
         unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
     }
-
-    unsafe {
-        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
-    }
-
     Ok(())
 }
 
@@ -1,7 +1,10 @@
 use crate::allocator::Locked;
+use crate::percpu::{self, this_cpu};
 use alloc::alloc::Layout;
 use core::alloc::GlobalAlloc;
+use core::cell::UnsafeCell;
 use core::{mem, ptr};
+use x86_64::instructions::interrupts::without_interrupts;
 
 struct ListNode {
     next: Option<&'static mut ListNode>,
@@ -13,6 +16,70 @@ struct ListNode {
 /// the block alignment (alignments must be always powers of 2)
 const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
 
+/// The number of free blocks of each size a CPU keeps in its cache.
+const CACHE_LIMIT: usize = 16;
+
+/// Free blocks that one CPU can allocate without taking the allocator lock.
+///
+/// Each CPU has one in its `PerCpu` area and only uses it with interrupts
+/// disabled, so there is no concurrent access.
+pub struct BlockCache {
+    list_heads: UnsafeCell<[Option<&'static mut ListNode>; BLOCK_SIZES.len()]>,
+    lengths: UnsafeCell<[usize; BLOCK_SIZES.len()]>,
+}
+
+impl BlockCache {
+    pub const fn new() -> Self {
+        const EMPTY: Option<&'static mut ListNode> = None;
+        BlockCache {
+            list_heads: UnsafeCell::new([EMPTY; BLOCK_SIZES.len()]),
+            lengths: UnsafeCell::new([0; BLOCK_SIZES.len()]),
+        }
+    }
+
+    /// Takes a block of size class `index` from the cache.
+    ///
+    /// Unsafe because the caller must own the cache and have interrupts
+    /// disabled.
+    unsafe fn pop(&self, index: usize) -> Option<*mut u8> {
+        let list_heads = &mut *self.list_heads.get();
+        let node = list_heads[index].take()?;
+        list_heads[index] = node.next.take();
+        (*self.lengths.get())[index] -= 1;
+        Some(node as *mut ListNode as *mut u8)
+    }
+
+    /// Puts a free block of size class `index` into the cache. Returns
+    /// `false` if the cache of that size is full.
+    ///
+    /// Unsafe for the same reasons as `pop`, and because `ptr` must be an
+    /// unused block of that size.
+    unsafe fn push(&self, index: usize, ptr: *mut u8) -> bool {
+        let lengths = &mut *self.lengths.get();
+        if lengths[index] >= CACHE_LIMIT {
+            return false;
+        }
+        let list_heads = &mut *self.list_heads.get();
+        let new_node_ptr = ptr as *mut ListNode;
+        new_node_ptr.write(ListNode {
+            next: list_heads[index].take(),
+        });
+        list_heads[index] = Some(&mut *new_node_ptr);
+        lengths[index] += 1;
+        true
+    }
+}
+
+/// Returns the cache of the calling CPU, or `None` before the per-CPU areas
+/// are set up.
+fn cpu_cache() -> Option<&'static BlockCache> {
+    if percpu::is_initialized() {
+        Some(this_cpu!(allocator_cache))
+    } else {
+        None
+    }
+}
+
 pub struct FixedSizeBlockAllocator {
     list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
     fallback_allocator: linked_list_allocator::Heap,
@@ -56,45 +123,64 @@ fn list_index(layout: &Layout) -> Option<usize> {
 
 unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let mut allocator = self.lock();
-        match list_index(&layout) {
-            Some(index) => {
-                match allocator.list_heads[index].take() {
-                    Some(node) => {
-                        allocator.list_heads[index] = node.next.take();
-                        node as *mut ListNode as *mut u8
-                    }
-                    None => {
-                        // no block exists in list => allocate new block
-                        let block_size = BLOCK_SIZES[index];
-                        // only works if all block sizes are a power of 2
-                        let block_align = block_size;
-                        let layout = Layout::from_size_align(block_size, block_align).unwrap();
-                        allocator.fallback_alloc(layout)
+        // a preempted thread must not hold the lock, see `thread`
+        without_interrupts(|| {
+            let index = list_index(&layout);
+            if let (Some(index), Some(cache)) = (index, cpu_cache()) {
+                if let Some(block) = cache.pop(index) {
+                    return block;
+                }
+            }
+
+            let mut allocator = self.lock();
+            match index {
+                Some(index) => {
+                    match allocator.list_heads[index].take() {
+                        Some(node) => {
+                            allocator.list_heads[index] = node.next.take();
+                            node as *mut ListNode as *mut u8
+                        }
+                        None => {
+                            // no block exists in list => allocate new block
+                            let block_size = BLOCK_SIZES[index];
+                            // only works if all block sizes are a power of 2
+                            let block_align = block_size;
+                            let layout = Layout::from_size_align(block_size, block_align).unwrap();
+                            allocator.fallback_alloc(layout)
+                        }
                     }
                 }
+                None => allocator.fallback_alloc(layout),
             }
-            None => allocator.fallback_alloc(layout),
-        }
+        })
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        let mut allocator = self.lock();
-        match list_index(&layout) {
-            Some(index) => {
-                let new_node = ListNode {
-                    next: allocator.list_heads[index].take(),
-                };
-                assert!(mem::size_of::<ListNode>() <= BLOCK_SIZES[index]);
-                assert!(mem::align_of::<ListNode>() <= BLOCK_SIZES[index]);
-                let new_node_ptr = ptr as *mut ListNode;
-                new_node_ptr.write(new_node);
-                allocator.list_heads[index] = Some(&mut *new_node_ptr);
+        without_interrupts(|| {
+            let index = list_index(&layout);
+            if let (Some(index), Some(cache)) = (index, cpu_cache()) {
+                if cache.push(index, ptr) {
+                    return;
+                }
             }
-            None => {
-                let ptr = ptr::NonNull::new(ptr).unwrap();
-                allocator.fallback_allocator.deallocate(ptr, layout);
+
+            let mut allocator = self.lock();
+            match index {
+                Some(index) => {
+                    let new_node = ListNode {
+                        next: allocator.list_heads[index].take(),
+                    };
+                    assert!(mem::size_of::<ListNode>() <= BLOCK_SIZES[index]);
+                    assert!(mem::align_of::<ListNode>() <= BLOCK_SIZES[index]);
+                    let new_node_ptr = ptr as *mut ListNode;
+                    new_node_ptr.write(new_node);
+                    allocator.list_heads[index] = Some(&mut *new_node_ptr);
+                }
+                None => {
+                    let ptr = ptr::NonNull::new(ptr).unwrap();
+                    allocator.fallback_allocator.deallocate(ptr, layout);
+                }
             }
-        }
+        })
     }
 }
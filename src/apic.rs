@@ -0,0 +1,155 @@
+//! The local APIC of each processor in xAPIC mode.
+//!
+//! Device interrupts still arrive through the PICs at the bootstrap
+//! processor; the local APICs are only used to send inter-processor
+//! interrupts (IPIs), e.g. to start the other processors (see `smp`) or to
+//! invalidate their TLBs (see `memory::tlb`).
+
+use crate::memory::phys_to_virt;
+use core::ptr;
+use spin::Once;
+use x86_64::{PhysAddr, VirtAddr};
+
+const ID_REGISTER: usize = 0x20;
+const EOI_REGISTER: usize = 0xB0;
+const SPURIOUS_VECTOR_REGISTER: usize = 0xF0;
+const ERROR_STATUS_REGISTER: usize = 0x280;
+const INTERRUPT_COMMAND_LOW: usize = 0x300;
+const INTERRUPT_COMMAND_HIGH: usize = 0x310;
+
+const SOFTWARE_ENABLE: u32 = 1 << 8;
+
+const DELIVERY_MODE_FIXED: u32 = 0;
+const DELIVERY_MODE_NMI: u32 = 0b100 << 8;
+const DELIVERY_MODE_INIT: u32 = 0b101 << 8;
+const DELIVERY_MODE_STARTUP: u32 = 0b110 << 8;
+const DELIVERY_STATUS_PENDING: u32 = 1 << 12;
+const LEVEL_ASSERT: u32 = 1 << 14;
+
+const SHORTHAND_SELF: u32 = 0b01 << 18;
+const SHORTHAND_ALL_INCLUDING_SELF: u32 = 0b10 << 18;
+const SHORTHAND_ALL_EXCLUDING_SELF: u32 = 0b11 << 18;
+
+/// The vector of spurious interrupts of the local APIC. Its low four bits
+/// must be set on older processors.
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+/// The processors an IPI is sent to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Destination {
+    /// The processor with the given APIC ID.
+    Apic(u8),
+    /// The calling processor.
+    Myself,
+    AllIncludingSelf,
+    AllExcludingSelf,
+}
+
+/// The registers are at the same address on every processor, but each
+/// processor only sees its own APIC there.
+static BASE: Once<VirtAddr> = Once::new();
+
+/// Records the address of the local APIC registers and enables the local
+/// APIC of the calling processor.
+///
+/// This function is unsafe because the caller must guarantee that `address`
+/// is the local APIC address from the MADT and that the complete physical
+/// memory is mapped (see `memory::init`).
+pub unsafe fn init(address: PhysAddr) {
+    BASE.call_once(|| phys_to_virt(address));
+    enable();
+}
+
+/// Returns `true` once `init` has been called.
+pub fn is_initialized() -> bool {
+    BASE.is_completed()
+}
+
+/// Enables the local APIC of the calling processor, which is needed to
+/// receive IPIs. Every processor has to call this after `init`.
+pub fn enable() {
+    let value = read(SPURIOUS_VECTOR_REGISTER) & !0xFF;
+    write(
+        SPURIOUS_VECTOR_REGISTER,
+        value | SOFTWARE_ENABLE | u32::from(SPURIOUS_VECTOR),
+    );
+}
+
+/// Returns the APIC ID of the calling processor.
+pub fn id() -> u8 {
+    (read(ID_REGISTER) >> 24) as u8
+}
+
+/// Signals the end of an interrupt that was delivered by the local APIC.
+/// Spurious interrupts must not be acknowledged.
+pub fn end_of_interrupt() {
+    write(EOI_REGISTER, 0);
+}
+
+/// Sends an interrupt on `vector` to the given processors. The handler has
+/// to acknowledge it with `end_of_interrupt`.
+///
+/// Vectors below 32 are reserved for exceptions and are rejected by the
+/// APIC.
+pub fn send_ipi(destination: Destination, vector: u8) {
+    send_command(destination, DELIVERY_MODE_FIXED | u32::from(vector));
+}
+
+/// Sends a non-maskable interrupt to the given processors, which arrives even
+/// if they have interrupts disabled.
+pub fn send_nmi(destination: Destination) {
+    send_command(destination, DELIVERY_MODE_NMI);
+}
+
+/// Sends an INIT IPI, which resets the given processor into the state in
+/// which it waits for a startup IPI.
+pub fn send_init(apic_id: u8) {
+    send_command(
+        Destination::Apic(apic_id),
+        DELIVERY_MODE_INIT | LEVEL_ASSERT,
+    );
+}
+
+/// Sends a startup IPI, which lets a processor that waits after an INIT IPI
+/// start executing in real mode at physical address `page * 4096`.
+pub fn send_startup(apic_id: u8, page: u8) {
+    send_command(
+        Destination::Apic(apic_id),
+        DELIVERY_MODE_STARTUP | u32::from(page),
+    );
+}
+
+/// Writes the interrupt command register and waits until the IPI is sent.
+fn send_command(destination: Destination, command: u32) {
+    use x86_64::instructions::interrupts::without_interrupts;
+
+    let (apic_id, shorthand) = match destination {
+        Destination::Apic(apic_id) => (apic_id, 0),
+        Destination::Myself => (0, SHORTHAND_SELF),
+        Destination::AllIncludingSelf => (0, SHORTHAND_ALL_INCLUDING_SELF),
+        Destination::AllExcludingSelf => (0, SHORTHAND_ALL_EXCLUDING_SELF),
+    };
+    // an interrupt handler sending an IPI in between would overwrite the
+    // destination in the upper half
+    without_interrupts(|| {
+        write(ERROR_STATUS_REGISTER, 0);
+        write(INTERRUPT_COMMAND_HIGH, u32::from(apic_id) << 24);
+        // writing the lower half sends the IPI
+        write(INTERRUPT_COMMAND_LOW, command | shorthand);
+        while read(INTERRUPT_COMMAND_LOW) & DELIVERY_STATUS_PENDING != 0 {
+            core::hint::spin_loop();
+        }
+    });
+}
+
+fn base() -> VirtAddr {
+    *BASE.get().expect("local APIC is not initialized")
+}
+
+fn read(register: usize) -> u32 {
+    unsafe { ptr::read_volatile((base() + register).as_ptr()) }
+}
+
+fn write(register: usize, value: u32) {
+    unsafe { ptr::write_volatile((base() + register).as_mut_ptr(), value) }
+}
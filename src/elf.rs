@@ -0,0 +1,279 @@
+//! Parsing and loading of ELF64 executables for user mode.
+//!
+//! Only statically linked x86_64 executables (`ET_EXEC`) are supported. All
+//! loadable segments must lie in user space and must not share pages with
+//! each other. `ElfFile::parse` checks all of this up front, so `loader`
+//! never has to undo a half-loaded program because of a malformed file.
+
+pub mod loader;
+
+pub use loader::{load, Program};
+
+use crate::usermode;
+use core::convert::TryInto;
+use x86_64::VirtAddr;
+
+const MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const CLASS_64: u8 = 2;
+const DATA_LITTLE_ENDIAN: u8 = 1;
+const VERSION_CURRENT: u8 = 1;
+const TYPE_EXECUTABLE: u16 = 2;
+const MACHINE_X86_64: u16 = 62;
+
+const HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+pub const PT_LOAD: u32 = 1;
+pub const PT_INTERP: u32 = 3;
+pub const PT_PHDR: u32 = 6;
+
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    /// The file is shorter than a header or table it contains.
+    Truncated,
+    BadMagic,
+    /// The file is not a little-endian ELF64 file of the current version.
+    UnsupportedFormat,
+    /// The file is not a statically linked executable.
+    NotExecutable,
+    WrongMachine,
+    /// A segment's file contents lie outside of the file or are larger than
+    /// the segment.
+    BadSegment,
+    /// A loadable segment lies outside of the part of user space for
+    /// programs, below `loader::PROGRAM_END`.
+    SegmentOutsideUserSpace,
+    /// Two loadable segments share a page.
+    OverlappingSegments,
+    /// The entry point lies outside of the executable segments.
+    BadEntryPoint,
+    /// The arguments and environment do not fit onto the user stack.
+    ArgumentsTooLong,
+    /// No frame is left for the segments, the stack or the page tables.
+    OutOfMemory,
+}
+
+/// A program header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramHeader {
+    pub kind: u32,
+    /// The `PF_*` flags.
+    pub flags: u32,
+    pub offset: u64,
+    pub virtual_address: u64,
+    pub file_size: u64,
+    pub memory_size: u64,
+    pub align: u64,
+}
+
+impl ProgramHeader {
+    fn parse(bytes: &[u8]) -> Self {
+        ProgramHeader {
+            kind: read_u32(bytes, 0),
+            flags: read_u32(bytes, 4),
+            offset: read_u64(bytes, 8),
+            virtual_address: read_u64(bytes, 16),
+            file_size: read_u64(bytes, 32),
+            memory_size: read_u64(bytes, 40),
+            align: read_u64(bytes, 48),
+        }
+    }
+
+    pub fn is_load(&self) -> bool {
+        self.kind == PT_LOAD
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.flags & PF_W != 0
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.flags & PF_X != 0
+    }
+
+    /// Returns the address range the segment occupies in memory.
+    pub fn memory_range(&self) -> core::ops::Range<u64> {
+        self.virtual_address..self.virtual_address + self.memory_size
+    }
+
+    /// Returns the pages the segment occupies, as the start of the first and
+    /// the end of the last one.
+    fn page_range(&self) -> core::ops::Range<u64> {
+        let range = self.memory_range();
+        (range.start & !0xFFF)..((range.end + 0xFFF) & !0xFFF)
+    }
+}
+
+/// A validated ELF64 executable.
+#[derive(Debug, Clone, Copy)]
+pub struct ElfFile<'a> {
+    data: &'a [u8],
+    entry: u64,
+    program_header_offset: usize,
+    program_header_count: usize,
+}
+
+impl<'a> ElfFile<'a> {
+    /// Checks that `data` is an executable that can be loaded into user space.
+    pub fn parse(data: &'a [u8]) -> Result<Self, ElfError> {
+        if data.len() < HEADER_SIZE {
+            return Err(ElfError::Truncated);
+        }
+        if data[..4] != MAGIC {
+            return Err(ElfError::BadMagic);
+        }
+        if data[4] != CLASS_64 || data[5] != DATA_LITTLE_ENDIAN || data[6] != VERSION_CURRENT {
+            return Err(ElfError::UnsupportedFormat);
+        }
+        if read_u16(data, 16) != TYPE_EXECUTABLE {
+            return Err(ElfError::NotExecutable);
+        }
+        if read_u16(data, 18) != MACHINE_X86_64 {
+            return Err(ElfError::WrongMachine);
+        }
+        if usize::from(read_u16(data, 54)) != PROGRAM_HEADER_SIZE {
+            return Err(ElfError::UnsupportedFormat);
+        }
+
+        let program_header_offset = read_u64(data, 32) as usize;
+        let program_header_count = usize::from(read_u16(data, 56));
+        let table_end = program_header_count
+            .checked_mul(PROGRAM_HEADER_SIZE)
+            .and_then(|size| size.checked_add(program_header_offset));
+        if table_end.map_or(true, |end| end > data.len()) {
+            return Err(ElfError::Truncated);
+        }
+
+        let file = ElfFile {
+            data,
+            entry: read_u64(data, 24),
+            program_header_offset,
+            program_header_count,
+        };
+        file.check_segments()?;
+        Ok(file)
+    }
+
+    fn check_segments(&self) -> Result<(), ElfError> {
+        let mut entry_is_executable = false;
+        for (index, header) in self.program_headers().enumerate() {
+            if header.kind == PT_INTERP {
+                return Err(ElfError::NotExecutable);
+            }
+            if !header.is_load() {
+                continue;
+            }
+            let file_end = header.offset.checked_add(header.file_size);
+            if file_end.map_or(true, |end| end > self.data.len() as u64)
+                || header.file_size > header.memory_size
+            {
+                return Err(ElfError::BadSegment);
+            }
+            let start = VirtAddr::try_new(header.virtual_address)
+                .map_err(|_| ElfError::SegmentOutsideUserSpace)?;
+            if !usermode::is_user_range(start, header.memory_size as usize) {
+                return Err(ElfError::SegmentOutsideUserSpace);
+            }
+            let pages = header.page_range();
+            // processes map shared memory and their heap above
+            if pages.end > loader::PROGRAM_END {
+                return Err(ElfError::SegmentOutsideUserSpace);
+            }
+            let overlaps = self
+                .program_headers()
+                .take(index)
+                .filter(|other| other.is_load())
+                .any(|other| {
+                    let other = other.page_range();
+                    pages.start < other.end && other.start < pages.end
+                });
+            if overlaps {
+                return Err(ElfError::OverlappingSegments);
+            }
+            if header.is_executable() && header.memory_range().contains(&self.entry) {
+                entry_is_executable = true;
+            }
+        }
+        if !entry_is_executable {
+            return Err(ElfError::BadEntryPoint);
+        }
+        Ok(())
+    }
+
+    pub fn entry(&self) -> VirtAddr {
+        VirtAddr::new(self.entry)
+    }
+
+    pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + 'a {
+        let table = &self.data[self.program_header_offset..];
+        table
+            .chunks_exact(PROGRAM_HEADER_SIZE)
+            .take(self.program_header_count)
+            .map(ProgramHeader::parse)
+    }
+
+    /// Returns the file contents of a segment.
+    pub fn segment_data(&self, header: &ProgramHeader) -> &'a [u8] {
+        let start = header.offset as usize;
+        &self.data[start..start + header.file_size as usize]
+    }
+
+    /// Returns the address at which the program headers are mapped, if a
+    /// loadable segment contains them.
+    pub fn program_headers_address(&self) -> Option<VirtAddr> {
+        let offset = self.program_header_offset as u64;
+        let size = (self.program_header_count * PROGRAM_HEADER_SIZE) as u64;
+        self.program_headers()
+            .find(|header| {
+                header.is_load()
+                    && header.offset <= offset
+                    && offset + size <= header.offset + header.file_size
+            })
+            .map(|header| VirtAddr::new(header.virtual_address + offset - header.offset))
+    }
+
+    pub fn program_header_count(&self) -> usize {
+        self.program_header_count
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+#[test_case]
+fn test_rejects_bad_magic() {
+    let mut data = [0u8; HEADER_SIZE];
+    data[..4].copy_from_slice(b"\x7FELG");
+    assert_eq!(ElfFile::parse(&data).unwrap_err(), ElfError::BadMagic);
+}
+
+#[test_case]
+fn test_rejects_truncated_file() {
+    assert_eq!(ElfFile::parse(&MAGIC).unwrap_err(), ElfError::Truncated);
+}
+
+#[test_case]
+fn test_rejects_32_bit_file() {
+    let mut data = [0u8; HEADER_SIZE];
+    data[..4].copy_from_slice(&MAGIC);
+    data[4] = 1;
+    data[5] = DATA_LITTLE_ENDIAN;
+    data[6] = VERSION_CURRENT;
+    assert_eq!(
+        ElfFile::parse(&data).unwrap_err(),
+        ElfError::UnsupportedFormat
+    );
+}
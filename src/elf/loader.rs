@@ -0,0 +1,223 @@
+use super::{ElfError, ElfFile, PROGRAM_HEADER_SIZE};
+use crate::memory::AddressSpace;
+use crate::thread;
+use crate::usermode::{self, USER_SPACE_END, USER_SPACE_START};
+use alloc::vec::Vec;
+use core::mem;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Page, PageTableFlags, Size4KiB,
+};
+use x86_64::VirtAddr;
+
+/// The end of the area for the segments of programs. The memory above is left
+/// to the shared memory and the heap of processes (see `process`) and to the
+/// stack.
+pub const PROGRAM_END: u64 = USER_SPACE_START + 0x0400_0000_0000;
+
+/// The end of the user stack. The page above it stays unmapped.
+pub const STACK_TOP: u64 = USER_SPACE_END - 4096;
+
+/// The size of the user stack, which does not grow.
+pub const STACK_SIZE: u64 = 64 * 1024;
+
+/// The entry types of the auxiliary vector.
+pub const AT_NULL: u64 = 0;
+pub const AT_PHDR: u64 = 3;
+pub const AT_PHENT: u64 = 4;
+pub const AT_PHNUM: u64 = 5;
+pub const AT_PAGESZ: u64 = 6;
+pub const AT_ENTRY: u64 = 9;
+
+/// A program loaded into its own address space, ready to run.
+pub struct Program {
+    pub address_space: AddressSpace,
+    pub entry: VirtAddr,
+    /// The initial stack pointer, which points to `argc`.
+    pub stack_pointer: VirtAddr,
+}
+
+impl Program {
+    /// Switches the calling thread to the program's address space and starts
+    /// the program in user mode.
+    pub fn run(self) -> ! {
+        unsafe {
+            thread::set_address_space(self.address_space.level_4_frame());
+            usermode::enter(self.entry, self.stack_pointer)
+        }
+    }
+}
+
+/// Loads `file` into a new address space and sets up a stack with the given
+/// arguments and environment variables (`KEY=value`), laid out as the System
+/// V ABI describes it:
+///
+/// ```text
+/// stack_pointer -> argc
+///                  argv[0], ..., argv[argc - 1], 0
+///                  envp[0], ..., 0
+///                  auxiliary vector (type, value pairs), AT_NULL, 0
+///                  strings
+/// STACK_TOP
+/// ```
+///
+/// On errors, all frames taken from `frame_allocator` are given back to it.
+pub fn load(
+    file: &ElfFile,
+    args: &[&str],
+    env: &[&str],
+    frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>),
+) -> Result<Program, ElfError> {
+    let mut address_space = AddressSpace::new(frame_allocator).ok_or(ElfError::OutOfMemory)?;
+    match load_into(&mut address_space, file, args, env, frame_allocator) {
+        Ok(stack_pointer) => Ok(Program {
+            address_space,
+            entry: file.entry(),
+            stack_pointer,
+        }),
+        Err(err) => {
+            // the address space was never active and nothing refers to it
+            unsafe { address_space.free(frame_allocator) };
+            Err(err)
+        }
+    }
+}
+
+/// Maps the segments and the stack into `address_space` and returns the
+/// initial stack pointer.
+fn load_into(
+    address_space: &mut AddressSpace,
+    file: &ElfFile,
+    args: &[&str],
+    env: &[&str],
+    frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>),
+) -> Result<VirtAddr, ElfError> {
+    for header in file.program_headers().filter(|header| header.is_load()) {
+        let mut flags = PageTableFlags::empty();
+        if header.is_writable() {
+            flags |= PageTableFlags::WRITABLE;
+        }
+        if !header.is_executable() {
+            flags |= PageTableFlags::NO_EXECUTE;
+        }
+        let range = header.page_range();
+        map(
+            address_space,
+            range.start,
+            range.end,
+            flags,
+            frame_allocator,
+        )?;
+        // the rest of the segment stays zeroed, which makes up `.bss`
+        address_space
+            .write(
+                VirtAddr::new(header.virtual_address),
+                file.segment_data(&header),
+            )
+            .expect("segment was just mapped");
+    }
+
+    let stack_flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    map(
+        address_space,
+        STACK_TOP - STACK_SIZE,
+        STACK_TOP,
+        stack_flags,
+        frame_allocator,
+    )?;
+    write_stack(address_space, file, args, env)
+}
+
+fn map(
+    address_space: &mut AddressSpace,
+    start: u64,
+    end: u64,
+    flags: PageTableFlags,
+    frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>),
+) -> Result<(), ElfError> {
+    let pages = Page::range(
+        Page::containing_address(VirtAddr::new(start)),
+        Page::containing_address(VirtAddr::new(end)),
+    );
+    usermode::map_user_pages(&mut address_space.mapper(), frame_allocator, pages, flags).map_err(
+        |err| match err {
+            MapToError::FrameAllocationFailed => ElfError::OutOfMemory,
+            // `ElfFile::parse` rules out overlapping segments and segments
+            // overlapping the stack
+            MapToError::PageAlreadyMapped(_) | MapToError::ParentEntryHugePage => {
+                ElfError::OverlappingSegments
+            }
+        },
+    )
+}
+
+/// Writes the initial stack contents below `STACK_TOP` and returns the stack
+/// pointer.
+fn write_stack(
+    address_space: &mut AddressSpace,
+    file: &ElfFile,
+    args: &[&str],
+    env: &[&str],
+) -> Result<VirtAddr, ElfError> {
+    let stack_bottom = STACK_TOP - STACK_SIZE;
+    let mut top = STACK_TOP;
+    let mut push_string = |string: &str| -> Result<u64, ElfError> {
+        let size = string.len() as u64 + 1;
+        if top - stack_bottom < size {
+            return Err(ElfError::ArgumentsTooLong);
+        }
+        top -= size;
+        let address = VirtAddr::new(top);
+        address_space
+            .write(address, string.as_bytes())
+            .and_then(|()| address_space.write(address + string.len(), &[0]))
+            .expect("stack was just mapped");
+        Ok(top)
+    };
+    let arg_pointers = args
+        .iter()
+        .map(|arg| push_string(arg))
+        .collect::<Result<Vec<u64>, ElfError>>()?;
+    let env_pointers = env
+        .iter()
+        .map(|var| push_string(var))
+        .collect::<Result<Vec<u64>, ElfError>>()?;
+
+    let mut words = Vec::new();
+    words.push(args.len() as u64);
+    words.extend_from_slice(&arg_pointers);
+    words.push(0);
+    words.extend_from_slice(&env_pointers);
+    words.push(0);
+    if let Some(address) = file.program_headers_address() {
+        words.extend_from_slice(&[AT_PHDR, address.as_u64()]);
+    }
+    words.extend_from_slice(&[
+        AT_PHENT,
+        PROGRAM_HEADER_SIZE as u64,
+        AT_PHNUM,
+        file.program_header_count() as u64,
+        AT_PAGESZ,
+        4096,
+        AT_ENTRY,
+        file.entry().as_u64(),
+        AT_NULL,
+        0,
+    ]);
+    // the stack pointer must be 16-byte aligned at the entry point
+    if words.len() % 2 == 1 {
+        words.push(0);
+    }
+
+    let size = (words.len() * mem::size_of::<u64>()) as u64;
+    let top = top & !0xF;
+    if top - stack_bottom < size {
+        return Err(ElfError::ArgumentsTooLong);
+    }
+    let stack_pointer = VirtAddr::new(top - size);
+    let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    address_space
+        .write(stack_pointer, &bytes)
+        .expect("stack was just mapped");
+    Ok(stack_pointer)
+}
@@ -1,4 +1,10 @@
+use crate::percpu::this_cpu;
+use alloc::boxed::Box;
+use alloc::vec;
+use core::cell::UnsafeCell;
+use core::sync::atomic::Ordering;
 use lazy_static::lazy_static;
+use x86_64::instructions::interrupts::without_interrupts;
 use x86_64::instructions::segmentation::Segment;
 use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
 use x86_64::structures::tss::TaskStateSegment;
@@ -6,54 +12,120 @@ use x86_64::VirtAddr;
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 
+const DOUBLE_FAULT_STACK_SIZE: usize = 4096 * 5;
+
+/// A TSS that can still be changed after it is loaded, since the stack for
+/// interrupts from user mode changes with every thread switch.
+pub struct Tss(UnsafeCell<TaskStateSegment>);
+
+// only the processor that loaded the TSS writes it, with interrupts disabled
+unsafe impl Sync for Tss {}
+
 lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
-            let stack_end = stack_start + STACK_SIZE;
-            stack_end
-        };
-        tss
+    static ref TSS: Tss = {
+        static mut STACK: [u8; DOUBLE_FAULT_STACK_SIZE] = [0; DOUBLE_FAULT_STACK_SIZE];
+
+        let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
+        new_tss(stack_start + DOUBLE_FAULT_STACK_SIZE)
     };
 }
 
 lazy_static! {
-    static ref GDT: (GlobalDescriptorTable, Selectors) = {
-        let mut gdt = GlobalDescriptorTable::new();
-        let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let data_selector = gdt.add_entry(Descriptor::kernel_data_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
-        (
-            gdt,
-            Selectors {
-                code_selector,
-                data_selector,
-                tss_selector,
-            },
-        )
-    };
+    static ref GDT: (GlobalDescriptorTable, Selectors) = new_gdt(&TSS);
 }
 
-struct Selectors {
-    code_selector: SegmentSelector,
-    data_selector: SegmentSelector,
-    tss_selector: SegmentSelector,
+/// The segment selectors, which are the same in the GDT of every processor.
+///
+/// The user data segment directly precedes the user code segment, as
+/// `sysret` requires.
+#[derive(Debug, Clone, Copy)]
+pub struct Selectors {
+    pub kernel_code: SegmentSelector,
+    pub kernel_data: SegmentSelector,
+    pub user_data: SegmentSelector,
+    pub user_code: SegmentSelector,
+    pub tss: SegmentSelector,
 }
 
+fn new_tss(double_fault_stack_end: VirtAddr) -> Tss {
+    let mut tss = TaskStateSegment::new();
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = double_fault_stack_end;
+    Tss(UnsafeCell::new(tss))
+}
+
+fn new_gdt(tss: &'static Tss) -> (GlobalDescriptorTable, Selectors) {
+    let mut gdt = GlobalDescriptorTable::new();
+    let kernel_code = gdt.add_entry(Descriptor::kernel_code_segment());
+    let kernel_data = gdt.add_entry(Descriptor::kernel_data_segment());
+    let user_data = gdt.add_entry(Descriptor::user_data_segment());
+    let user_code = gdt.add_entry(Descriptor::user_code_segment());
+    // the descriptor only holds the address of the TSS
+    let tss = gdt.add_entry(Descriptor::tss_segment(unsafe { &*tss.0.get() }));
+    (
+        gdt,
+        Selectors {
+            kernel_code,
+            kernel_data,
+            user_data,
+            user_code,
+            tss,
+        },
+    )
+}
+
+/// Loads the GDT and TSS of the bootstrap processor.
+///
+/// `percpu::init` must have been called.
 pub fn init() {
+    load(&GDT, &TSS);
+}
+
+/// Loads a new GDT and TSS on an application processor.
+///
+/// Every processor needs a TSS of its own, because loading a TSS marks its
+/// descriptor as busy and the IST stacks must not be shared. The tables are
+/// allocated on the heap and never freed.
+pub fn init_ap() {
+    let stack = Box::leak(vec![0u8; DOUBLE_FAULT_STACK_SIZE].into_boxed_slice());
+    let stack_end = VirtAddr::from_ptr(stack.as_ptr()) + stack.len();
+    let tss: &'static Tss = Box::leak(Box::new(new_tss(stack_end)));
+    load(Box::leak(Box::new(new_gdt(tss))), tss);
+}
+
+/// Returns the segment selectors, with the requested privilege level of the
+/// user segments set to 3.
+pub fn selectors() -> Selectors {
+    GDT.1
+}
+
+/// Sets the stack that the calling processor switches to when an interrupt
+/// or exception arrives in user mode (entry 0 of the privilege stack table)
+/// and on system calls (see `syscall`).
+pub fn set_kernel_stack(stack_end: VirtAddr) {
+    let tss = this_cpu!(tss).get().expect("no TSS loaded on this CPU");
+    without_interrupts(|| unsafe {
+        (*tss.0.get()).privilege_stack_table[0] = stack_end;
+        this_cpu!(syscall_stack).store(stack_end.as_u64(), Ordering::Relaxed);
+    });
+}
+
+/// Returns the stack set by `set_kernel_stack` on the calling processor.
+pub fn kernel_stack() -> VirtAddr {
+    let tss = this_cpu!(tss).get().expect("no TSS loaded on this CPU");
+    without_interrupts(|| unsafe { (*tss.0.get()).privilege_stack_table[0] })
+}
+
+fn load(gdt: &'static (GlobalDescriptorTable, Selectors), tss: &'static Tss) {
     use x86_64::instructions::segmentation::{CS, DS, ES, SS};
     use x86_64::instructions::tables::load_tss;
 
-    GDT.0.load();
+    gdt.0.load();
     unsafe {
-        CS::set_reg(GDT.1.code_selector);
-        DS::set_reg(GDT.1.data_selector);
-        ES::set_reg(GDT.1.data_selector);
-        SS::set_reg(GDT.1.data_selector);
-        load_tss(GDT.1.tss_selector);
+        CS::set_reg(gdt.1.kernel_code);
+        DS::set_reg(gdt.1.kernel_data);
+        ES::set_reg(gdt.1.kernel_data);
+        SS::set_reg(gdt.1.kernel_data);
+        load_tss(gdt.1.tss);
     }
+    this_cpu!(tss).call_once(|| tss);
 }
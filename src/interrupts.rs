@@ -1,3 +1,6 @@
+pub mod irq;
+pub mod stats;
+
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
 
 use pic8259::ChainedPics;
@@ -5,9 +8,11 @@ use spin::Mutex;
 
 use crate::gdt;
 use crate::hlt_loop;
+use crate::percpu::{self, this_cpu};
+use crate::usermode;
 use crate::{print, println};
+use core::sync::atomic::Ordering;
 use lazy_static::lazy_static;
-use pc_keyboard::ScancodeSet;
 
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
@@ -18,7 +23,9 @@ pub static PICS: Mutex<ChainedPics> =
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
+        irq::install_stubs(&mut idt);
         idt.breakpoint.set_handler_fn(breakpoint_handler);
+        idt.non_maskable_interrupt.set_handler_fn(nmi_handler);
         unsafe {
             idt.double_fault
                 .set_handler_fn(double_fault_handler)
@@ -26,7 +33,16 @@ lazy_static! {
         }
         idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
         idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
+        idt[InterruptIndex::TlbShootdown.as_usize()].set_handler_fn(tlb_shootdown_handler);
+        idt[InterruptIndex::ApicSpurious.as_usize()]
+            .set_handler_fn(apic_spurious_interrupt_handler);
         idt.page_fault.set_handler_fn(page_fault_handler);
+        idt.divide_error.set_handler_fn(divide_error_handler);
+        idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+        idt.stack_segment_fault
+            .set_handler_fn(stack_segment_fault_handler);
+        idt.general_protection_fault
+            .set_handler_fn(general_protection_fault_handler);
         idt
     };
 }
@@ -35,10 +51,12 @@ lazy_static! {
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard,
+    TlbShootdown = 0xFE,
+    ApicSpurious = crate::apic::SPURIOUS_VECTOR,
 }
 
 impl InterruptIndex {
-    fn as_u8(self) -> u8 {
+    pub fn as_u8(self) -> u8 {
         self as u8
     }
 
@@ -52,48 +70,204 @@ pub fn init_idt() {
     IDT.load();
 }
 
+/// Switches to the GS base of the kernel if a handler interrupted user mode,
+/// and back to that of user mode when dropped.
+///
+/// Must be created before anything else in a handler, and dropped right
+/// before it returns. A handler that does not return to user mode, such as
+/// one that calls `usermode::kill_current`, never drops it.
+pub(crate) struct SwapGsGuard {
+    from_user: bool,
+}
+
+impl SwapGsGuard {
+    pub(crate) fn enter(stack_frame: &InterruptStackFrame) -> Self {
+        let from_user = usermode::is_user_frame(stack_frame);
+        if from_user {
+            unsafe { percpu::swap_gs() };
+        }
+        SwapGsGuard { from_user }
+    }
+}
+
+impl Drop for SwapGsGuard {
+    fn drop(&mut self) {
+        if self.from_user {
+            unsafe { percpu::swap_gs() };
+        }
+    }
+}
+
+/// Counts an interrupt handler in the nesting depth of the calling CPU until
+/// it is dropped.
+pub(crate) struct NestingGuard(());
+
+impl NestingGuard {
+    /// Must be called right after `SwapGsGuard::enter`.
+    pub(crate) fn enter() -> Self {
+        // exceptions may happen before the per-CPU areas exist
+        if percpu::is_initialized() {
+            this_cpu!(interrupt_depth).fetch_add(1, Ordering::Relaxed);
+        }
+        NestingGuard(())
+    }
+}
+
+impl Drop for NestingGuard {
+    fn drop(&mut self) {
+        if percpu::is_initialized() {
+            this_cpu!(interrupt_depth).fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Returns the number of interrupt and exception handlers that are running
+/// on the calling CPU.
+pub fn nesting_depth() -> usize {
+    if percpu::is_initialized() {
+        this_cpu!(interrupt_depth).load(Ordering::Relaxed)
+    } else {
+        0
+    }
+}
+
+/// Returns `true` if the caller runs in an interrupt or exception handler.
+pub fn in_interrupt() -> bool {
+    nesting_depth() > 0
+}
+
+/// The line on the master PIC the slave PIC is cascaded to.
+const CASCADE_IRQ: u8 = 2;
+
+/// Unmasks the given IRQ line (0-15) of the chained PICs. Lines of the slave
+/// PIC also unmask the cascade line on the master.
+pub fn unmask_irq(irq: u8) {
+    update_irq_masks(|mut masks| {
+        masks &= !(1 << irq);
+        if irq >= 8 {
+            masks &= !(1 << CASCADE_IRQ);
+        }
+        masks
+    });
+}
+
+/// Masks the given IRQ line (0-15) of the chained PICs.
+pub fn mask_irq(irq: u8) {
+    update_irq_masks(|masks| masks | 1 << irq);
+}
+
+/// Reads both interrupt mask registers as one 16-bit mask (bit n = IRQ n),
+/// applies `f` and writes the result back.
+fn update_irq_masks(f: impl FnOnce(u16) -> u16) {
+    use x86_64::instructions::interrupts::without_interrupts;
+    use x86_64::instructions::port::Port;
+
+    without_interrupts(|| {
+        // hold the lock so that nobody else talks to the PICs meanwhile
+        let _pics = PICS.lock();
+        let mut master_data: Port<u8> = Port::new(0x21);
+        let mut slave_data: Port<u8> = Port::new(0xA1);
+        unsafe {
+            let masks = u16::from(master_data.read()) | u16::from(slave_data.read()) << 8;
+            let masks = f(masks);
+            master_data.write(masks as u8);
+            slave_data.write((masks >> 8) as u8);
+        }
+    });
+}
+
+/// Reads the in-service registers of both PICs as one 16-bit mask (bit n = IRQ n).
+fn read_in_service_registers() -> u16 {
+    use x86_64::instructions::port::Port;
+
+    const OCW3_READ_ISR: u8 = 0x0B;
+
+    let _pics = PICS.lock();
+    let mut master_command: Port<u8> = Port::new(0x20);
+    let mut slave_command: Port<u8> = Port::new(0xA0);
+    unsafe {
+        master_command.write(OCW3_READ_ISR);
+        slave_command.write(OCW3_READ_ISR);
+        u16::from(master_command.read()) | u16::from(slave_command.read()) << 8
+    }
+}
+
+/// Checks whether an interrupt on IRQ 7 or 15 is spurious, i.e. the PIC
+/// raised it although the line was no longer asserted.
+///
+/// A spurious interrupt must not be acknowledged at the PIC that raised it,
+/// but a spurious IRQ 15 still needs an EOI at the master, which saw a real
+/// interrupt on the cascade line.
+pub(crate) fn handle_spurious_irq(irq: u8) -> bool {
+    use x86_64::instructions::port::Port;
+
+    const EOI: u8 = 0x20;
+
+    if irq != 7 && irq != 15 {
+        return false;
+    }
+    if read_in_service_registers() & (1 << irq) != 0 {
+        return false;
+    }
+
+    stats::record_spurious(irq);
+    if irq == 15 {
+        let _pics = PICS.lock();
+        let mut master_command: Port<u8> = Port::new(0x20);
+        unsafe { master_command.write(EOI) };
+    }
+    true
+}
+
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
+    let _gs = SwapGsGuard::enter(&stack_frame);
+    let _nesting = NestingGuard::enter();
+    stats::record(3);
     println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
 }
 
+extern "x86-interrupt" fn nmi_handler(stack_frame: InterruptStackFrame) {
+    let _gs = SwapGsGuard::enter(&stack_frame);
+    let _nesting = NestingGuard::enter();
+    stats::record(2);
+}
+
 extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame,
     _error_code: u64,
 ) -> ! {
+    stats::record(8);
     panic!("EXCEPTION: DOUBLE_FAULT\n{:#?}", stack_frame);
 }
 
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    print!(".");
+extern "x86-interrupt" fn timer_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _gs = SwapGsGuard::enter(&stack_frame);
+    {
+        let _nesting = NestingGuard::enter();
+        stats::record(InterruptIndex::Timer.as_u8());
+        crate::time::tick();
+        crate::timer::tick();
 
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
+        unsafe {
+            PICS.lock()
+                .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
+        }
     }
-}
 
-extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
-    use spin::Mutex;
-    use x86_64::instructions::port::Port;
+    // may switch to another thread, so the EOI has to be sent before and the
+    // handler must no longer count as running
+    crate::thread::tick();
+}
 
-    lazy_static! {
-        static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> = Mutex::new(
-            Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::Ignore)
-        );
-    }
+extern "x86-interrupt" fn keyboard_interrupt_handler(stack_frame: InterruptStackFrame) {
+    use crate::ps2::{self, Ps2Port};
 
-    let mut keyboard = KEYBOARD.lock();
-    let mut port = Port::new(0x60);
+    let _gs = SwapGsGuard::enter(&stack_frame);
+    let _nesting = NestingGuard::enter();
+    stats::record(InterruptIndex::Keyboard.as_u8());
 
-    let scancode: u8 = unsafe { port.read() };
-    if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-        if let Some(key) = keyboard.process_keyevent(key_event) {
-            match key {
-                DecodedKey::Unicode(c) => print!("{}", c),
-                DecodedKey::RawKey(raw_key) => print!("{:?}", raw_key),
-            }
-        }
+    if let Some(scancode) = ps2::read_from_interrupt(Ps2Port::Keyboard) {
+        crate::keyboard::add_scancode(scancode);
     }
 
     unsafe {
@@ -102,12 +276,50 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
     }
 }
 
+extern "x86-interrupt" fn tlb_shootdown_handler(stack_frame: InterruptStackFrame) {
+    let _gs = SwapGsGuard::enter(&stack_frame);
+    let _nesting = NestingGuard::enter();
+    stats::record(InterruptIndex::TlbShootdown.as_u8());
+    crate::memory::tlb::handle_shootdown();
+    crate::apic::end_of_interrupt();
+}
+
+extern "x86-interrupt" fn apic_spurious_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _gs = SwapGsGuard::enter(&stack_frame);
+    let _nesting = NestingGuard::enter();
+    // spurious interrupts of the local APIC must not be acknowledged
+    stats::record(InterruptIndex::ApicSpurious.as_u8());
+}
+
 extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame,
+    mut stack_frame: InterruptStackFrame,
     error_code: PageFaultErrorCode,
 ) {
     use x86_64::registers::control::Cr2;
 
+    let _gs = SwapGsGuard::enter(&stack_frame);
+    let nesting = NestingGuard::enter();
+    stats::record(14);
+    // a bad user address passed to `usermode::copy_from_user` and the like
+    if !usermode::is_user_frame(&stack_frame) && usermode::is_user_address(Cr2::read()) {
+        if let Some(fixup) = usermode::fixup_address(stack_frame.instruction_pointer) {
+            unsafe {
+                stack_frame
+                    .as_mut()
+                    .update(|frame| frame.instruction_pointer = fixup)
+            };
+            return;
+        }
+    }
+    if usermode::is_user_frame(&stack_frame) {
+        drop(nesting);
+        usermode::kill_current(format_args!(
+            "page fault at {:?} ({:?}), rip {:?}",
+            Cr2::read(),
+            error_code,
+            stack_frame.instruction_pointer
+        ));
+    }
     println!("EXCEPTION: PAGE FAULT");
     println!("Accessed Address: {:?}", Cr2::read());
     println!("Error Code: {:?}", error_code);
@@ -115,6 +327,52 @@ extern "x86-interrupt" fn page_fault_handler(
     hlt_loop();
 }
 
+extern "x86-interrupt" fn divide_error_handler(stack_frame: InterruptStackFrame) {
+    fault(&stack_frame, 0, "DIVIDE ERROR", None);
+}
+
+extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame) {
+    fault(&stack_frame, 6, "INVALID OPCODE", None);
+}
+
+extern "x86-interrupt" fn stack_segment_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    fault(&stack_frame, 12, "STACK SEGMENT FAULT", Some(error_code));
+}
+
+extern "x86-interrupt" fn general_protection_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    fault(
+        &stack_frame,
+        13,
+        "GENERAL PROTECTION FAULT",
+        Some(error_code),
+    );
+}
+
+/// Handles an exception that cannot be recovered from: a fault in user mode
+/// kills the user program, a fault in the kernel is a bug.
+fn fault(stack_frame: &InterruptStackFrame, vector: u8, name: &str, error_code: Option<u64>) {
+    let _gs = SwapGsGuard::enter(stack_frame);
+    let nesting = NestingGuard::enter();
+    stats::record(vector);
+    if usermode::is_user_frame(stack_frame) {
+        drop(nesting);
+        usermode::kill_current(format_args!(
+            "{} (error code {:?}), rip {:?}",
+            name, error_code, stack_frame.instruction_pointer
+        ));
+    }
+    panic!(
+        "EXCEPTION: {} (error code {:?})\n{:#?}",
+        name, error_code, stack_frame
+    );
+}
+
 #[test_case]
 fn test_breakpoint_exception() {
     x86_64::instructions::interrupts::int3();
@@ -0,0 +1,249 @@
+use super::{
+    handle_spurious_irq, mask_irq, stats, unmask_irq, InterruptIndex, NestingGuard, SwapGsGuard,
+    PICS, PIC_1_OFFSET,
+};
+use crate::{apic, serial_println, workqueue};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::RwLock;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+/// The first vector that is not reserved for CPU exceptions.
+const FIRST_VECTOR: usize = 32;
+const VECTOR_COUNT: usize = 256 - FIRST_VECTOR;
+
+/// Number of IRQ lines of the chained PICs.
+pub const PIC_IRQ_COUNT: u8 = 16;
+
+/// Vectors from here on are free for `request_vector`, the ones below are
+/// the remapped lines of the PICs.
+const FIRST_FREE_VECTOR: u8 = PIC_1_OFFSET + PIC_IRQ_COUNT;
+
+/// Tells the dispatcher whether a handler on a shared line recognized the
+/// interrupt as coming from its device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqReturn {
+    Handled,
+    NotHandled,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    /// The IRQ line does not exist on the PICs.
+    InvalidIrq,
+    /// The line or vector is served by a built-in handler of the IDT.
+    Reserved,
+    /// The line is already claimed and not every party agreed to share it.
+    Busy,
+    /// There are no unclaimed vectors left.
+    NoFreeVector,
+}
+
+type Handler = Box<dyn Fn(&InterruptStackFrame) -> IrqReturn + Send + Sync>;
+
+struct Line {
+    shared: bool,
+    handlers: Vec<(u64, Handler)>,
+}
+
+/// A registered interrupt handler, which can be removed again through `free_irq`.
+#[derive(Debug, PartialEq, Eq)]
+#[must_use = "the handle is needed to unregister the handler"]
+pub struct IrqHandle {
+    vector: u8,
+    id: u64,
+}
+
+impl IrqHandle {
+    /// Returns the interrupt vector the handler is registered for.
+    pub fn vector(&self) -> u8 {
+        self.vector
+    }
+}
+
+static LINES: RwLock<[Option<Line>; VECTOR_COUNT]> = {
+    const NONE: Option<Line> = None;
+    RwLock::new([NONE; VECTOR_COUNT])
+};
+
+static NEXT_HANDLER_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Registers `handler` as the only handler of the given PIC IRQ line (0-15)
+/// and unmasks the line.
+pub fn request_irq<F>(irq: u8, handler: F) -> Result<IrqHandle, IrqError>
+where
+    F: Fn(&InterruptStackFrame) -> IrqReturn + Send + Sync + 'static,
+{
+    claim_irq(irq, false, Box::new(handler))
+}
+
+/// Adds `handler` to the chain of handlers of the given PIC IRQ line (0-15)
+/// and unmasks the line. All handlers of the line must be registered through
+/// this function.
+///
+/// On an interrupt, every handler of the chain is called in registration order.
+pub fn request_shared_irq<F>(irq: u8, handler: F) -> Result<IrqHandle, IrqError>
+where
+    F: Fn(&InterruptStackFrame) -> IrqReturn + Send + Sync + 'static,
+{
+    claim_irq(irq, true, Box::new(handler))
+}
+
+/// Registers `handler` on an unclaimed vector above the PIC range and returns
+/// its handle, through which the allocated vector can be queried.
+pub fn request_vector<F>(handler: F) -> Result<IrqHandle, IrqError>
+where
+    F: Fn(&InterruptStackFrame) -> IrqReturn + Send + Sync + 'static,
+{
+    let handler: Handler = Box::new(handler);
+    without_interrupts(|| {
+        let mut lines = LINES.write();
+        let vector = (FIRST_FREE_VECTOR..=u8::MAX)
+            .find(|&vector| !is_reserved(vector) && lines[index(vector)].is_none())
+            .ok_or(IrqError::NoFreeVector)?;
+        Ok(add_handler(
+            &mut lines[index(vector)],
+            vector,
+            false,
+            handler,
+        ))
+    })
+}
+
+/// Removes a handler registered through one of the `request_*` functions.
+///
+/// A PIC line is masked again once its last handler is gone.
+pub fn free_irq(handle: IrqHandle) {
+    // drop the handler outside of the interrupt-free section
+    let removed = without_interrupts(|| {
+        let mut lines = LINES.write();
+        let slot = &mut lines[index(handle.vector)];
+        let line = slot.as_mut()?;
+        let position = line.handlers.iter().position(|(id, _)| *id == handle.id)?;
+        let (_, handler) = line.handlers.remove(position);
+        let line_unused = line.handlers.is_empty();
+        if line_unused {
+            *slot = None;
+        }
+        Some((handler, line_unused))
+    });
+
+    if let Some((_, true)) = removed {
+        if handle.vector < FIRST_FREE_VECTOR {
+            mask_irq(handle.vector - PIC_1_OFFSET);
+        }
+    }
+}
+
+fn claim_irq(irq: u8, shared: bool, handler: Handler) -> Result<IrqHandle, IrqError> {
+    if irq >= PIC_IRQ_COUNT {
+        return Err(IrqError::InvalidIrq);
+    }
+    let vector = PIC_1_OFFSET + irq;
+    if is_reserved(vector) {
+        return Err(IrqError::Reserved);
+    }
+
+    let handle = without_interrupts(|| {
+        let mut lines = LINES.write();
+        let slot = &mut lines[index(vector)];
+        if let Some(line) = slot {
+            if !(line.shared && shared) {
+                return Err(IrqError::Busy);
+            }
+        }
+        Ok(add_handler(slot, vector, shared, handler))
+    })?;
+    unmask_irq(irq);
+    Ok(handle)
+}
+
+fn add_handler(slot: &mut Option<Line>, vector: u8, shared: bool, handler: Handler) -> IrqHandle {
+    let id = NEXT_HANDLER_ID.fetch_add(1, Ordering::Relaxed);
+    slot.get_or_insert_with(|| Line {
+        shared,
+        handlers: Vec::new(),
+    })
+    .handlers
+    .push((id, handler));
+    IrqHandle { vector, id }
+}
+
+/// Vectors with a dedicated handler in the IDT never reach the dispatcher.
+fn is_reserved(vector: u8) -> bool {
+    vector == InterruptIndex::Timer.as_u8()
+        || vector == InterruptIndex::Keyboard.as_u8()
+        || vector == InterruptIndex::TlbShootdown.as_u8()
+        || vector == InterruptIndex::ApicSpurious.as_u8()
+}
+
+fn index(vector: u8) -> usize {
+    usize::from(vector) - FIRST_VECTOR
+}
+
+/// Calls the handlers registered for `vector` and acknowledges the interrupt
+/// at the PIC or, for the vectors above, at the local APIC that received the
+/// IPI.
+/// Spurious interrupts of the PICs are only counted.
+///
+/// Handlers run with interrupts disabled and must not register or free
+/// handlers themselves, since the handler table is locked meanwhile.
+fn dispatch(vector: u8, stack_frame: &InterruptStackFrame) {
+    let _gs = SwapGsGuard::enter(stack_frame);
+    let _nesting = NestingGuard::enter();
+    stats::record(vector);
+
+    let is_pic_vector = (PIC_1_OFFSET..FIRST_FREE_VECTOR).contains(&vector);
+    if is_pic_vector && handle_spurious_irq(vector - PIC_1_OFFSET) {
+        return;
+    }
+
+    let handled = {
+        let lines = LINES.read();
+        match &lines[index(vector)] {
+            Some(line) => line.handlers.iter().fold(false, |handled, (_, handler)| {
+                handler(stack_frame) == IrqReturn::Handled || handled
+            }),
+            None => false,
+        }
+    };
+
+    if !handled {
+        workqueue::schedule_or_run(warn_unhandled, u64::from(vector));
+    }
+
+    if is_pic_vector {
+        unsafe {
+            PICS.lock().notify_end_of_interrupt(vector);
+        }
+    } else if apic::is_initialized() {
+        // after an `int` instruction, no interrupt is in service and the
+        // EOI is ignored
+        apic::end_of_interrupt();
+    }
+}
+
+fn warn_unhandled(vector: u64) {
+    serial_println!("WARNING: unhandled interrupt on vector {}", vector);
+}
+
+extern "x86-interrupt" fn vector_stub<const VECTOR: u8>(stack_frame: InterruptStackFrame) {
+    dispatch(VECTOR, &stack_frame);
+}
+
+/// Points the IDT entries of the vectors `16 * hi + lo` to their stubs.
+macro_rules! set_vector_stubs {
+    ($idt:ident; $($hi:literal)*) => {
+        $( set_vector_stubs!(@row $idt; $hi; 0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15); )*
+    };
+    (@row $idt:ident; $hi:literal; $($lo:literal)*) => {
+        $( $idt[$hi * 16 + $lo].set_handler_fn(vector_stub::<{ $hi * 16 + $lo }>); )*
+    };
+}
+
+/// Installs a generic dispatching stub for every non-exception vector.
+pub(super) fn install_stubs(idt: &mut InterruptDescriptorTable) {
+    set_vector_stubs!(idt; 2 3 4 5 6 7 8 9 10 11 12 13 14 15);
+}
@@ -0,0 +1,115 @@
+use super::{InterruptIndex, PIC_1_OFFSET, PIC_2_OFFSET};
+use crate::serial;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+static COUNTS: [AtomicU64; 256] = {
+    const ZERO: AtomicU64 = AtomicU64::new(0);
+    [ZERO; 256]
+};
+
+/// Spurious interrupts of the master (IRQ 7) and slave (IRQ 15) PIC.
+static SPURIOUS: [AtomicU64; 2] = [AtomicU64::new(0), AtomicU64::new(0)];
+
+const EXCEPTION_NAMES: [&str; 32] = [
+    "divide error",
+    "debug",
+    "non-maskable interrupt",
+    "breakpoint",
+    "overflow",
+    "bound range exceeded",
+    "invalid opcode",
+    "device not available",
+    "double fault",
+    "coprocessor segment overrun",
+    "invalid TSS",
+    "segment not present",
+    "stack-segment fault",
+    "general protection fault",
+    "page fault",
+    "reserved",
+    "x87 floating-point",
+    "alignment check",
+    "machine check",
+    "SIMD floating-point",
+    "virtualization",
+    "reserved",
+    "reserved",
+    "reserved",
+    "reserved",
+    "reserved",
+    "reserved",
+    "reserved",
+    "reserved",
+    "reserved",
+    "security exception",
+    "reserved",
+];
+
+/// Counts one interrupt on the given vector. Called by every handler.
+#[inline]
+pub fn record(vector: u8) {
+    COUNTS[usize::from(vector)].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counts a spurious interrupt of the PIC that owns the given IRQ line.
+pub(super) fn record_spurious(irq: u8) {
+    SPURIOUS[usize::from(irq >= 8)].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns the number of interrupts received on the given vector, including
+/// spurious ones.
+pub fn count(vector: u8) -> u64 {
+    COUNTS[usize::from(vector)].load(Ordering::Relaxed)
+}
+
+/// Returns the number of spurious interrupts of the master and slave PIC.
+pub fn spurious_counts() -> (u64, u64) {
+    (
+        SPURIOUS[0].load(Ordering::Relaxed),
+        SPURIOUS[1].load(Ordering::Relaxed),
+    )
+}
+
+fn describe(vector: u8, f: &mut impl Write) -> fmt::Result {
+    let pic_vectors = PIC_1_OFFSET..PIC_2_OFFSET + 8;
+    if vector < 32 {
+        f.write_str(EXCEPTION_NAMES[usize::from(vector)])
+    } else if vector == InterruptIndex::Timer.as_u8() {
+        f.write_str("IRQ 0  timer")
+    } else if vector == InterruptIndex::Keyboard.as_u8() {
+        f.write_str("IRQ 1  keyboard")
+    } else if vector == InterruptIndex::TlbShootdown.as_u8() {
+        f.write_str("TLB shootdown IPI")
+    } else if vector == InterruptIndex::ApicSpurious.as_u8() {
+        f.write_str("APIC spurious")
+    } else if pic_vectors.contains(&vector) {
+        let irq = vector - PIC_1_OFFSET;
+        let pic = if irq < 8 { "master" } else { "slave" };
+        write!(f, "IRQ {:<2} {} PIC", irq, pic)
+    } else {
+        f.write_str("dynamic")
+    }
+}
+
+/// Writes a `/proc/interrupts`-like table of all vectors that were hit.
+pub fn write_table(f: &mut impl Write) -> fmt::Result {
+    writeln!(f, "{:>6} {:>12}  description", "vector", "count")?;
+    for vector in 0..=u8::MAX {
+        let count = count(vector);
+        if count == 0 {
+            continue;
+        }
+        write!(f, "{:>6} {:>12}  ", vector, count)?;
+        describe(vector, f)?;
+        writeln!(f)?;
+    }
+    let (master, slave) = spurious_counts();
+    writeln!(f, "{:>6} {:>12}  spurious IRQ 7", "SPU", master)?;
+    writeln!(f, "{:>6} {:>12}  spurious IRQ 15", "SPU", slave)
+}
+
+/// Prints the interrupt table to the serial port.
+pub fn dump() {
+    write_table(&mut serial::Writer).unwrap();
+}
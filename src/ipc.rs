@@ -0,0 +1,16 @@
+//! Communication between processes and kernel threads.
+//!
+//! `pipe` creates an anonymous pipe, a byte stream with a bounded buffer, and
+//! `channel` a pair of connected endpoints that exchange messages of bytes
+//! and handles. Both block like the primitives in `sync`, so they need
+//! `thread::init`, and report when the other side is gone. Processes reach
+//! them through handles (see `process::Object`) and system calls.
+
+mod channel;
+mod pipe;
+
+pub use channel::{
+    channel, Channel, ChannelError, Message, SendError, CHANNEL_CAPACITY, MAX_MESSAGE_HANDLES,
+    MAX_MESSAGE_SIZE,
+};
+pub use pipe::{pipe, BrokenPipe, PipeReader, PipeWriter, PIPE_CAPACITY};
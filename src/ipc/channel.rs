@@ -0,0 +1,198 @@
+use crate::process::Object;
+use crate::sync::{Condvar, Mutex};
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::{fmt, mem};
+
+/// The maximum number of data bytes in a message.
+pub const MAX_MESSAGE_SIZE: usize = 4096;
+
+/// The maximum number of objects a message carries.
+pub const MAX_MESSAGE_HANDLES: usize = 8;
+
+/// How many messages an endpoint queues before senders block.
+pub const CHANNEL_CAPACITY: usize = 16;
+
+/// Creates a channel and returns its two endpoints.
+///
+/// Each endpoint sends messages to the other one and receives the messages
+/// sent by it, in order. Endpoints can be cloned; an endpoint is closed once
+/// all its clones are dropped, which drops the messages queued for it.
+pub fn channel() -> (Channel, Channel) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            queues: [VecDeque::new(), VecDeque::new()],
+            endpoints: [1, 1],
+        }),
+        changed: Condvar::new(),
+    });
+    (
+        Channel {
+            shared: shared.clone(),
+            side: 0,
+        },
+        Channel { shared, side: 1 },
+    )
+}
+
+/// A message: bytes and the objects that handles referred to when it was
+/// sent.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Message {
+    pub data: Vec<u8>,
+    pub objects: Vec<Object>,
+}
+
+impl Message {
+    pub fn new(data: Vec<u8>, objects: Vec<Object>) -> Self {
+        Message { data, objects }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelError {
+    /// The other endpoint is closed and, for receiving, no message is left.
+    PeerClosed,
+    /// The message exceeds `MAX_MESSAGE_SIZE` or `MAX_MESSAGE_HANDLES`, or for
+    /// receiving, the limits of the caller.
+    TooLarge,
+}
+
+/// A message that could not be sent, which is handed back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SendError {
+    pub error: ChannelError,
+    pub message: Message,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    /// Notified when a message is sent or received and when an endpoint
+    /// closes.
+    changed: Condvar,
+}
+
+struct State {
+    /// The messages queued for each side.
+    queues: [VecDeque<Message>; 2],
+    /// The number of open clones of each side.
+    endpoints: [usize; 2],
+}
+
+/// An endpoint of a channel.
+pub struct Channel {
+    shared: Arc<Shared>,
+    /// 0 or 1, the index of this endpoint in `State`.
+    side: usize,
+}
+
+impl Channel {
+    fn peer(&self) -> usize {
+        1 - self.side
+    }
+
+    /// Sends `message` to the other endpoint, sleeping while its queue is
+    /// full.
+    pub fn send(&self, message: Message) -> Result<(), SendError> {
+        if message.data.len() > MAX_MESSAGE_SIZE || message.objects.len() > MAX_MESSAGE_HANDLES {
+            return Err(SendError {
+                error: ChannelError::TooLarge,
+                message,
+            });
+        }
+        let peer = self.peer();
+        let state = self.shared.state.lock();
+        let mut state = self.shared.changed.wait_while(state, |state| {
+            state.queues[peer].len() == CHANNEL_CAPACITY && state.endpoints[peer] > 0
+        });
+        if state.endpoints[peer] == 0 {
+            return Err(SendError {
+                error: ChannelError::PeerClosed,
+                message,
+            });
+        }
+        state.queues[peer].push_back(message);
+        drop(state);
+        self.shared.changed.notify_all();
+        Ok(())
+    }
+
+    /// Receives the oldest message, sleeping while there is none.
+    pub fn recv(&self) -> Result<Message, ChannelError> {
+        self.recv_within(MAX_MESSAGE_SIZE, MAX_MESSAGE_HANDLES)
+    }
+
+    /// Like `recv`, but fails with `TooLarge` if the oldest message has more
+    /// than `max_size` bytes or `max_objects` objects, and leaves it queued.
+    pub fn recv_within(
+        &self,
+        max_size: usize,
+        max_objects: usize,
+    ) -> Result<Message, ChannelError> {
+        let side = self.side;
+        let peer = self.peer();
+        let state = self.shared.state.lock();
+        let mut state = self.shared.changed.wait_while(state, |state| {
+            state.queues[side].is_empty() && state.endpoints[peer] > 0
+        });
+        let message = state.queues[side].front().ok_or(ChannelError::PeerClosed)?;
+        if message.data.len() > max_size || message.objects.len() > max_objects {
+            return Err(ChannelError::TooLarge);
+        }
+        let message = state.queues[side].pop_front().unwrap();
+        drop(state);
+        self.shared.changed.notify_all();
+        Ok(message)
+    }
+
+    /// Returns `true` if `other` is an endpoint of the same channel.
+    pub fn same_channel(&self, other: &Channel) -> bool {
+        Arc::ptr_eq(&self.shared, &other.shared)
+    }
+}
+
+impl Clone for Channel {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().endpoints[self.side] += 1;
+        Channel {
+            shared: self.shared.clone(),
+            side: self.side,
+        }
+    }
+}
+
+impl Drop for Channel {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock();
+        state.endpoints[self.side] -= 1;
+        let dropped = if state.endpoints[self.side] == 0 {
+            mem::take(&mut state.queues[self.side])
+        } else {
+            VecDeque::new()
+        };
+        drop(state);
+        self.shared.changed.notify_all();
+        // the messages may hold endpoints of this very channel, whose drop
+        // locks the state again
+        drop(dropped);
+    }
+}
+
+/// Endpoints are equal if they are the same side of the same channel.
+impl PartialEq for Channel {
+    fn eq(&self, other: &Self) -> bool {
+        self.same_channel(other) && self.side == other.side
+    }
+}
+
+impl Eq for Channel {}
+
+impl fmt::Debug for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Channel")
+            .field("shared", &Arc::as_ptr(&self.shared))
+            .field("side", &self.side)
+            .finish()
+    }
+}
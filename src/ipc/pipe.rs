@@ -0,0 +1,181 @@
+use crate::sync::{Condvar, Mutex};
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::fmt;
+
+/// How many bytes a pipe buffers before writers block.
+pub const PIPE_CAPACITY: usize = 4096;
+
+/// Creates an anonymous pipe and returns its read and its write end, or
+/// `None` if there is no memory left for the buffer.
+///
+/// Both ends can be cloned. Reads return 0 (end of file) once the buffer is
+/// empty and all write ends are closed; writes fail with `BrokenPipe` once
+/// all read ends are closed.
+pub fn pipe() -> Option<(PipeReader, PipeWriter)> {
+    let mut buffer = VecDeque::new();
+    // user programs decide how many pipes there are, so running out of
+    // memory must not abort the kernel
+    buffer.try_reserve_exact(PIPE_CAPACITY).ok()?;
+    let pipe = Arc::new(Pipe {
+        state: Mutex::new(PipeState {
+            buffer,
+            readers: 1,
+            writers: 1,
+        }),
+        changed: Condvar::new(),
+    });
+    Some((PipeReader { pipe: pipe.clone() }, PipeWriter { pipe }))
+}
+
+/// All read ends of the pipe are closed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BrokenPipe;
+
+struct Pipe {
+    state: Mutex<PipeState>,
+    /// Notified when bytes are written or read and when an end closes.
+    changed: Condvar,
+}
+
+struct PipeState {
+    buffer: VecDeque<u8>,
+    readers: usize,
+    writers: usize,
+}
+
+/// The read end of a pipe.
+pub struct PipeReader {
+    pipe: Arc<Pipe>,
+}
+
+impl PipeReader {
+    /// Reads up to `buf.len()` bytes into `buf` and returns how many were
+    /// read, sleeping while the pipe is empty. Returns 0 at the end of file.
+    pub fn read(&self, buf: &mut [u8]) -> usize {
+        if buf.is_empty() {
+            return 0;
+        }
+        let state = self.pipe.state.lock();
+        let mut state = self
+            .pipe
+            .changed
+            .wait_while(state, |state| state.buffer.is_empty() && state.writers > 0);
+        let len = buf.len().min(state.buffer.len());
+        for (dst, src) in buf.iter_mut().zip(state.buffer.drain(..len)) {
+            *dst = src;
+        }
+        drop(state);
+        if len > 0 {
+            self.pipe.changed.notify_all();
+        }
+        len
+    }
+}
+
+impl Clone for PipeReader {
+    fn clone(&self) -> Self {
+        self.pipe.state.lock().readers += 1;
+        PipeReader {
+            pipe: self.pipe.clone(),
+        }
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        let mut state = self.pipe.state.lock();
+        state.readers -= 1;
+        if state.readers == 0 {
+            // nobody reads the bytes anymore
+            state.buffer.clear();
+        }
+        drop(state);
+        self.pipe.changed.notify_all();
+    }
+}
+
+/// Read ends are equal if they belong to the same pipe.
+impl PartialEq for PipeReader {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.pipe, &other.pipe)
+    }
+}
+
+impl Eq for PipeReader {}
+
+impl fmt::Debug for PipeReader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PipeReader")
+            .field("pipe", &Arc::as_ptr(&self.pipe))
+            .finish()
+    }
+}
+
+/// The write end of a pipe.
+pub struct PipeWriter {
+    pipe: Arc<Pipe>,
+}
+
+impl PipeWriter {
+    /// Writes all of `buf`, sleeping while the pipe is full, and returns
+    /// `buf.len()`.
+    ///
+    /// If the last read end closes before all bytes are written, returns how
+    /// many were, or `BrokenPipe` if none were.
+    pub fn write(&self, buf: &[u8]) -> Result<usize, BrokenPipe> {
+        let mut written = 0;
+        while written < buf.len() {
+            let state = self.pipe.state.lock();
+            let mut state = self.pipe.changed.wait_while(state, |state| {
+                state.buffer.len() == PIPE_CAPACITY && state.readers > 0
+            });
+            if state.readers == 0 {
+                break;
+            }
+            let len = (buf.len() - written).min(PIPE_CAPACITY - state.buffer.len());
+            state.buffer.extend(&buf[written..written + len]);
+            written += len;
+            drop(state);
+            self.pipe.changed.notify_all();
+        }
+        if written == 0 && !buf.is_empty() {
+            Err(BrokenPipe)
+        } else {
+            Ok(written)
+        }
+    }
+}
+
+impl Clone for PipeWriter {
+    fn clone(&self) -> Self {
+        self.pipe.state.lock().writers += 1;
+        PipeWriter {
+            pipe: self.pipe.clone(),
+        }
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.pipe.state.lock().writers -= 1;
+        self.pipe.changed.notify_all();
+    }
+}
+
+/// Write ends are equal if they belong to the same pipe.
+impl PartialEq for PipeWriter {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.pipe, &other.pipe)
+    }
+}
+
+impl Eq for PipeWriter {}
+
+impl fmt::Debug for PipeWriter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PipeWriter")
+            .field("pipe", &Arc::as_ptr(&self.pipe))
+            .finish()
+    }
+}
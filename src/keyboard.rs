@@ -0,0 +1,476 @@
+mod device;
+mod event;
+mod layout;
+
+pub use device::{set_leds, set_typematic, Leds, ScancodeSet, TypematicDelay};
+pub use event::{KeyAction, KeyboardEvent, Modifiers};
+pub use layout::Layout;
+
+use crate::ps2::Ps2Error;
+use crate::{print, serial_println, workqueue};
+use conquer_once::spin::OnceCell;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use crossbeam_queue::ArrayQueue;
+use futures_util::stream::{Stream, StreamExt};
+use futures_util::task::AtomicWaker;
+use lazy_static::lazy_static;
+use pc_keyboard::{
+    layouts, DecodedKey, HandleControl, KeyCode, KeyEvent, Keyboard, ScancodeSet1, ScancodeSet2,
+};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+/// The number of scancodes buffered until the consumers catch up.
+const SCANCODE_QUEUE_SIZE: usize = 128;
+
+static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Assembles (possibly multi-byte) scancodes into key events. The layout
+/// of the wrapped `Keyboard` is irrelevant, only `add_byte` is used.
+enum ScancodeDecoder {
+    Set1(Keyboard<layouts::Us104Key, ScancodeSet1>),
+    Set2(Keyboard<layouts::Us104Key, ScancodeSet2>),
+}
+
+impl ScancodeDecoder {
+    fn new(set: ScancodeSet) -> Self {
+        match set {
+            ScancodeSet::Set1 => ScancodeDecoder::Set1(Keyboard::new(
+                layouts::Us104Key,
+                ScancodeSet1,
+                HandleControl::Ignore,
+            )),
+            ScancodeSet::Set2 => ScancodeDecoder::Set2(Keyboard::new(
+                layouts::Us104Key,
+                ScancodeSet2,
+                HandleControl::Ignore,
+            )),
+        }
+    }
+
+    fn set(&self) -> ScancodeSet {
+        match self {
+            ScancodeDecoder::Set1(_) => ScancodeSet::Set1,
+            ScancodeDecoder::Set2(_) => ScancodeSet::Set2,
+        }
+    }
+
+    fn add_byte(&mut self, scancode: u8) -> Option<KeyEvent> {
+        let result = match self {
+            ScancodeDecoder::Set1(keyboard) => keyboard.add_byte(scancode),
+            ScancodeDecoder::Set2(keyboard) => keyboard.add_byte(scancode),
+        };
+        result.ok()?
+    }
+}
+
+/// Turns scancodes into `KeyboardEvent`s.
+struct Decoder {
+    scancodes: ScancodeDecoder,
+    layout: Layout,
+    modifiers: Modifiers,
+    /// The key pressed last, as long as it is held. Only this key repeats.
+    repeating: Option<KeyCode>,
+    /// The LED state last sent to the keyboard.
+    leds: Leds,
+}
+
+impl Decoder {
+    fn new() -> Self {
+        Decoder {
+            scancodes: ScancodeDecoder::new(ScancodeSet::Set1),
+            layout: Layout::default(),
+            modifiers: Modifiers::default(),
+            repeating: None,
+            leds: Leds::default(),
+        }
+    }
+
+    fn decode(&mut self, scancode: u8) -> Option<KeyboardEvent> {
+        let key_event = self.scancodes.add_byte(scancode)?;
+        let code = key_event.code;
+        let action = KeyAction::from(&key_event);
+
+        let held = self.repeating == Some(code);
+        match action {
+            KeyAction::Pressed => self.repeating = Some(code),
+            KeyAction::Released if held => self.repeating = None,
+            KeyAction::Released => {}
+        }
+        self.modifiers.update(code, action, held);
+        self.sync_leds();
+
+        Some(KeyboardEvent {
+            code,
+            action,
+            modifiers: self.modifiers,
+            key: self.layout.map(&key_event, &self.modifiers),
+        })
+    }
+
+    /// Makes the LEDs reflect the lock key state.
+    ///
+    /// The PS/2 command blocks until the keyboard acknowledges it, so it is
+    /// sent by the work queue, without the decoder locked. If the update
+    /// cannot be queued, the next event tries again.
+    fn sync_leds(&mut self) {
+        let leds = Leds::from(&self.modifiers);
+        if leds != self.leds && workqueue::schedule(apply_leds, leds.as_byte().into()).is_ok() {
+            self.leds = leds;
+        }
+    }
+}
+
+/// Sends the LED state queued by `Decoder::sync_leds`.
+fn apply_leds(byte: u64) {
+    if let Err(err) = set_leds(Leds::from_byte(byte as u8)) {
+        serial_println!("WARNING: failed to set keyboard LEDs: {:?}", err);
+    }
+}
+
+lazy_static! {
+    /// Decoder state shared by all consumers, so that multi-byte scancodes
+    /// and modifiers are tracked correctly regardless of who reads them.
+    static ref DECODER: Mutex<Decoder> = Mutex::new(Decoder::new());
+}
+
+/// Selects the layout used to map keys to characters.
+pub fn set_layout(layout: Layout) {
+    DECODER.lock().layout = layout;
+}
+
+/// Returns the active keyboard layout.
+pub fn layout() -> Layout {
+    DECODER.lock().layout
+}
+
+/// Switches the scancode set sent by the keyboard and the decoder with it.
+///
+/// Scancodes that are still queued are discarded, since they belong to the
+/// previous set.
+pub fn set_scancode_set(set: ScancodeSet) -> Result<(), Ps2Error> {
+    let mut decoder = DECODER.lock();
+    device::set_scancode_set(set)?;
+    while try_read_scancode().is_some() {}
+    decoder.scancodes = ScancodeDecoder::new(set);
+    decoder.repeating = None;
+    Ok(())
+}
+
+/// Returns the scancode set the decoder currently expects.
+pub fn scancode_set() -> ScancodeSet {
+    DECODER.lock().scancodes.set()
+}
+
+/// Creates the scancode queue. Scancodes that arrive before are dropped.
+///
+/// Must be called after the heap is initialized.
+pub fn init() {
+    SCANCODE_QUEUE
+        .try_init_once(|| ArrayQueue::new(SCANCODE_QUEUE_SIZE))
+        .expect("keyboard::init should only be called once");
+}
+
+/// Called by the keyboard interrupt handler
+///
+/// Must not block or allocate.
+pub(crate) fn add_scancode(scancode: u8) {
+    if let Ok(queue) = SCANCODE_QUEUE.try_get() {
+        if queue.push(scancode).is_err() {
+            workqueue::schedule_or_run(warn_queue_full, u64::from(scancode));
+        } else {
+            WAKER.wake();
+        }
+    } else {
+        workqueue::schedule_or_run(warn_queue_uninitialized, u64::from(scancode));
+    }
+}
+
+fn warn_queue_full(scancode: u64) {
+    serial_println!(
+        "WARNING: scancode queue full; dropping keyboard input {:#x}",
+        scancode
+    );
+}
+
+fn warn_queue_uninitialized(scancode: u64) {
+    serial_println!(
+        "WARNING: scancode queue uninitialized; dropping keyboard input {:#x}",
+        scancode
+    );
+}
+
+/// Removes the oldest raw scancode from the queue, if any.
+pub fn try_read_scancode() -> Option<u8> {
+    SCANCODE_QUEUE.try_get().ok()?.pop()
+}
+
+/// Feeds one scancode to the shared decoder and returns the event it completes.
+fn decode(scancode: u8) -> Option<KeyboardEvent> {
+    DECODER.lock().decode(scancode)
+}
+
+/// Decodes queued scancodes until an event is complete, without blocking.
+pub fn try_read_event() -> Option<KeyboardEvent> {
+    while let Some(scancode) = try_read_scancode() {
+        if let Some(event) = decode(scancode) {
+            return Some(event);
+        }
+    }
+    None
+}
+
+/// Decodes queued scancodes until a key is pressed, without blocking.
+pub fn try_read_key() -> Option<DecodedKey> {
+    while let Some(event) = try_read_event() {
+        if let Some(key) = event.key {
+            return Some(key);
+        }
+    }
+    None
+}
+
+/// Halts the CPU until the next key event is decoded.
+///
+/// Interrupts must be enabled when calling this function, since the queue is
+/// only filled by the keyboard interrupt.
+pub fn read_event() -> KeyboardEvent {
+    loop {
+        if let Some(event) = try_read_event() {
+            return event;
+        }
+
+        // a scancode arriving between the check and `hlt` would otherwise
+        // only wake us up on the next interrupt
+        interrupts::disable();
+        if SCANCODE_QUEUE
+            .try_get()
+            .map_or(true, |queue| queue.is_empty())
+        {
+            interrupts::enable_and_hlt();
+        } else {
+            interrupts::enable();
+        }
+    }
+}
+
+/// Halts the CPU until the next key is pressed. See `read_event`.
+pub fn read_key() -> DecodedKey {
+    loop {
+        if let Some(key) = read_event().key {
+            return key;
+        }
+    }
+}
+
+/// An asynchronous stream of raw scancodes.
+///
+/// Only one stream should be polled at a time, since the keyboard interrupt
+/// only wakes the task that polled last.
+pub struct ScancodeStream {
+    _private: (),
+}
+
+impl ScancodeStream {
+    pub fn new() -> Self {
+        ScancodeStream { _private: () }
+    }
+}
+
+impl Default for ScancodeStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stream for ScancodeStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+        let queue = SCANCODE_QUEUE
+            .try_get()
+            .expect("scancode queue not initialized");
+
+        // fast path
+        if let Some(scancode) = queue.pop() {
+            return Poll::Ready(Some(scancode));
+        }
+
+        // register before the second check, so that no wakeup gets lost
+        WAKER.register(cx.waker());
+        match queue.pop() {
+            Some(scancode) => {
+                WAKER.take();
+                Poll::Ready(Some(scancode))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// An asynchronous stream of key presses and releases.
+pub struct EventStream {
+    scancodes: ScancodeStream,
+}
+
+impl EventStream {
+    pub fn new() -> Self {
+        EventStream {
+            scancodes: ScancodeStream::new(),
+        }
+    }
+}
+
+impl Default for EventStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stream for EventStream {
+    type Item = KeyboardEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<KeyboardEvent>> {
+        loop {
+            match self.scancodes.poll_next_unpin(cx) {
+                Poll::Ready(Some(scancode)) => {
+                    if let Some(event) = decode(scancode) {
+                        return Poll::Ready(Some(event));
+                    }
+                }
+                other => return other.map(|_| None),
+            }
+        }
+    }
+}
+
+/// An asynchronous stream of pressed keys as mapped by the active layout.
+pub struct KeyStream {
+    events: EventStream,
+}
+
+impl KeyStream {
+    pub fn new() -> Self {
+        KeyStream {
+            events: EventStream::new(),
+        }
+    }
+}
+
+impl Default for KeyStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stream for KeyStream {
+    type Item = DecodedKey;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<DecodedKey>> {
+        loop {
+            match self.events.poll_next_unpin(cx) {
+                Poll::Ready(Some(event)) => {
+                    if let Some(key) = event.key {
+                        return Poll::Ready(Some(key));
+                    }
+                }
+                other => return other.map(|_| None),
+            }
+        }
+    }
+}
+
+/// Waits asynchronously for the next key press.
+pub async fn next_key() -> DecodedKey {
+    KeyStream::new()
+        .next()
+        .await
+        .expect("the key stream never ends")
+}
+
+fn print_key(key: DecodedKey) {
+    match key {
+        DecodedKey::Unicode(character) => print!("{}", character),
+        DecodedKey::RawKey(key) => print!("{:?}", key),
+    }
+}
+
+/// Echoes every key to the screen.
+pub async fn print_keypresses() {
+    let mut keys = KeyStream::new();
+    while let Some(key) = keys.next().await {
+        print_key(key);
+    }
+}
+
+/// Echoes every key to the screen, halting the CPU while waiting for input.
+pub fn echo_keypresses() -> ! {
+    loop {
+        print_key(read_key());
+    }
+}
+
+#[test_case]
+fn test_lock_keys_toggle_on_press() {
+    // set 1: Caps Lock is 0x3A, Num Lock 0x45, a release adds 0x80
+    let mut decoder = Decoder::new();
+    let event = decoder.decode(0x3A).unwrap();
+    assert!(event.is_press());
+    assert!(event.modifiers.caps_lock());
+    assert!(decoder.decode(0xBA).unwrap().modifiers.caps_lock());
+
+    assert!(decoder.decode(0x45).unwrap().modifiers.num_lock());
+    decoder.decode(0xC5);
+
+    let event = decoder.decode(0x3A).unwrap();
+    assert!(!event.modifiers.caps_lock());
+    assert!(event.modifiers.num_lock());
+}
+
+#[test_case]
+fn test_held_lock_key_toggles_once() {
+    let mut decoder = Decoder::new();
+    decoder.decode(0x3A);
+    decoder.decode(0x3A);
+    let event = decoder.decode(0x3A).unwrap();
+    assert!(event.modifiers.caps_lock());
+    decoder.decode(0xBA);
+    assert!(decoder.repeating.is_none());
+    assert!(!decoder.decode(0x3A).unwrap().modifiers.caps_lock());
+}
+
+#[test_case]
+fn test_leds_round_trip_through_byte() {
+    let leds = Leds {
+        caps_lock: true,
+        num_lock: false,
+        scroll_lock: true,
+    };
+    assert_eq!(Leds::from_byte(leds.as_byte()), leds);
+}
+
+#[test_case]
+fn test_layout_change_keeps_lock_state() {
+    // set 1: A is 0x1E
+    let mut decoder = Decoder::new();
+    decoder.decode(0x3A);
+    decoder.decode(0xBA);
+    decoder.layout = Layout::Uk105Key;
+    assert_eq!(
+        decoder.decode(0x1E).unwrap().key,
+        Some(DecodedKey::Unicode('A'))
+    );
+}
+
+#[test_case]
+fn test_held_caps_lock_maps_like_the_leds() {
+    let mut decoder = Decoder::new();
+    decoder.decode(0x3A);
+    decoder.decode(0x3A);
+    decoder.decode(0xBA);
+    let event = decoder.decode(0x1E).unwrap();
+    assert!(event.modifiers.caps_lock());
+    assert_eq!(event.key, Some(DecodedKey::Unicode('A')));
+}
@@ -0,0 +1,106 @@
+use super::Modifiers;
+use crate::ps2::{self, Ps2Error, Ps2Port};
+
+const COMMAND_SET_LEDS: u8 = 0xED;
+const COMMAND_SCANCODE_SET: u8 = 0xF0;
+const COMMAND_SET_TYPEMATIC: u8 = 0xF3;
+
+const LED_SCROLL_LOCK: u8 = 1 << 0;
+const LED_NUM_LOCK: u8 = 1 << 1;
+const LED_CAPS_LOCK: u8 = 1 << 2;
+
+/// The scancode sets the decoder understands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScancodeSet {
+    /// Set 1, which the controller produces by translating set 2.
+    Set1,
+    /// Set 2 as sent by the keyboard, with translation disabled.
+    Set2,
+}
+
+/// The state of the three keyboard LEDs.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Leds {
+    pub caps_lock: bool,
+    pub num_lock: bool,
+    pub scroll_lock: bool,
+}
+
+impl Leds {
+    pub(super) fn from_byte(byte: u8) -> Self {
+        Leds {
+            caps_lock: byte & LED_CAPS_LOCK != 0,
+            num_lock: byte & LED_NUM_LOCK != 0,
+            scroll_lock: byte & LED_SCROLL_LOCK != 0,
+        }
+    }
+
+    pub(super) fn as_byte(&self) -> u8 {
+        let mut byte = 0;
+        if self.caps_lock {
+            byte |= LED_CAPS_LOCK;
+        }
+        if self.num_lock {
+            byte |= LED_NUM_LOCK;
+        }
+        if self.scroll_lock {
+            byte |= LED_SCROLL_LOCK;
+        }
+        byte
+    }
+}
+
+impl From<&Modifiers> for Leds {
+    fn from(modifiers: &Modifiers) -> Self {
+        Leds {
+            caps_lock: modifiers.caps_lock(),
+            num_lock: modifiers.num_lock(),
+            scroll_lock: modifiers.scroll_lock(),
+        }
+    }
+}
+
+/// The delay before a held key starts repeating.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TypematicDelay {
+    Ms250 = 0,
+    Ms500 = 1,
+    Ms750 = 2,
+    Ms1000 = 3,
+}
+
+/// Switches the keyboard LEDs on or off.
+pub fn set_leds(leds: Leds) -> Result<(), Ps2Error> {
+    ps2::with_controller(|controller| {
+        controller.send_to_device_with_arg(Ps2Port::Keyboard, COMMAND_SET_LEDS, leds.as_byte())
+    })
+}
+
+/// Configures the delay after which a held key repeats, and the repeat rate.
+///
+/// `rate` ranges from 0 (30 characters per second) to 31 (2 characters per
+/// second). Panics if it is larger.
+pub fn set_typematic(delay: TypematicDelay, rate: u8) -> Result<(), Ps2Error> {
+    assert!(rate <= 0x1F, "invalid typematic rate");
+    let value = (delay as u8) << 5 | rate;
+    ps2::with_controller(|controller| {
+        controller.send_to_device_with_arg(Ps2Port::Keyboard, COMMAND_SET_TYPEMATIC, value)
+    })
+}
+
+/// Tells the keyboard to send scancode set 2 and enables or disables the
+/// translation to set 1 in the controller accordingly.
+///
+/// The keyboard interrupt is disabled meanwhile, so that no scancode of the
+/// old set ends up in the queue halfway through the switch.
+pub(super) fn set_scancode_set(set: ScancodeSet) -> Result<(), Ps2Error> {
+    ps2::with_controller(|controller| {
+        controller.set_interrupt(Ps2Port::Keyboard, false)?;
+        let result = controller
+            .send_to_device_with_arg(Ps2Port::Keyboard, COMMAND_SCANCODE_SET, 2)
+            .and_then(|_| controller.set_translation(set == ScancodeSet::Set1));
+        controller.set_interrupt(Ps2Port::Keyboard, true)?;
+        result
+    })
+}
@@ -0,0 +1,119 @@
+use pc_keyboard::{DecodedKey, KeyCode, KeyEvent, KeyState};
+
+/// Whether a key went down or came up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyAction {
+    Pressed,
+    Released,
+}
+
+/// The state of the modifier and lock keys at the time of a key event.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Modifiers {
+    left_shift: bool,
+    right_shift: bool,
+    left_ctrl: bool,
+    right_ctrl: bool,
+    left_alt: bool,
+    right_alt: bool,
+    caps_lock: bool,
+    num_lock: bool,
+    scroll_lock: bool,
+}
+
+impl Modifiers {
+    pub fn shift(&self) -> bool {
+        self.left_shift || self.right_shift
+    }
+
+    pub fn ctrl(&self) -> bool {
+        self.left_ctrl || self.right_ctrl
+    }
+
+    /// Returns whether the left Alt key is held.
+    pub fn alt(&self) -> bool {
+        self.left_alt
+    }
+
+    /// Returns whether the right Alt key (AltGr on european layouts) is held.
+    pub fn alt_gr(&self) -> bool {
+        self.right_alt
+    }
+
+    pub fn caps_lock(&self) -> bool {
+        self.caps_lock
+    }
+
+    pub fn num_lock(&self) -> bool {
+        self.num_lock
+    }
+
+    pub fn scroll_lock(&self) -> bool {
+        self.scroll_lock
+    }
+
+    /// Updates the state with the given event. Lock keys toggle on press, so
+    /// `held` must tell whether the key was already down (typematic repeat).
+    pub(super) fn update(&mut self, code: KeyCode, action: KeyAction, held: bool) {
+        let pressed = action == KeyAction::Pressed;
+        match code {
+            KeyCode::ShiftLeft => self.left_shift = pressed,
+            KeyCode::ShiftRight => self.right_shift = pressed,
+            KeyCode::ControlLeft => self.left_ctrl = pressed,
+            KeyCode::ControlRight => self.right_ctrl = pressed,
+            KeyCode::AltLeft => self.left_alt = pressed,
+            KeyCode::AltRight => self.right_alt = pressed,
+            KeyCode::CapsLock if pressed && !held => self.caps_lock = !self.caps_lock,
+            KeyCode::NumpadLock if pressed && !held => self.num_lock = !self.num_lock,
+            KeyCode::ScrollLock if pressed && !held => self.scroll_lock = !self.scroll_lock,
+            _ => {}
+        }
+    }
+}
+
+/// A key press or release together with the modifier state after it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyboardEvent {
+    pub code: KeyCode,
+    pub action: KeyAction,
+    pub modifiers: Modifiers,
+    /// The key as mapped by the active layout, only present for presses.
+    pub key: Option<DecodedKey>,
+}
+
+impl KeyboardEvent {
+    pub fn is_press(&self) -> bool {
+        self.action == KeyAction::Pressed
+    }
+
+    /// Returns whether this is a press of the given character key with Ctrl
+    /// held, e.g. `event.is_ctrl('c')` for Ctrl+C.
+    pub fn is_ctrl(&self, character: char) -> bool {
+        self.is_press()
+            && self.modifiers.ctrl()
+            && matches!(self.key, Some(DecodedKey::Unicode(c)) if c.eq_ignore_ascii_case(&character))
+    }
+}
+
+impl From<&KeyEvent> for KeyAction {
+    fn from(event: &KeyEvent) -> Self {
+        match event.state {
+            KeyState::Up => KeyAction::Released,
+            _ => KeyAction::Pressed,
+        }
+    }
+}
+
+#[test_case]
+fn test_lock_keys_ignore_typematic_repeat() {
+    let mut modifiers = Modifiers::default();
+    modifiers.update(KeyCode::CapsLock, KeyAction::Pressed, false);
+    modifiers.update(KeyCode::CapsLock, KeyAction::Pressed, true);
+    modifiers.update(KeyCode::CapsLock, KeyAction::Released, true);
+    assert!(modifiers.caps_lock());
+
+    modifiers.update(KeyCode::ShiftLeft, KeyAction::Pressed, false);
+    modifiers.update(KeyCode::ShiftRight, KeyAction::Pressed, false);
+    modifiers.update(KeyCode::ShiftLeft, KeyAction::Released, false);
+    assert!(modifiers.shift());
+}
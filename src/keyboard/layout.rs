@@ -0,0 +1,64 @@
+use super::Modifiers;
+use pc_keyboard::{
+    layouts, DecodedKey, HandleControl, KeyCode, KeyEvent, KeyState, KeyboardLayout,
+};
+
+/// The keyboard layouts that can be selected at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    Us104Key,
+    Uk105Key,
+    /// German QWERTZ (`pc_keyboard` calls it `De104Key`).
+    De105Key,
+    Dvorak,
+    Azerty,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout::Us104Key
+    }
+}
+
+impl Layout {
+    /// Maps a key press to a character according to the layout, with the
+    /// shift, control, AltGr and lock state taken from `modifiers`. Releases
+    /// and presses of the modifier and lock keys map to nothing.
+    ///
+    /// The decoder's `Modifiers` are the only keyboard state, so that the
+    /// characters agree with them and the LEDs, also across layout changes
+    /// and typematic repeats of lock keys.
+    pub(super) fn map(self, event: &KeyEvent, modifiers: &Modifiers) -> Option<DecodedKey> {
+        if event.state == KeyState::Up {
+            return None;
+        }
+        match event.code {
+            KeyCode::ShiftLeft
+            | KeyCode::ShiftRight
+            | KeyCode::ControlLeft
+            | KeyCode::ControlRight
+            | KeyCode::AltRight
+            | KeyCode::CapsLock
+            | KeyCode::NumpadLock => return None,
+            _ => {}
+        }
+        let modifiers = pc_keyboard::Modifiers {
+            lshift: modifiers.shift(),
+            rshift: false,
+            lctrl: modifiers.ctrl(),
+            rctrl: false,
+            numlock: modifiers.num_lock(),
+            capslock: modifiers.caps_lock(),
+            alt_gr: modifiers.alt_gr(),
+        };
+        let code = event.code;
+        const CTRL: HandleControl = HandleControl::Ignore;
+        Some(match self {
+            Layout::Us104Key => layouts::Us104Key::map_keycode(code, &modifiers, CTRL),
+            Layout::Uk105Key => layouts::Uk105Key::map_keycode(code, &modifiers, CTRL),
+            Layout::De105Key => layouts::De104Key::map_keycode(code, &modifiers, CTRL),
+            Layout::Dvorak => layouts::Dvorak104Key::map_keycode(code, &modifiers, CTRL),
+            Layout::Azerty => layouts::Azerty::map_keycode(code, &modifiers, CTRL),
+        })
+    }
+}
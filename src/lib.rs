@@ -10,12 +10,33 @@
 
 extern crate alloc;
 
+pub mod acpi;
 pub mod allocator;
+pub mod apic;
+pub mod elf;
 pub mod gdt;
 pub mod interrupts;
+pub mod ipc;
+pub mod keyboard;
 pub mod memory;
+pub mod mouse;
+pub mod percpu;
+pub mod process;
+pub mod ps2;
+pub mod rtc;
 pub mod serial;
+pub mod smp;
+pub mod sync;
+pub mod syscall;
+pub mod task;
+#[doc(hidden)]
+pub mod test_support;
+pub mod thread;
+pub mod time;
+pub mod timer;
+pub mod usermode;
 pub mod vga_buffer;
+pub mod workqueue;
 
 #[cfg(test)]
 use bootloader::{entry_point, BootInfo};
@@ -46,11 +67,17 @@ fn ktest_main(boot_info: &'static mut BootInfo) -> ! {
 }
 
 pub fn init() {
+    percpu::init();
     gdt::init();
+    syscall::init();
     interrupts::init_idt();
     unsafe {
         interrupts::PICS.lock().initialize();
     }
+    time::init();
+    if let Err(err) = ps2::init() {
+        serial_println!("WARNING: PS/2 controller initialization failed: {:?}", err);
+    }
     x86_64::instructions::interrupts::enable();
 }
 
@@ -59,6 +86,10 @@ pub fn test_runner(tests: &[&dyn Testable]) {
     for test in tests {
         test.run();
     }
+    interrupts::stats::dump();
+    if thread::is_initialized() {
+        thread::stats::dump();
+    }
     exit_qemu(QemuExitCode::Success);
 }
 
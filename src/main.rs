@@ -14,14 +14,15 @@ use bootloader::boot_info::FrameBuffer;
 use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
 use rust_os::println;
-use x86_64::VirtAddr;
+use x86_64::{PhysAddr, VirtAddr};
 
 entry_point!(kernel_main);
 
 #[allow(unreachable_code)]
 fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
-    use rust_os::allocator;
     use rust_os::memory::{self, BootInfoFrameAllocator};
+    use rust_os::task::{Executor, Task};
+    use rust_os::{acpi, allocator, keyboard, mouse, rtc, smp, thread, time, timer, workqueue};
 
     if let Some(fb) = boot_info.framebuffer.as_mut() {
         init(fb);
@@ -36,6 +37,30 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
     let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_regions) };
 
     allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    thread::init();
+    timer::init();
+    workqueue::init();
+    keyboard::init();
+    if let Err(err) = mouse::init() {
+        println!("PS/2 mouse not available: {:?}", err);
+    }
+
+    if let Some(rsdp_addr) = boot_info.rsdp_addr.into_option() {
+        unsafe { acpi::init(PhysAddr::new(rsdp_addr)) }.expect("ACPI initialization failed");
+    }
+    time::init_hpet();
+    println!(
+        "TSC: {} Hz (calibrated against {:?}, invariant: {})",
+        time::tsc::frequency_hz(),
+        time::tsc::calibration_source(),
+        time::tsc::is_invariant()
+    );
+    println!("RTC: {} UTC", rtc::init());
+    match smp::init(&mut mapper, &mut frame_allocator) {
+        Ok(online) => println!("SMP: {} of {} CPUs online", online, smp::cpus().len()),
+        Err(err) => println!("SMP not available: {:?}", err),
+    }
+    memory::frames::init(frame_allocator);
 
     let heap_value = Box::new(41);
     println!("heap value at {:p}", heap_value);
@@ -63,7 +88,9 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
     #[cfg(test)]
     test_main();
 
-    rust_os::hlt_loop();
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(keyboard::print_keypresses()));
+    executor.run();
 }
 
 pub fn init(fb: &'static mut FrameBuffer) {
@@ -1,17 +1,98 @@
+pub mod address_space;
+pub mod frames;
+pub mod shared;
+pub mod tlb;
+
+pub use address_space::AddressSpace;
+pub use frames::GlobalFrameAllocator;
+pub use shared::SharedMemory;
+
 use bootloader::boot_info::{MemoryRegionKind, MemoryRegions};
+use spin::Once;
+use x86_64::structures::paging::mapper::{FlagUpdateError, UnmapError};
 use x86_64::structures::paging::{
-    page_table::PageTableEntry, FrameAllocator, Mapper, OffsetPageTable, Page, PhysFrame, Size4KiB,
+    page_table::PageTableEntry, FrameAllocator, Mapper, OffsetPageTable, Page, PageTableFlags,
+    PhysFrame, Size4KiB,
 };
 use x86_64::{structures::paging::PageTable, PhysAddr, VirtAddr};
 
+static PHYSICAL_MEMORY_OFFSET: Once<VirtAddr> = Once::new();
+
+/// The level 4 table that was active during `init`, which threads without an
+/// address space of their own run on.
+static KERNEL_LEVEL_4_FRAME: Once<PhysFrame> = Once::new();
+
+/// Frames below this address are only handed out by `allocate_low_frame`,
+/// since real-mode code such as the SMP trampoline has to live there.
+const LOW_MEMORY_END: u64 = 0x10_0000;
+
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    PHYSICAL_MEMORY_OFFSET.call_once(|| physical_memory_offset);
+    KERNEL_LEVEL_4_FRAME.call_once(|| x86_64::registers::control::Cr3::read().0);
     let level_4_table = active_level_4_table(physical_memory_offset);
     OffsetPageTable::new(level_4_table, physical_memory_offset)
 }
 
+/// Returns the virtual address at which the given physical address is mapped
+/// by the bootloader's complete physical memory mapping.
+///
+/// Panics if `memory::init` has not been called yet.
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    let offset = PHYSICAL_MEMORY_OFFSET
+        .get()
+        .expect("physical memory offset is not initialized");
+    *offset + addr.as_u64()
+}
+
+/// Returns the frame of the kernel's level 4 table, or `None` before `init`.
+pub fn kernel_level_4_frame() -> Option<PhysFrame> {
+    KERNEL_LEVEL_4_FRAME.get().copied()
+}
+
+/// Returns a mapper for the kernel's level 4 table, whose lower levels all
+/// address spaces share.
+///
+/// This function is unsafe because the mapper aliases the one returned by
+/// `init`, so the caller must only change mappings that nothing else uses.
+/// Panics if `init` has not been called.
+pub unsafe fn kernel_mapper() -> OffsetPageTable<'static> {
+    let frame = kernel_level_4_frame().expect("memory::init not called");
+    let level_4_table = &mut *phys_to_virt(frame.start_address()).as_mut_ptr();
+    OffsetPageTable::new(level_4_table, phys_to_virt(PhysAddr::new(0)))
+}
+
+/// Unmaps `page` and invalidates it in the TLB of every CPU (see `tlb`).
+/// Returns the frame the page was mapped to.
+pub fn unmap(
+    mapper: &mut impl Mapper<Size4KiB>,
+    page: Page<Size4KiB>,
+) -> Result<PhysFrame, UnmapError> {
+    let (frame, flush) = mapper.unmap(page)?;
+    flush.ignore();
+    tlb::flush_page(page);
+    Ok(frame)
+}
+
+/// Changes the flags of a mapped page and invalidates the old translation in
+/// the TLB of every CPU (see `tlb`).
+///
+/// This function is unsafe for the same reasons as `Mapper::update_flags`:
+/// the new flags must not break memory safety, e.g. by making memory that is
+/// still in use inaccessible.
+pub unsafe fn update_flags(
+    mapper: &mut impl Mapper<Size4KiB>,
+    page: Page<Size4KiB>,
+    flags: PageTableFlags,
+) -> Result<(), FlagUpdateError> {
+    mapper.update_flags(page, flags)?.ignore();
+    tlb::flush_page(page);
+    Ok(())
+}
+
 pub struct BootInfoFrameAllocator {
     memory_regions: &'static MemoryRegions,
     next: usize,
+    next_low: usize,
 }
 
 impl BootInfoFrameAllocator {
@@ -26,9 +107,24 @@ impl BootInfoFrameAllocator {
         BootInfoFrameAllocator {
             memory_regions,
             next: 0,
+            next_low: 0,
         }
     }
 
+    /// Allocates a usable frame below 1 MiB, which is reachable from real
+    /// mode. Frame 0 is never returned.
+    pub fn allocate_low_frame(&mut self) -> Option<PhysFrame> {
+        let frame = self
+            .usable_frames()
+            .filter(|frame| {
+                let addr = frame.start_address().as_u64();
+                addr != 0 && addr < LOW_MEMORY_END
+            })
+            .nth(self.next_low);
+        self.next_low += 1;
+        frame
+    }
+
     /// Returns an iterator over the usable frames specified in the MemoryRegions
     fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
         // get usable memory regions
@@ -48,7 +144,10 @@ impl BootInfoFrameAllocator {
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
-        let frame = self.usable_frames().nth(self.next);
+        let frame = self
+            .usable_frames()
+            .filter(|frame| frame.start_address().as_u64() >= LOW_MEMORY_END)
+            .nth(self.next);
         self.next += 1;
         frame
     }
@@ -0,0 +1,159 @@
+//! Address spaces of user programs.
+//!
+//! Every address space has its own level 4 table. The entries for user space
+//! (see `usermode::USER_SPACE_START`) start out empty, all other entries are
+//! copied from the kernel's level 4 table, so the kernel is mapped the same
+//! everywhere. Kernel mappings that need a new level 4 entry after an address
+//! space was created are not visible in it.
+
+use super::shared::SHARED;
+use super::{kernel_level_4_frame, phys_to_virt};
+use crate::usermode::{USER_SPACE_END, USER_SPACE_START};
+use core::ops::Range;
+use core::ptr;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::page_table::PageTableEntry;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, OffsetPageTable, PageTable, PhysFrame, Size4KiB, Translate,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// A user address that is not mapped in the address space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NotMapped(pub VirtAddr);
+
+pub struct AddressSpace {
+    level_4_frame: PhysFrame,
+}
+
+impl AddressSpace {
+    /// Creates an address space with the kernel's mappings and an empty user
+    /// space, or returns `None` if no frame is left for the level 4 table.
+    ///
+    /// `memory::init` must have been called.
+    pub fn new(frame_allocator: &mut impl FrameAllocator<Size4KiB>) -> Option<Self> {
+        let kernel_frame = kernel_level_4_frame().expect("memory::init not called");
+        let level_4_frame = frame_allocator.allocate_frame()?;
+        let kernel_table: &PageTable =
+            unsafe { &*phys_to_virt(kernel_frame.start_address()).as_ptr() };
+        let table: &mut PageTable =
+            unsafe { &mut *phys_to_virt(level_4_frame.start_address()).as_mut_ptr() };
+        table.zero();
+        for (index, entry) in kernel_table.iter().enumerate() {
+            if !user_entries().contains(&index) {
+                table[index] = entry.clone();
+            }
+        }
+        Some(AddressSpace { level_4_frame })
+    }
+
+    /// Returns the frame of the level 4 table, which is loaded into CR3 to
+    /// switch to the address space.
+    pub fn level_4_frame(&self) -> PhysFrame {
+        self.level_4_frame
+    }
+
+    /// Returns a mapper for the page tables of the address space.
+    pub fn mapper(&mut self) -> OffsetPageTable<'_> {
+        unsafe { OffsetPageTable::new(&mut *self.table(), phys_to_virt(PhysAddr::new(0))) }
+    }
+
+    /// Returns `true` if the address space is loaded in CR3 of the calling
+    /// processor.
+    pub fn is_active(&self) -> bool {
+        Cr3::read().0 == self.level_4_frame
+    }
+
+    /// Returns the physical address `address` is mapped to.
+    pub fn translate(&self, address: VirtAddr) -> Option<PhysAddr> {
+        // the mapper is only used for reading
+        let mapper =
+            unsafe { OffsetPageTable::new(&mut *self.table(), phys_to_virt(PhysAddr::new(0))) };
+        mapper.translate_addr(address)
+    }
+
+    /// Copies `bytes` to `address`, no matter whether the address space is
+    /// active and the pages are writable.
+    pub fn write(&mut self, address: VirtAddr, bytes: &[u8]) -> Result<(), NotMapped> {
+        self.for_each_chunk(address, bytes.len(), |virt, offset, len| unsafe {
+            ptr::copy_nonoverlapping(bytes[offset..].as_ptr(), virt.as_mut_ptr(), len);
+        })
+    }
+
+    /// Fills `buffer` with the bytes at `address`.
+    pub fn read(&self, address: VirtAddr, buffer: &mut [u8]) -> Result<(), NotMapped> {
+        self.for_each_chunk(address, buffer.len(), |virt, offset, len| unsafe {
+            ptr::copy_nonoverlapping(virt.as_ptr(), buffer[offset..].as_mut_ptr(), len);
+        })
+    }
+
+    /// Calls `f` with the kernel address, offset and length of each part of
+    /// the `len` bytes at `address` that lies in one page.
+    fn for_each_chunk(
+        &self,
+        address: VirtAddr,
+        len: usize,
+        mut f: impl FnMut(VirtAddr, usize, usize),
+    ) -> Result<(), NotMapped> {
+        let mut offset = 0;
+        while offset < len {
+            let current = address + offset;
+            let phys = self.translate(current).ok_or(NotMapped(current))?;
+            let chunk = (4096 - (current.as_u64() % 4096) as usize).min(len - offset);
+            f(phys_to_virt(phys), offset, chunk);
+            offset += chunk;
+        }
+        Ok(())
+    }
+
+    /// Frees the frames mapped in user space, except for those of shared
+    /// memory (see `shared::SHARED`), the page tables that map them and the
+    /// level 4 table.
+    ///
+    /// This function is unsafe because the caller must guarantee that the
+    /// address space is not active on any CPU, that no references to its user
+    /// memory are left and that all its frames belong to `frame_deallocator`.
+    pub unsafe fn free(self, frame_deallocator: &mut impl FrameDeallocator<Size4KiB>) {
+        let table = &*self.table();
+        for index in user_entries() {
+            free_entry(&table[index], 3, frame_deallocator);
+        }
+        frame_deallocator.deallocate_frame(self.level_4_frame);
+    }
+
+    fn table(&self) -> *mut PageTable {
+        phys_to_virt(self.level_4_frame.start_address()).as_mut_ptr()
+    }
+}
+
+/// Frees the frame `entry` points to, which is a page table of the given
+/// level or a mapped page at level 0, together with everything it maps.
+/// Shared pages are left to their `SharedMemory`.
+unsafe fn free_entry(
+    entry: &PageTableEntry,
+    level: u8,
+    frame_deallocator: &mut impl FrameDeallocator<Size4KiB>,
+) {
+    // user space has no huge pages
+    let frame = match entry.frame() {
+        Ok(frame) => frame,
+        Err(_) => return,
+    };
+    if level == 0 && entry.flags().contains(SHARED) {
+        return;
+    }
+    if level > 0 {
+        let table: &PageTable = &*phys_to_virt(frame.start_address()).as_ptr();
+        for entry in table.iter() {
+            free_entry(entry, level - 1, frame_deallocator);
+        }
+    }
+    frame_deallocator.deallocate_frame(frame);
+}
+
+/// The level 4 entries that map user space.
+fn user_entries() -> Range<usize> {
+    let start = usize::from(VirtAddr::new(USER_SPACE_START).p4_index());
+    let end = usize::from(VirtAddr::new(USER_SPACE_END).p4_index());
+    start..end
+}
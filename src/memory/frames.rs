@@ -0,0 +1,94 @@
+//! The global frame allocator, which user programs get their memory from.
+//!
+//! It hands out the frames of the `BootInfoFrameAllocator` passed to `init`
+//! and keeps freed frames on a free list for reuse. The list is linked through
+//! the free frames themselves, so freeing a frame never allocates.
+
+use super::{phys_to_virt, BootInfoFrameAllocator};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB};
+use x86_64::PhysAddr;
+
+static FRAMES: Mutex<Option<Frames>> = Mutex::new(None);
+
+struct Frames {
+    boot: BootInfoFrameAllocator,
+    /// The most recently freed frame, whose first word holds the address of
+    /// the next free frame, or 0 at the end of the list.
+    free_list: Option<PhysFrame>,
+    /// The number of frames handed out and not freed again.
+    allocated: usize,
+}
+
+impl Frames {
+    fn allocate(&mut self) -> Option<PhysFrame> {
+        let frame = match self.free_list {
+            Some(frame) => {
+                let next = unsafe { *phys_to_virt(frame.start_address()).as_ptr::<u64>() };
+                self.free_list =
+                    (next != 0).then(|| PhysFrame::containing_address(PhysAddr::new(next)));
+                frame
+            }
+            None => self.boot.allocate_frame()?,
+        };
+        self.allocated += 1;
+        Some(frame)
+    }
+
+    unsafe fn deallocate(&mut self, frame: PhysFrame) {
+        let next = self
+            .free_list
+            .map_or(0, |next| next.start_address().as_u64());
+        *phys_to_virt(frame.start_address()).as_mut_ptr::<u64>() = next;
+        self.free_list = Some(frame);
+        self.allocated -= 1;
+    }
+}
+
+/// Makes `GlobalFrameAllocator` hand out the frames of `boot`, which must not
+/// be used anymore afterwards.
+///
+/// Panics if called twice.
+pub fn init(boot: BootInfoFrameAllocator) {
+    let mut frames = FRAMES.lock();
+    assert!(frames.is_none(), "frame allocator initialized twice");
+    *frames = Some(Frames {
+        boot,
+        free_list: None,
+        allocated: 0,
+    });
+}
+
+/// Returns `true` once `init` has been called.
+pub fn is_initialized() -> bool {
+    without_interrupts(|| FRAMES.lock().is_some())
+}
+
+/// Returns the number of frames handed out by `GlobalFrameAllocator` and not
+/// freed again.
+pub fn allocated() -> usize {
+    with(|frames| frames.allocated)
+}
+
+fn with<T>(f: impl FnOnce(&mut Frames) -> T) -> T {
+    without_interrupts(|| f(FRAMES.lock().as_mut().expect("frames::init not called")))
+}
+
+/// Allocates from and frees to the global frame allocator.
+///
+/// Panics if `init` has not been called.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GlobalFrameAllocator;
+
+unsafe impl FrameAllocator<Size4KiB> for GlobalFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        with(Frames::allocate)
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for GlobalFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        with(|frames| frames.deallocate(frame))
+    }
+}
@@ -0,0 +1,167 @@
+//! Shared memory objects, which several address spaces map at once.
+//!
+//! A `SharedMemory` owns zeroed frames from `GlobalFrameAllocator`. Clones
+//! refer to the same frames, which are freed when the last clone is dropped.
+//! Whoever maps the object keeps a clone for as long as the mapping exists;
+//! the mapped pages carry the `SHARED` flag, so that `AddressSpace::free`
+//! leaves their frames alone.
+
+use super::{phys_to_virt, GlobalFrameAllocator};
+use crate::usermode::is_user_address;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::{fmt, ptr};
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB,
+};
+
+/// Marks user pages whose frames belong to a `SharedMemory` and not to the
+/// address space. One of the bits the CPU leaves to the OS.
+pub const SHARED: PageTableFlags = PageTableFlags::BIT_9;
+
+#[derive(Clone)]
+pub struct SharedMemory {
+    frames: Arc<Frames>,
+}
+
+struct Frames(Vec<PhysFrame>);
+
+impl Drop for Frames {
+    fn drop(&mut self) {
+        for &frame in &self.0 {
+            unsafe { GlobalFrameAllocator.deallocate_frame(frame) };
+        }
+    }
+}
+
+impl SharedMemory {
+    /// Allocates an object of `size` bytes, rounded up to whole pages, or
+    /// returns `None` if no memory is left, also for the list of frames on
+    /// the kernel heap.
+    ///
+    /// `frames::init` must have been called.
+    pub fn new(size: usize) -> Option<Self> {
+        let count = (size.checked_add(4095)?) / 4096;
+        let mut frames = Frames(Vec::new());
+        frames.0.try_reserve_exact(count).ok()?;
+        for _ in 0..count {
+            // dropping `frames` frees those allocated so far
+            let frame = GlobalFrameAllocator.allocate_frame()?;
+            unsafe {
+                ptr::write_bytes(
+                    phys_to_virt(frame.start_address()).as_mut_ptr::<u8>(),
+                    0,
+                    4096,
+                );
+            }
+            frames.0.push(frame);
+        }
+        Some(SharedMemory {
+            frames: Arc::new(frames),
+        })
+    }
+
+    /// Returns the size in bytes, a multiple of the page size.
+    pub fn size(&self) -> usize {
+        self.frames.0.len() * 4096
+    }
+
+    /// Returns the number of pages.
+    pub fn page_count(&self) -> usize {
+        self.frames.0.len()
+    }
+
+    /// Returns `true` if `other` refers to the same frames.
+    pub fn same_object(&self, other: &SharedMemory) -> bool {
+        Arc::ptr_eq(&self.frames, &other.frames)
+    }
+
+    /// Copies `bytes` into the object at `offset`.
+    ///
+    /// Panics if the bytes do not fit.
+    pub fn write(&self, offset: usize, bytes: &[u8]) {
+        self.for_each_chunk(offset, bytes.len(), |virt, done, len| unsafe {
+            ptr::copy_nonoverlapping(bytes[done..].as_ptr(), virt, len);
+        });
+    }
+
+    /// Fills `buffer` with the bytes of the object at `offset`.
+    ///
+    /// Panics if the bytes do not fit.
+    pub fn read(&self, offset: usize, buffer: &mut [u8]) {
+        self.for_each_chunk(offset, buffer.len(), |virt, done, len| unsafe {
+            ptr::copy_nonoverlapping(virt, buffer[done..].as_mut_ptr(), len);
+        });
+    }
+
+    /// Calls `f` with the kernel address of each part of the `len` bytes at
+    /// `offset` that lies in one frame, and with how many bytes came before.
+    fn for_each_chunk(&self, offset: usize, len: usize, mut f: impl FnMut(*mut u8, usize, usize)) {
+        let end = offset.checked_add(len).expect("offset overflows");
+        assert!(end <= self.size(), "access beyond the end of shared memory");
+        let mut done = 0;
+        while done < len {
+            let current = offset + done;
+            let frame = self.frames.0[current / 4096];
+            let chunk = (4096 - current % 4096).min(len - done);
+            let virt = phys_to_virt(frame.start_address()) + current % 4096;
+            f(virt.as_mut_ptr(), done, chunk);
+            done += chunk;
+        }
+    }
+
+    /// Maps the object to consecutive pages starting at `start`, which user
+    /// code can access with the given `flags`, to which `PRESENT`,
+    /// `USER_ACCESSIBLE` and `SHARED` are added.
+    ///
+    /// The caller must keep a clone of the object for as long as the pages
+    /// are mapped, and unmap them with `memory::unmap`. On errors, the error
+    /// comes with the number of pages from `start` that were mapped.
+    ///
+    /// Panics if a page lies outside of user space.
+    pub fn map(
+        &self,
+        mapper: &mut impl Mapper<Size4KiB>,
+        frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+        start: Page,
+        flags: PageTableFlags,
+    ) -> Result<(), (usize, MapToError<Size4KiB>)> {
+        let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | SHARED;
+        let table_flags =
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+        for (index, &frame) in self.frames.0.iter().enumerate() {
+            let page = start + index as u64;
+            assert!(
+                is_user_address(page.start_address()),
+                "{:?} is outside of user space",
+                page
+            );
+            unsafe {
+                mapper
+                    .map_to_with_table_flags(page, frame, flags, table_flags, frame_allocator)
+                    .map_err(|err| (index, err))?
+                    .flush();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Objects are equal if they refer to the same frames.
+impl PartialEq for SharedMemory {
+    fn eq(&self, other: &Self) -> bool {
+        self.same_object(other)
+    }
+}
+
+impl Eq for SharedMemory {}
+
+impl fmt::Debug for SharedMemory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SharedMemory")
+            .field("frames", &Arc::as_ptr(&self.frames))
+            .field("size", &self.size())
+            .finish()
+    }
+}
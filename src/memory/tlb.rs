@@ -0,0 +1,127 @@
+//! TLB shootdown.
+//!
+//! `MapperFlush::flush` only invalidates the TLB of the calling CPU, but the
+//! kernel's page tables are shared by all of them. When a page is unmapped or
+//! loses permissions, the functions here also send a `TlbShootdown` IPI to the
+//! other online CPUs and wait until each of them has invalidated the pages.
+//!
+//! Only one shootdown runs at a time. A CPU that waits for its turn keeps
+//! serving the requests of the others meanwhile, so concurrent shootdowns do
+//! not deadlock even though they run with interrupts disabled.
+
+use crate::interrupts::InterruptIndex;
+use crate::{apic, percpu, smp};
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::structures::paging::page::PageRange;
+use x86_64::structures::paging::{Page, Size4KiB};
+use x86_64::VirtAddr;
+
+/// Requests covering more pages flush the whole TLB instead, which keeps
+/// global pages; the kernel does not map any.
+const MAX_PAGE_FLUSHES: u64 = 32;
+
+/// The page count of a request that flushes the whole TLB.
+const FLUSH_ALL: u64 = u64::MAX;
+
+static SHOOTDOWN: Mutex<()> = Mutex::new(());
+
+/// The pages of the running request.
+static START: AtomicU64 = AtomicU64::new(0);
+static PAGES: AtomicU64 = AtomicU64::new(0);
+
+/// One bit per CPU index that has not yet invalidated the request's pages.
+static PENDING: AtomicU64 = AtomicU64::new(0);
+
+/// Invalidates `page` in the TLB of every CPU.
+pub fn flush_page(page: Page<Size4KiB>) {
+    shootdown(page.start_address(), 1);
+}
+
+/// Invalidates the pages of `range` in the TLB of every CPU.
+pub fn flush_range(range: PageRange<Size4KiB>) {
+    shootdown(range.start.start_address(), range.end - range.start);
+}
+
+/// Invalidates all non-global translations in the TLB of every CPU.
+pub fn flush_all() {
+    shootdown(VirtAddr::zero(), FLUSH_ALL);
+}
+
+fn shootdown(start: VirtAddr, pages: u64) {
+    if pages == 0 {
+        return;
+    }
+    invalidate(start, pages);
+    let targets = other_online_cpus();
+    if targets == 0 {
+        return;
+    }
+
+    without_interrupts(|| {
+        let _shootdown = loop {
+            if let Some(guard) = SHOOTDOWN.try_lock() {
+                break guard;
+            }
+            handle_shootdown();
+            spin_loop();
+        };
+
+        START.store(start.as_u64(), Ordering::Relaxed);
+        PAGES.store(pages, Ordering::Relaxed);
+        PENDING.store(targets, Ordering::Release);
+        for (index, cpu) in smp::cpus().iter().enumerate() {
+            if targets & (1 << index) != 0 {
+                apic::send_ipi(
+                    apic::Destination::Apic(cpu.apic_id()),
+                    InterruptIndex::TlbShootdown.as_u8(),
+                );
+            }
+        }
+        while PENDING.load(Ordering::Acquire) != 0 {
+            spin_loop();
+        }
+    });
+}
+
+/// Returns the mask of the online CPUs other than the calling one.
+fn other_online_cpus() -> u64 {
+    let cpus = smp::cpus();
+    if cpus.len() <= 1 {
+        return 0;
+    }
+    let this_cpu = percpu::cpu_index();
+    cpus.iter()
+        .enumerate()
+        .filter(|&(index, cpu)| index != this_cpu && cpu.is_online())
+        .fold(0, |mask, (index, _)| mask | 1 << index)
+}
+
+/// Invalidates the pages of the running request if the calling CPU still
+/// has to. Called by the `TlbShootdown` IPI handler.
+pub(crate) fn handle_shootdown() {
+    let bit = 1 << percpu::cpu_index();
+    if PENDING.load(Ordering::Acquire) & bit == 0 {
+        return;
+    }
+    invalidate(
+        VirtAddr::new(START.load(Ordering::Relaxed)),
+        PAGES.load(Ordering::Relaxed),
+    );
+    PENDING.fetch_and(!bit, Ordering::Release);
+}
+
+/// Invalidates the pages in the TLB of the calling CPU.
+fn invalidate(start: VirtAddr, pages: u64) {
+    use x86_64::instructions::tlb;
+
+    if pages > MAX_PAGE_FLUSHES {
+        tlb::flush_all();
+        return;
+    }
+    for page in 0..pages {
+        tlb::flush(start + page * 4096);
+    }
+}
@@ -0,0 +1,261 @@
+use crate::interrupts::irq::{self, IrqHandle, IrqReturn};
+use crate::ps2::{self, Ps2Error, Ps2Port};
+use crate::{serial_println, workqueue};
+use conquer_once::spin::OnceCell;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use crossbeam_queue::ArrayQueue;
+use futures_util::stream::Stream;
+use futures_util::task::AtomicWaker;
+use spin::Mutex;
+
+/// The IRQ line of the auxiliary PS/2 port on the slave PIC.
+pub const MOUSE_IRQ: u8 = 12;
+
+const EVENT_QUEUE_SIZE: usize = 64;
+
+const COMMAND_SET_DEFAULTS: u8 = 0xF6;
+const COMMAND_ENABLE_REPORTING: u8 = 0xF4;
+const COMMAND_SET_SAMPLE_RATE: u8 = 0xF3;
+const COMMAND_GET_DEVICE_ID: u8 = 0xF2;
+
+/// Device ID of an IntelliMouse, which sends a fourth byte with wheel motion.
+const DEVICE_ID_WHEEL_MOUSE: u8 = 3;
+
+const PACKET_LEFT_BUTTON: u8 = 1 << 0;
+const PACKET_RIGHT_BUTTON: u8 = 1 << 1;
+const PACKET_MIDDLE_BUTTON: u8 = 1 << 2;
+/// Always set in the first byte of a packet, used to stay in sync.
+const PACKET_ALWAYS_ONE: u8 = 1 << 3;
+const PACKET_X_SIGN: u8 = 1 << 4;
+const PACKET_Y_SIGN: u8 = 1 << 5;
+const PACKET_X_OVERFLOW: u8 = 1 << 6;
+const PACKET_Y_OVERFLOW: u8 = 1 << 7;
+
+static EVENT_QUEUE: OnceCell<ArrayQueue<MouseEvent>> = OnceCell::uninit();
+static WAKER: AtomicWaker = AtomicWaker::new();
+static DECODER: Mutex<PacketDecoder> = Mutex::new(PacketDecoder::new(3));
+static IRQ_HANDLE: OnceCell<IrqHandle> = OnceCell::uninit();
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MouseButtons {
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool,
+}
+
+/// One decoded mouse packet.
+///
+/// `dy` is positive when the mouse moves away from the user, and `wheel` is
+/// positive when the wheel is turned towards the user.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    pub dx: i16,
+    pub dy: i16,
+    pub wheel: i8,
+    pub buttons: MouseButtons,
+}
+
+/// Assembles the bytes of the mouse into 3-byte (standard) or 4-byte
+/// (IntelliMouse) packets.
+pub struct PacketDecoder {
+    bytes: [u8; 4],
+    received: usize,
+    packet_size: usize,
+}
+
+impl PacketDecoder {
+    pub const fn new(packet_size: usize) -> Self {
+        PacketDecoder {
+            bytes: [0; 4],
+            received: 0,
+            packet_size,
+        }
+    }
+
+    /// Adds one byte and returns the event once a packet is complete.
+    pub fn add_byte(&mut self, byte: u8) -> Option<MouseEvent> {
+        // drop bytes until we see something that can start a packet
+        if self.received == 0 && byte & PACKET_ALWAYS_ONE == 0 {
+            return None;
+        }
+        self.bytes[self.received] = byte;
+        self.received += 1;
+        if self.received < self.packet_size {
+            return None;
+        }
+        self.received = 0;
+
+        let flags = self.bytes[0];
+        // overflowing packets carry meaningless motion
+        if flags & (PACKET_X_OVERFLOW | PACKET_Y_OVERFLOW) != 0 {
+            return None;
+        }
+        // the movement is a 9-bit two's complement value with the sign in `flags`
+        let delta = |value: u8, sign: u8| {
+            let value = i16::from(value);
+            if flags & sign != 0 {
+                value - 0x100
+            } else {
+                value
+            }
+        };
+        // the lower four bits of the fourth byte are a two's complement value
+        let wheel = if self.packet_size == 4 {
+            ((self.bytes[3] << 4) as i8) >> 4
+        } else {
+            0
+        };
+
+        Some(MouseEvent {
+            dx: delta(self.bytes[1], PACKET_X_SIGN),
+            dy: delta(self.bytes[2], PACKET_Y_SIGN),
+            wheel,
+            buttons: MouseButtons {
+                left: flags & PACKET_LEFT_BUTTON != 0,
+                right: flags & PACKET_RIGHT_BUTTON != 0,
+                middle: flags & PACKET_MIDDLE_BUTTON != 0,
+            },
+        })
+    }
+}
+
+/// Enables the mouse on the auxiliary port, switching it to 4-byte packets
+/// with wheel movement if it supports them, and starts handling IRQ 12.
+///
+/// `ps2::init` must have found an auxiliary port, and the heap must be initialized.
+pub fn init() -> Result<(), Ps2Error> {
+    let packet_size = ps2::with_controller(|controller| {
+        if !controller.has_aux_port() {
+            return Err(Ps2Error::NoAuxPort);
+        }
+        // the responses are polled, so they must neither raise IRQ 12 (which
+        // would find the buffer empty) nor be mixed up with stale bytes
+        controller.set_interrupt(Ps2Port::Aux, false)?;
+        controller.flush_output();
+        let packet_size = configure(controller);
+        controller.set_interrupt(Ps2Port::Aux, true)?;
+        packet_size
+    })?;
+
+    *DECODER.lock() = PacketDecoder::new(packet_size);
+    EVENT_QUEUE
+        .try_init_once(|| ArrayQueue::new(EVENT_QUEUE_SIZE))
+        .expect("mouse::init should only be called once");
+
+    let handle = irq::request_irq(MOUSE_IRQ, |_| {
+        match ps2::read_from_interrupt(Ps2Port::Aux) {
+            Some(byte) => {
+                add_byte(byte);
+                IrqReturn::Handled
+            }
+            None => IrqReturn::NotHandled,
+        }
+    })
+    .expect("IRQ 12 is already in use");
+    IRQ_HANDLE.init_once(|| handle);
+
+    Ok(())
+}
+
+/// Sets up the mouse and returns its packet size.
+fn configure(controller: &mut ps2::Controller) -> Result<usize, Ps2Error> {
+    controller.send_to_device(Ps2Port::Aux, COMMAND_SET_DEFAULTS)?;
+
+    // the magic sample rate sequence unlocks the wheel of an IntelliMouse
+    for &rate in &[200, 100, 80] {
+        controller.send_to_device_with_arg(Ps2Port::Aux, COMMAND_SET_SAMPLE_RATE, rate)?;
+    }
+    controller.send_to_device(Ps2Port::Aux, COMMAND_GET_DEVICE_ID)?;
+    let packet_size = match controller.read_from_device(Ps2Port::Aux)? {
+        DEVICE_ID_WHEEL_MOUSE => 4,
+        _ => 3,
+    };
+
+    controller.send_to_device(Ps2Port::Aux, COMMAND_ENABLE_REPORTING)?;
+    Ok(packet_size)
+}
+
+fn add_byte(byte: u8) {
+    let event = match DECODER.lock().add_byte(byte) {
+        Some(event) => event,
+        None => return,
+    };
+    if let Ok(queue) = EVENT_QUEUE.try_get() {
+        if queue.push(event).is_err() {
+            workqueue::schedule_or_run(warn_queue_full, 0);
+        } else {
+            WAKER.wake();
+        }
+    }
+}
+
+fn warn_queue_full(_: u64) {
+    serial_println!("WARNING: mouse event queue full; dropping mouse input");
+}
+
+/// Removes the oldest mouse event from the queue, if any.
+pub fn try_read_event() -> Option<MouseEvent> {
+    EVENT_QUEUE.try_get().ok()?.pop()
+}
+
+/// An asynchronous stream of mouse events.
+///
+/// Only one stream should be polled at a time, since the mouse interrupt
+/// only wakes the task that polled last.
+pub struct MouseEventStream {
+    _private: (),
+}
+
+impl MouseEventStream {
+    pub fn new() -> Self {
+        MouseEventStream { _private: () }
+    }
+}
+
+impl Default for MouseEventStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stream for MouseEventStream {
+    type Item = MouseEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<MouseEvent>> {
+        let queue = EVENT_QUEUE
+            .try_get()
+            .expect("mouse event queue not initialized");
+
+        if let Some(event) = queue.pop() {
+            return Poll::Ready(Some(event));
+        }
+
+        WAKER.register(cx.waker());
+        match queue.pop() {
+            Some(event) => {
+                WAKER.take();
+                Poll::Ready(Some(event))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+#[test_case]
+fn test_decode_packets() {
+    let mut decoder = PacketDecoder::new(3);
+    // a byte without the always-one bit is skipped to resynchronize
+    assert_eq!(decoder.add_byte(0x00), None);
+    assert_eq!(decoder.add_byte(0b0001_1001), None);
+    assert_eq!(decoder.add_byte(0xFF), None);
+    let event = decoder.add_byte(0x05).unwrap();
+    assert_eq!((event.dx, event.dy), (-1, 5));
+    assert!(event.buttons.left && !event.buttons.right);
+
+    let mut decoder = PacketDecoder::new(4);
+    for &byte in &[0b0000_1000, 0x00, 0x00] {
+        assert_eq!(decoder.add_byte(byte), None);
+    }
+    assert_eq!(decoder.add_byte(0x0F).unwrap().wheel, -1);
+}
@@ -0,0 +1,176 @@
+//! Per-CPU data areas.
+//!
+//! Every processor has a `PerCpu` area whose address is in its GS base, so
+//! `gs:[0]` (which holds the address of the area itself) leads each processor
+//! to its own data without any locking. Code outside this module accesses the
+//! fields through the `this_cpu!` macro instead of reading GS by hand.
+//!
+//! While the kernel runs, `IA32_KERNEL_GS_BASE` holds the GS base of user
+//! mode, which is 0. Every way into user mode ends with a `swapgs`, and every
+//! way from user mode into the kernel starts with one (see `syscall` and
+//! `interrupts::SwapGsGuard`), so user code never sees the address of the
+//! area.
+//!
+//! The fields are atomics or only touched by the owning processor with
+//! interrupts disabled, so no other synchronization is needed.
+
+use crate::allocator::fixed_size_block::BlockCache;
+use crate::gdt::Tss;
+use alloc::boxed::Box;
+use core::arch::asm;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+use spin::Once;
+use x86_64::registers::model_specific::Msr;
+
+const IA32_GS_BASE: u32 = 0xC000_0101;
+const IA32_KERNEL_GS_BASE: u32 = 0xC000_0102;
+
+/// The value of `current_thread` while the processor runs no thread.
+pub(crate) const NO_THREAD: u64 = u64::MAX;
+
+/// The data area of one processor.
+#[repr(C)]
+pub struct PerCpu {
+    /// The address of this area, at offset 0 for `current`.
+    this: AtomicPtr<PerCpu>,
+    /// The top of the kernel stack of the running thread, at offset 8 for
+    /// the `syscall` entry stub (see `gdt::set_kernel_stack`).
+    pub(crate) syscall_stack: AtomicU64,
+    /// Where the `syscall` entry stub saves the user stack pointer, at
+    /// offset 16.
+    #[allow(dead_code)]
+    user_rsp: AtomicU64,
+    pub(crate) cpu_index: usize,
+    /// The ID of the running thread, or `NO_THREAD`. Kept up to date by the
+    /// scheduler.
+    pub(crate) current_thread: AtomicU64,
+    /// The number of interrupt handlers that are running on this processor.
+    pub(crate) interrupt_depth: AtomicUsize,
+    /// The TSS loaded by `gdt`.
+    pub(crate) tss: Once<&'static Tss>,
+    pub(crate) allocator_cache: BlockCache,
+}
+
+unsafe impl Sync for PerCpu {}
+
+impl PerCpu {
+    const fn new(cpu_index: usize) -> Self {
+        PerCpu {
+            this: AtomicPtr::new(core::ptr::null_mut()),
+            syscall_stack: AtomicU64::new(0),
+            user_rsp: AtomicU64::new(0),
+            cpu_index,
+            current_thread: AtomicU64::new(NO_THREAD),
+            interrupt_depth: AtomicUsize::new(0),
+            tss: Once::new(),
+            allocator_cache: BlockCache::new(),
+        }
+    }
+}
+
+/// The area of the bootstrap processor, which is needed before the heap.
+static BSP_AREA: PerCpu = PerCpu::new(0);
+
+/// Set once the bootstrap processor has its area. Every other processor
+/// installs its area before it runs any other kernel code.
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Returns a reference to a field of the calling processor's `PerCpu` area.
+///
+/// `percpu::init` must have been called on the processor.
+macro_rules! this_cpu {
+    ($field:ident) => {
+        &$crate::percpu::current().$field
+    };
+}
+pub(crate) use this_cpu;
+
+/// Installs the area of the bootstrap processor. Must be called before
+/// anything else during initialization.
+pub fn init() {
+    install(&BSP_AREA);
+    INITIALIZED.store(true, Ordering::SeqCst);
+}
+
+/// Allocates the area of the application processor with the given index,
+/// which `init_ap` installs once the processor runs.
+pub fn new_area(cpu_index: usize) -> &'static PerCpu {
+    Box::leak(Box::new(PerCpu::new(cpu_index)))
+}
+
+/// Frees an area from `new_area` that was never installed.
+///
+/// This function is unsafe because no processor may use the area anymore.
+pub unsafe fn free_area(area: &'static PerCpu) {
+    drop(Box::from_raw(area as *const PerCpu as *mut PerCpu));
+}
+
+/// Installs the area of an application processor, which must come from
+/// `new_area`.
+pub fn init_ap(area: &'static PerCpu) {
+    install(area);
+}
+
+fn install(area: &'static PerCpu) {
+    use x86_64::instructions::segmentation::{Segment, FS, GS};
+    use x86_64::structures::gdt::SegmentSelector;
+
+    area.this
+        .store(area as *const PerCpu as *mut PerCpu, Ordering::SeqCst);
+    let address = area as *const PerCpu as u64;
+    unsafe {
+        // loading a segment register resets its base, so it has to happen
+        // before the base is written
+        FS::set_reg(SegmentSelector(0));
+        GS::set_reg(SegmentSelector(0));
+        Msr::new(IA32_GS_BASE).write(address);
+        Msr::new(IA32_KERNEL_GS_BASE).write(0);
+    }
+}
+
+/// Exchanges the GS base with `IA32_KERNEL_GS_BASE`.
+///
+/// This function is unsafe because `this_cpu!` only works again after the
+/// matching second call.
+#[inline]
+pub(crate) unsafe fn swap_gs() {
+    asm!("swapgs", options(nostack, preserves_flags));
+}
+
+/// Returns `true` once the bootstrap processor installed its area.
+pub fn is_initialized() -> bool {
+    INITIALIZED.load(Ordering::Relaxed)
+}
+
+/// Returns the area of the calling processor.
+///
+/// The area must have been installed; use `this_cpu!` to access a field.
+#[inline]
+pub fn current() -> &'static PerCpu {
+    let area: *const PerCpu;
+    unsafe {
+        asm!("mov {}, gs:[0]", out(reg) area, options(nostack, readonly, preserves_flags));
+        &*area
+    }
+}
+
+/// Returns the index of the calling processor in `smp::cpus`.
+pub fn cpu_index() -> usize {
+    *this_cpu!(cpu_index)
+}
+
+#[test_case]
+fn test_bootstrap_processor_area() {
+    assert!(core::ptr::eq(current(), &BSP_AREA));
+    assert_eq!(cpu_index(), 0);
+}
+
+#[test_case]
+fn test_no_interrupt_nesting_in_tests() {
+    assert_eq!(crate::interrupts::nesting_depth(), 0);
+}
+
+#[test_case]
+fn test_kernel_gs_base_holds_user_value() {
+    assert_eq!(unsafe { Msr::new(IA32_KERNEL_GS_BASE).read() }, 0);
+}
@@ -0,0 +1,468 @@
+//! Processes: user programs with an address space and handles of their own.
+//!
+//! `spawn` loads an ELF executable into a new process, which runs on a thread
+//! of its own. The process that called `spawn` becomes its parent; processes
+//! spawned by kernel threads are children of the kernel, which any kernel
+//! thread can `wait` for.
+//!
+//! Processes start other processes by the name of an executable that the
+//! kernel made available with `register_program`.
+//!
+//! Processes can map `memory::SharedMemory` objects with `map_shared`, which
+//! keeps them alive until `unmap_shared` or the exit of the process.
+//!
+//! A process exits through the `exit` system call or is killed after a fault
+//! in user mode, which only ends the process and never the kernel. Its address
+//! space and handles are freed right away, but it stays in the process table
+//! as a zombie until its parent collects the exit code with `wait`. The
+//! children of an exited process become orphans, which nobody waits for, so
+//! they are removed from the table as soon as they exit.
+//!
+//! Processes get their memory from `memory::frames`, which must have been
+//! initialized, as must `thread`.
+
+mod handle;
+
+pub use handle::{Handle, HandleTable, Object, MAX_HANDLES};
+
+use crate::elf::{self, loader, ElfError, ElfFile};
+use crate::memory::{self, GlobalFrameAllocator, SharedMemory};
+use crate::sync::{Condvar, Mutex};
+use crate::thread::{self, ThreadId};
+use crate::usermode::{self, USER_SPACE_START};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::mem;
+use core::sync::atomic::{AtomicU64, Ordering};
+use lazy_static::lazy_static;
+use x86_64::structures::paging::{FrameDeallocator, Mapper, Page, PageTableFlags};
+use x86_64::VirtAddr;
+
+/// The exit code of processes that were killed after a fault.
+pub const EXIT_KILLED: i32 = -1;
+
+/// Where the heap of every process starts (see `grow_heap`).
+pub const HEAP_START: u64 = USER_SPACE_START + 0x0800_0000_0000;
+
+/// The heap must not grow into the stack.
+const HEAP_END: u64 = loader::STACK_TOP - loader::STACK_SIZE;
+
+/// Where `map_shared` maps shared memory, between the program and the heap.
+const SHARED_START: u64 = loader::PROGRAM_END;
+const SHARED_END: u64 = HEAP_START;
+
+lazy_static! {
+    static ref TABLE: Mutex<ProcessTable> = Mutex::new(ProcessTable::default());
+    /// The executables that processes can start by name, see `program`.
+    static ref PROGRAMS: Mutex<BTreeMap<String, &'static [u8]>> = Mutex::new(BTreeMap::new());
+}
+
+/// Notified whenever a process exits.
+static EXITED: Condvar = Condvar::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Pid(u64);
+
+impl Pid {
+    fn new() -> Self {
+        static NEXT_PID: AtomicU64 = AtomicU64::new(1);
+        Pid(NEXT_PID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Returns the PID with the given number, which may not belong to a
+    /// process.
+    pub fn from_u64(pid: u64) -> Self {
+        Pid(pid)
+    }
+
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for Pid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessState {
+    Running,
+    /// Exited with the given code, which the parent has not collected yet.
+    Zombie(i32),
+}
+
+/// Who waits for a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Parent {
+    Kernel,
+    Process(Pid),
+    /// The parent exited first.
+    Orphaned,
+}
+
+struct Process {
+    name: String,
+    parent: Parent,
+    state: ProcessState,
+    /// `None` once the process has exited.
+    address_space: Option<memory::AddressSpace>,
+    /// The end of the heap, which is page-aligned.
+    heap_end: u64,
+    /// The shared memory mapped by the process, by start address.
+    mappings: BTreeMap<u64, SharedMemory>,
+    handles: HandleTable,
+}
+
+#[derive(Default)]
+struct ProcessTable {
+    processes: BTreeMap<Pid, Process>,
+    /// The process each running user thread belongs to.
+    threads: BTreeMap<ThreadId, Pid>,
+}
+
+impl ProcessTable {
+    /// Turns `pid` into a zombie, or removes it if it is an orphan, and
+    /// orphans its children.
+    fn exit(&mut self, pid: Pid, code: i32) {
+        let mut orphaned_zombies = Vec::new();
+        for (&child, process) in self.processes.iter_mut() {
+            if process.parent == Parent::Process(pid) {
+                process.parent = Parent::Orphaned;
+                if let ProcessState::Zombie(_) = process.state {
+                    orphaned_zombies.push(child);
+                }
+            }
+        }
+        for child in orphaned_zombies {
+            self.processes.remove(&child);
+        }
+
+        let process = self
+            .processes
+            .get_mut(&pid)
+            .expect("exiting process not in process table");
+        if process.parent == Parent::Orphaned {
+            self.processes.remove(&pid);
+        } else {
+            process.state = ProcessState::Zombie(code);
+        }
+    }
+}
+
+/// Loads the ELF executable in `data` into a new process and starts it with
+/// the given arguments and environment variables (see `elf::load`).
+///
+/// The process is named after its first argument.
+pub fn spawn(data: &[u8], args: &[&str], env: &[&str]) -> Result<Pid, ElfError> {
+    spawn_with_handles(data, args, env, Vec::new())
+}
+
+/// Like `spawn`, but also hands `objects` to the process. They get the
+/// lowest free handles in order: 0 for the first, then those after the
+/// standard handles.
+///
+/// Panics if there are more objects than free handles.
+pub fn spawn_with_handles(
+    data: &[u8],
+    args: &[&str],
+    env: &[&str],
+    objects: Vec<Object>,
+) -> Result<Pid, ElfError> {
+    let file = ElfFile::parse(data)?;
+    let elf::Program {
+        address_space,
+        entry,
+        stack_pointer,
+    } = elf::load(&file, args, env, &mut GlobalFrameAllocator)?;
+    let level_4_frame = address_space.level_4_frame();
+
+    let pid = Pid::new();
+    let name = String::from(args.first().copied().unwrap_or("?"));
+    let mut handles = HandleTable::standard();
+    handles
+        .insert_all(objects)
+        .expect("more objects than free handles");
+    let process = Process {
+        name: name.clone(),
+        parent: current().map_or(Parent::Kernel, Parent::Process),
+        state: ProcessState::Running,
+        address_space: Some(address_space),
+        heap_end: HEAP_START,
+        mappings: BTreeMap::new(),
+        handles,
+    };
+    TABLE.lock().processes.insert(pid, process);
+
+    thread::Builder::new().name(name).spawn(move || {
+        // before the first system call needs it
+        TABLE.lock().threads.insert(thread::current(), pid);
+        // the address space is only freed by this thread in `exit`
+        unsafe {
+            thread::set_address_space(level_4_frame);
+            usermode::enter(entry, stack_pointer)
+        }
+    });
+    Ok(pid)
+}
+
+/// Makes the ELF executable in `data` available under `name`, which the
+/// `spawn` system call looks up with `program`. Replaces any earlier
+/// executable of that name.
+pub fn register_program(name: &str, data: &'static [u8]) {
+    PROGRAMS.lock().insert(String::from(name), data);
+}
+
+/// Returns the executable registered under `name` with `register_program`.
+pub fn program(name: &str) -> Option<&'static [u8]> {
+    PROGRAMS.lock().get(name).copied()
+}
+
+/// Returns the process the current thread belongs to, or `None` for kernel
+/// threads.
+pub fn current() -> Option<Pid> {
+    if !thread::is_initialized() {
+        return None;
+    }
+    TABLE.lock().threads.get(&thread::current()).copied()
+}
+
+/// Returns the state of the given process, or `None` if it is not in the
+/// process table (anymore).
+pub fn state(pid: Pid) -> Option<ProcessState> {
+    TABLE
+        .lock()
+        .processes
+        .get(&pid)
+        .map(|process| process.state)
+}
+
+/// Returns the name of the given process.
+pub fn name(pid: Pid) -> Option<String> {
+    TABLE
+        .lock()
+        .processes
+        .get(&pid)
+        .map(|process| process.name.clone())
+}
+
+/// Runs `f` on the handle table of the current process.
+///
+/// User threads outside of processes, like those started with
+/// `usermode::enter` directly, get a fresh table with the standard handles
+/// that is dropped afterwards.
+pub fn with_handles<T>(f: impl FnOnce(&mut HandleTable) -> T) -> T {
+    let pid = match current() {
+        Some(pid) => pid,
+        None => return f(&mut HandleTable::standard()),
+    };
+    let mut table = TABLE.lock();
+    let process = table
+        .processes
+        .get_mut(&pid)
+        .expect("current process not in process table");
+    f(&mut process.handles)
+}
+
+/// Grows the heap of the current process by `increment` bytes, rounded up to
+/// whole pages, and returns the start of the new memory, which is zeroed and
+/// writable. The heap grows without gaps, so the new memory directly follows
+/// the memory of the last call.
+///
+/// Returns `None`, and leaves the heap as it was, if the heap would grow into
+/// the stack or no memory is left. Panics if the current thread does not belong to a process.
+pub fn grow_heap(increment: usize) -> Option<VirtAddr> {
+    let pid = current().expect("grow_heap called outside of a process");
+    let mut table = TABLE.lock();
+    let process = table
+        .processes
+        .get_mut(&pid)
+        .expect("current process not in process table");
+    let start = process.heap_end;
+    let end = start.checked_add(increment as u64)?.checked_add(0xFFF)? & !0xFFF;
+    if end > HEAP_END {
+        return None;
+    }
+    let address_space = process
+        .address_space
+        .as_mut()
+        .expect("current process has exited");
+    let pages = Page::range(
+        Page::containing_address(VirtAddr::new(start)),
+        Page::containing_address(VirtAddr::new(end)),
+    );
+    let mut mapper = address_space.mapper();
+    if usermode::map_user_pages(
+        &mut mapper,
+        &mut GlobalFrameAllocator,
+        pages,
+        PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+    )
+    .is_err()
+    {
+        // the pages mapped before running out of frames
+        for page in pages {
+            if let Ok(frame) = memory::unmap(&mut mapper, page) {
+                unsafe { GlobalFrameAllocator.deallocate_frame(frame) };
+            }
+        }
+        return None;
+    }
+    process.heap_end = end;
+    Some(VirtAddr::new(start))
+}
+
+/// Maps `memory` into the current process at the lowest free address of the
+/// shared memory area, with the given `flags` (see `SharedMemory::map`), and
+/// returns the address. The process keeps the object alive until it unmaps
+/// it with `unmap_shared` or exits.
+///
+/// Returns `None` if `memory` is empty or the area or the memory for page
+/// tables is exhausted. Panics if the current thread does not belong to a
+/// process.
+pub fn map_shared(memory: SharedMemory, flags: PageTableFlags) -> Option<VirtAddr> {
+    let pid = current().expect("map_shared called outside of a process");
+    let mut table = TABLE.lock();
+    let process = table
+        .processes
+        .get_mut(&pid)
+        .expect("current process not in process table");
+    let size = memory.size() as u64;
+    if size == 0 {
+        return None;
+    }
+    let mut start = SHARED_START;
+    for (&mapped, other) in process.mappings.iter() {
+        if start + size <= mapped {
+            break;
+        }
+        start = mapped + other.size() as u64;
+    }
+    if start + size > SHARED_END {
+        return None;
+    }
+    let address_space = process
+        .address_space
+        .as_mut()
+        .expect("current process has exited");
+    let mut mapper = address_space.mapper();
+    let first = Page::containing_address(VirtAddr::new(start));
+    if let Err((mapped, _)) = memory.map(&mut mapper, &mut GlobalFrameAllocator, first, flags) {
+        for page in Page::range(first, first + mapped as u64) {
+            memory::unmap(&mut mapper, page).expect("shared page not mapped");
+        }
+        return None;
+    }
+    process.mappings.insert(start, memory);
+    Some(VirtAddr::new(start))
+}
+
+/// Unmaps the shared memory that `map_shared` mapped at `address` from the
+/// current process. Returns `false` if nothing is mapped there.
+///
+/// Panics if the current thread does not belong to a process.
+pub fn unmap_shared(address: VirtAddr) -> bool {
+    let pid = current().expect("unmap_shared called outside of a process");
+    let memory = {
+        let mut table = TABLE.lock();
+        let process = table
+            .processes
+            .get_mut(&pid)
+            .expect("current process not in process table");
+        let memory = match process.mappings.remove(&address.as_u64()) {
+            Some(memory) => memory,
+            None => return false,
+        };
+        let mut mapper = process
+            .address_space
+            .as_mut()
+            .expect("current process has exited")
+            .mapper();
+        let first = Page::containing_address(address);
+        let pages = Page::range(first, first + memory.page_count() as u64);
+        for page in pages {
+            let (_, flush) = mapper.unmap(page).expect("shared page not mapped");
+            flush.ignore();
+        }
+        memory::tlb::flush_range(pages);
+        memory
+    };
+    // the frames may be freed now that no TLB holds them anymore
+    drop(memory);
+    true
+}
+
+/// Terminates the current process with `code`, which its parent gets from
+/// `wait`.
+///
+/// Panics if the current thread does not belong to a process.
+pub fn exit(code: i32) -> ! {
+    let pid = current().expect("exit called outside of a process");
+    let (address_space, mappings, handles) = {
+        let mut table = TABLE.lock();
+        let process = table
+            .processes
+            .get_mut(&pid)
+            .expect("current process not in process table");
+        (
+            process.address_space.take(),
+            mem::take(&mut process.mappings),
+            mem::take(&mut process.handles),
+        )
+    };
+    drop(handles);
+    let kernel_frame = memory::kernel_level_4_frame().expect("memory::init not called");
+    // user memory is not touched anymore, and only this thread ever ran on the
+    // address space, so no other CPU has it loaded
+    unsafe {
+        thread::set_address_space(kernel_frame);
+        if let Some(address_space) = address_space {
+            address_space.free(&mut GlobalFrameAllocator);
+        }
+    }
+    // only now that nothing maps them, shared frames may be freed
+    drop(mappings);
+
+    {
+        let mut table = TABLE.lock();
+        table.threads.remove(&thread::current());
+        table.exit(pid, code);
+    }
+    EXITED.notify_all();
+    thread::exit();
+}
+
+/// Blocks until a child of the current process exits, removes it from the
+/// process table and returns its PID and exit code. Kernel threads wait for
+/// the processes spawned by any kernel thread.
+///
+/// `pid` selects the child to wait for; `None` waits for any child. Returns
+/// `None` if there is no such child.
+pub fn wait(pid: Option<Pid>) -> Option<(Pid, i32)> {
+    let parent = current().map_or(Parent::Kernel, Parent::Process);
+    let mut table = TABLE.lock();
+    loop {
+        let mut children = table.processes.iter().filter(|(&child, process)| {
+            process.parent == parent && pid.map_or(true, |pid| pid == child)
+        });
+        let mut found = false;
+        let zombie = children.find_map(|(&child, process)| {
+            found = true;
+            match process.state {
+                ProcessState::Zombie(code) => Some((child, code)),
+                ProcessState::Running => None,
+            }
+        });
+        if let Some((child, code)) = zombie {
+            table.processes.remove(&child);
+            return Some((child, code));
+        }
+        if !found {
+            return None;
+        }
+        table = EXITED.wait(table);
+    }
+}
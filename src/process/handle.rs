@@ -0,0 +1,84 @@
+use crate::ipc::{Channel, PipeReader, PipeWriter};
+use crate::memory::SharedMemory;
+use crate::syscall::{STDERR, STDOUT};
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// The number a process uses to refer to a kernel object in system calls,
+/// like a file descriptor on Unix.
+pub type Handle = u32;
+
+/// How many handles a process can have open at once.
+pub const MAX_HANDLES: usize = 256;
+
+/// A kernel object that handles refer to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Object {
+    /// The console, which `write` prints to and mirrors to the serial port.
+    Console,
+    /// The read end of a pipe, for `read`.
+    PipeReader(PipeReader),
+    /// The write end of a pipe, for `write`.
+    PipeWriter(PipeWriter),
+    /// A channel endpoint, for `send` and `recv`.
+    Channel(Channel),
+    /// A shared memory object, for `shm_map`.
+    SharedMemory(SharedMemory),
+}
+
+/// The open handles of a process.
+#[derive(Debug, Default)]
+pub struct HandleTable {
+    objects: BTreeMap<Handle, Object>,
+}
+
+impl HandleTable {
+    /// Returns a table with `STDOUT` and `STDERR`, which refer to the
+    /// console.
+    pub fn standard() -> Self {
+        let mut table = HandleTable::default();
+        table.objects.insert(STDOUT, Object::Console);
+        table.objects.insert(STDERR, Object::Console);
+        table
+    }
+
+    pub fn get(&self, handle: Handle) -> Option<&Object> {
+        self.objects.get(&handle)
+    }
+
+    /// Returns how many more handles can be opened.
+    pub fn free(&self) -> usize {
+        MAX_HANDLES - self.objects.len()
+    }
+
+    /// Adds `object` under the lowest free handle and returns the handle.
+    ///
+    /// Fails if `MAX_HANDLES` handles are open, handing `object` back.
+    pub fn insert(&mut self, object: Object) -> Result<Handle, Object> {
+        if self.free() == 0 {
+            return Err(object);
+        }
+        let handle = (0..)
+            .find(|handle| !self.objects.contains_key(handle))
+            .unwrap();
+        self.objects.insert(handle, object);
+        Ok(handle)
+    }
+
+    /// Adds all `objects` like `insert` and returns their handles, or none
+    /// of them if they do not all fit, handing them back.
+    pub fn insert_all(&mut self, objects: Vec<Object>) -> Result<Vec<Handle>, Vec<Object>> {
+        if self.free() < objects.len() {
+            return Err(objects);
+        }
+        Ok(objects
+            .into_iter()
+            .map(|object| self.insert(object).unwrap())
+            .collect())
+    }
+
+    /// Closes `handle` and returns the object it referred to.
+    pub fn remove(&mut self, handle: Handle) -> Option<Object> {
+        self.objects.remove(&handle)
+    }
+}
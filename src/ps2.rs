@@ -0,0 +1,317 @@
+use crate::serial_println;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+
+/// How often the status register is polled before an operation times out.
+const TIMEOUT_POLLS: usize = 100_000;
+
+/// How often a device command is repeated when the device asks for a resend.
+const MAX_RESENDS: usize = 3;
+
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+const STATUS_AUX_DATA: u8 = 1 << 5;
+
+const COMMAND_READ_CONFIG: u8 = 0x20;
+const COMMAND_WRITE_CONFIG: u8 = 0x60;
+const COMMAND_DISABLE_AUX: u8 = 0xA7;
+const COMMAND_ENABLE_AUX: u8 = 0xA8;
+const COMMAND_TEST_AUX: u8 = 0xA9;
+const COMMAND_SELF_TEST: u8 = 0xAA;
+const COMMAND_TEST_KEYBOARD: u8 = 0xAB;
+const COMMAND_DISABLE_KEYBOARD: u8 = 0xAD;
+const COMMAND_ENABLE_KEYBOARD: u8 = 0xAE;
+const COMMAND_WRITE_AUX: u8 = 0xD4;
+
+const CONFIG_KEYBOARD_INTERRUPT: u8 = 1 << 0;
+const CONFIG_AUX_INTERRUPT: u8 = 1 << 1;
+const CONFIG_AUX_CLOCK_DISABLED: u8 = 1 << 5;
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+const SELF_TEST_PASSED: u8 = 0x55;
+const PORT_TEST_PASSED: u8 = 0x00;
+
+/// Acknowledge byte sent by PS/2 devices after every command byte.
+pub const DEVICE_ACK: u8 = 0xFA;
+/// Sent by PS/2 devices if they want the last byte again.
+pub const DEVICE_RESEND: u8 = 0xFE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ps2Error {
+    Timeout,
+    /// The controller self-test returned the given value instead of 0x55.
+    SelfTestFailed(u8),
+    /// The interface test of a port returned the given error code.
+    PortTestFailed(Ps2Port, u8),
+    /// The controller has no auxiliary (mouse) port.
+    NoAuxPort,
+    /// The device answered a command with something else than an ACK.
+    UnexpectedResponse(u8),
+}
+
+/// The two ports of the 8042 controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ps2Port {
+    /// The first port, usually connected to the keyboard (IRQ 1).
+    Keyboard,
+    /// The second port, usually connected to the mouse (IRQ 12).
+    Aux,
+}
+
+/// The result of the controller initialization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControllerInfo {
+    pub keyboard_port: bool,
+    pub aux_port: bool,
+}
+
+pub struct Controller {
+    data: Port<u8>,
+    /// Reading yields the status register, writing sends a controller command.
+    command: Port<u8>,
+    aux_port: bool,
+}
+
+static CONTROLLER: Mutex<Controller> = Mutex::new(Controller {
+    data: Port::new(0x60),
+    command: Port::new(0x64),
+    aux_port: false,
+});
+
+impl Controller {
+    fn status(&mut self) -> u8 {
+        unsafe { self.command.read() }
+    }
+
+    fn wait_for_input_empty(&mut self) -> Result<(), Ps2Error> {
+        for _ in 0..TIMEOUT_POLLS {
+            if self.status() & STATUS_INPUT_FULL == 0 {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+        Err(Ps2Error::Timeout)
+    }
+
+    fn wait_for_output_full(&mut self) -> Result<(), Ps2Error> {
+        for _ in 0..TIMEOUT_POLLS {
+            if self.status() & STATUS_OUTPUT_FULL != 0 {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+        Err(Ps2Error::Timeout)
+    }
+
+    fn send_command(&mut self, command: u8) -> Result<(), Ps2Error> {
+        self.wait_for_input_empty()?;
+        unsafe { self.command.write(command) };
+        Ok(())
+    }
+
+    fn write_data(&mut self, value: u8) -> Result<(), Ps2Error> {
+        self.wait_for_input_empty()?;
+        unsafe { self.data.write(value) };
+        Ok(())
+    }
+
+    /// Waits for the next byte from the controller or one of the devices.
+    pub fn read_data(&mut self) -> Result<u8, Ps2Error> {
+        self.wait_for_output_full()?;
+        Ok(unsafe { self.data.read() })
+    }
+
+    /// Returns the next byte if there is one, together with the port it came from.
+    pub fn try_read_data(&mut self) -> Option<(Ps2Port, u8)> {
+        let status = self.status();
+        if status & STATUS_OUTPUT_FULL == 0 {
+            return None;
+        }
+        let port = if status & STATUS_AUX_DATA != 0 {
+            Ps2Port::Aux
+        } else {
+            Ps2Port::Keyboard
+        };
+        Some((port, unsafe { self.data.read() }))
+    }
+
+    /// Waits for the next byte from the device on the given port, dropping
+    /// bytes of the other port that arrive in the meantime.
+    pub fn read_from_device(&mut self, port: Ps2Port) -> Result<u8, Ps2Error> {
+        for _ in 0..TIMEOUT_POLLS {
+            match self.try_read_data() {
+                Some((from, byte)) if from == port => return Ok(byte),
+                Some(_) => {}
+                None => core::hint::spin_loop(),
+            }
+        }
+        Err(Ps2Error::Timeout)
+    }
+
+    /// Discards all bytes in the output buffer.
+    pub fn flush_output(&mut self) {
+        while self.try_read_data().is_some() {}
+    }
+
+    pub fn read_config(&mut self) -> Result<u8, Ps2Error> {
+        self.send_command(COMMAND_READ_CONFIG)?;
+        self.read_data()
+    }
+
+    pub fn write_config(&mut self, config: u8) -> Result<(), Ps2Error> {
+        self.send_command(COMMAND_WRITE_CONFIG)?;
+        self.write_data(config)
+    }
+
+    /// Returns whether the controller has a working auxiliary port.
+    pub fn has_aux_port(&self) -> bool {
+        self.aux_port
+    }
+
+    fn initialize(&mut self) -> Result<ControllerInfo, Ps2Error> {
+        // keep the devices from interfering with the setup
+        self.send_command(COMMAND_DISABLE_KEYBOARD)?;
+        self.send_command(COMMAND_DISABLE_AUX)?;
+        self.flush_output();
+
+        // disable the interrupts until the ports are tested
+        let config = self.read_config()?;
+        let aux_clock_was_disabled = config & CONFIG_AUX_CLOCK_DISABLED != 0;
+        let config = config & !(CONFIG_KEYBOARD_INTERRUPT | CONFIG_AUX_INTERRUPT);
+        self.write_config(config)?;
+
+        self.send_command(COMMAND_SELF_TEST)?;
+        match self.read_data()? {
+            SELF_TEST_PASSED => {}
+            other => return Err(Ps2Error::SelfTestFailed(other)),
+        }
+        // the self-test may reset the controller on some hardware
+        self.write_config(config)?;
+
+        // if enabling the second port clears its clock-disable bit, it exists
+        let mut aux_port = false;
+        if aux_clock_was_disabled {
+            self.send_command(COMMAND_ENABLE_AUX)?;
+            aux_port = self.read_config()? & CONFIG_AUX_CLOCK_DISABLED == 0;
+            self.send_command(COMMAND_DISABLE_AUX)?;
+        }
+
+        let keyboard_port = self.test_port(Ps2Port::Keyboard).is_ok();
+        if aux_port {
+            aux_port = self.test_port(Ps2Port::Aux).is_ok();
+        }
+
+        let mut config = self.read_config()?;
+        if keyboard_port {
+            self.send_command(COMMAND_ENABLE_KEYBOARD)?;
+            config |= CONFIG_KEYBOARD_INTERRUPT;
+        }
+        if aux_port {
+            self.send_command(COMMAND_ENABLE_AUX)?;
+            config |= CONFIG_AUX_INTERRUPT;
+        }
+        self.write_config(config)?;
+        self.aux_port = aux_port;
+
+        Ok(ControllerInfo {
+            keyboard_port,
+            aux_port,
+        })
+    }
+
+    fn test_port(&mut self, port: Ps2Port) -> Result<(), Ps2Error> {
+        let command = match port {
+            Ps2Port::Keyboard => COMMAND_TEST_KEYBOARD,
+            Ps2Port::Aux => COMMAND_TEST_AUX,
+        };
+        self.send_command(command)?;
+        match self.read_data()? {
+            PORT_TEST_PASSED => Ok(()),
+            error => {
+                serial_println!("WARNING: PS/2 {:?} port test failed: {:#x}", port, error);
+                Err(Ps2Error::PortTestFailed(port, error))
+            }
+        }
+    }
+
+    /// Sends a byte to the device on the given port and waits for its ACK,
+    /// repeating the byte if the device asks for a resend.
+    ///
+    /// The interrupts of the port should be disabled (or the caller must run
+    /// with interrupts disabled), since the interrupt handlers would
+    /// otherwise consume the response.
+    pub fn send_to_device(&mut self, port: Ps2Port, value: u8) -> Result<(), Ps2Error> {
+        for _ in 0..=MAX_RESENDS {
+            if port == Ps2Port::Aux {
+                self.send_command(COMMAND_WRITE_AUX)?;
+            }
+            self.write_data(value)?;
+            match self.read_from_device(port)? {
+                DEVICE_ACK => return Ok(()),
+                DEVICE_RESEND => continue,
+                other => return Err(Ps2Error::UnexpectedResponse(other)),
+            }
+        }
+        Err(Ps2Error::UnexpectedResponse(DEVICE_RESEND))
+    }
+
+    /// Sends a command byte followed by its argument byte to a device.
+    pub fn send_to_device_with_arg(
+        &mut self,
+        port: Ps2Port,
+        command: u8,
+        arg: u8,
+    ) -> Result<(), Ps2Error> {
+        self.send_to_device(port, command)?;
+        self.send_to_device(port, arg)
+    }
+
+    /// Enables or disables the translation of scancode set 2 to set 1.
+    pub fn set_translation(&mut self, enabled: bool) -> Result<(), Ps2Error> {
+        let config = self.read_config()?;
+        let config = if enabled {
+            config | CONFIG_TRANSLATION
+        } else {
+            config & !CONFIG_TRANSLATION
+        };
+        self.write_config(config)
+    }
+
+    /// Enables or disables the interrupt of the given port.
+    pub fn set_interrupt(&mut self, port: Ps2Port, enabled: bool) -> Result<(), Ps2Error> {
+        let bit = match port {
+            Ps2Port::Keyboard => CONFIG_KEYBOARD_INTERRUPT,
+            Ps2Port::Aux => CONFIG_AUX_INTERRUPT,
+        };
+        let config = self.read_config()?;
+        let config = if enabled { config | bit } else { config & !bit };
+        self.write_config(config)
+    }
+}
+
+/// Runs `f` on the controller with interrupts disabled, so that the
+/// interrupt handlers neither deadlock nor steal the responses.
+pub fn with_controller<T>(f: impl FnOnce(&mut Controller) -> T) -> T {
+    without_interrupts(|| f(&mut CONTROLLER.lock()))
+}
+
+/// Disables both ports, runs the controller and port self-tests and enables
+/// the ports that passed together with their interrupts.
+pub fn init() -> Result<ControllerInfo, Ps2Error> {
+    with_controller(|controller| controller.initialize())
+}
+
+/// Reads the pending byte of the given port, if there is one. Meant for the
+/// interrupt handlers, which run with interrupts disabled already.
+///
+/// Bytes of the other port are left in the buffer; they raise their own interrupt.
+pub(crate) fn read_from_interrupt(port: Ps2Port) -> Option<u8> {
+    let mut controller = CONTROLLER.lock();
+    let status = controller.status();
+    let is_aux = status & STATUS_AUX_DATA != 0;
+    if status & STATUS_OUTPUT_FULL == 0 || is_aux != (port == Ps2Port::Aux) {
+        return None;
+    }
+    Some(unsafe { controller.data.read() })
+}
@@ -0,0 +1,331 @@
+use crate::acpi;
+use crate::interrupts::irq::{self, IrqError, IrqHandle, IrqReturn};
+use crate::memory::phys_to_virt;
+use crate::time::Instant;
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use spin::{Mutex, Once};
+use x86_64::instructions::port::Port;
+
+/// The IRQ line of the real-time clock on the slave PIC.
+pub const RTC_IRQ: u8 = 8;
+
+const REGISTER_SECONDS: u8 = 0x00;
+const REGISTER_MINUTES: u8 = 0x02;
+const REGISTER_HOURS: u8 = 0x04;
+const REGISTER_DAY: u8 = 0x07;
+const REGISTER_MONTH: u8 = 0x08;
+const REGISTER_YEAR: u8 = 0x09;
+const REGISTER_STATUS_A: u8 = 0x0A;
+const REGISTER_STATUS_B: u8 = 0x0B;
+const REGISTER_STATUS_C: u8 = 0x0C;
+
+/// The century register used when the ACPI FADT does not name one.
+const DEFAULT_CENTURY_REGISTER: u8 = 0x32;
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const STATUS_B_PERIODIC_INTERRUPT: u8 = 1 << 6;
+const STATUS_B_BINARY_MODE: u8 = 1 << 2;
+const STATUS_B_24_HOUR_MODE: u8 = 1 << 1;
+const STATUS_C_PERIODIC_INTERRUPT: u8 = 1 << 6;
+const HOUR_PM_BIT: u8 = 1 << 7;
+
+struct Cmos {
+    index: Port<u8>,
+    data: Port<u8>,
+}
+
+impl Cmos {
+    fn read(&mut self, register: u8) -> u8 {
+        unsafe {
+            self.index.write(register);
+            self.data.read()
+        }
+    }
+
+    fn write(&mut self, register: u8, value: u8) {
+        unsafe {
+            self.index.write(register);
+            self.data.write(value);
+        }
+    }
+
+    fn update_in_progress(&mut self) -> bool {
+        self.read(REGISTER_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0
+    }
+
+    /// Reads the raw time registers once no update is in progress.
+    fn read_raw(&mut self, century_register: u8) -> [u8; 7] {
+        while self.update_in_progress() {
+            core::hint::spin_loop();
+        }
+        [
+            self.read(REGISTER_SECONDS),
+            self.read(REGISTER_MINUTES),
+            self.read(REGISTER_HOURS),
+            self.read(REGISTER_DAY),
+            self.read(REGISTER_MONTH),
+            self.read(REGISTER_YEAR),
+            self.read(century_register),
+        ]
+    }
+}
+
+static CMOS: Mutex<Cmos> = Mutex::new(Cmos {
+    index: Port::new(0x70),
+    data: Port::new(0x71),
+});
+
+/// The wall-clock time read at `init`, together with the TSC at that moment.
+static BOOT_TIME: Once<(u64, Instant)> = Once::new();
+
+static PERIODIC_TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// The handler registered for IRQ 8 while the periodic interrupt is enabled.
+static IRQ_HANDLE: Mutex<Option<IrqHandle>> = Mutex::new(None);
+
+/// A calendar date and time of day in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Returns the number of seconds since 1970-01-01 00:00:00 UTC.
+    pub fn to_unix_timestamp(&self) -> u64 {
+        let days = days_from_civil(i64::from(self.year), self.month.into(), self.day.into());
+        let seconds_of_day =
+            u64::from(self.hour) * 3600 + u64::from(self.minute) * 60 + u64::from(self.second);
+        days as u64 * 86400 + seconds_of_day
+    }
+
+    /// Converts the number of seconds since 1970-01-01 00:00:00 UTC into a date.
+    pub fn from_unix_timestamp(timestamp: u64) -> Self {
+        let (year, month, day) = civil_from_days((timestamp / 86400) as i64);
+        let seconds_of_day = timestamp % 86400;
+        DateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (seconds_of_day / 3600) as u8,
+            minute: (seconds_of_day / 60 % 60) as u8,
+            second: (seconds_of_day % 60) as u8,
+        }
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// Days since 1970-01-01 for the given proleptic Gregorian date.
+///
+/// See http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let month_index = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The inverse of `days_from_civil`, returning `(year, month, day)`.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = if days >= 0 { days } else { days - 146096 } / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+fn bcd_to_binary(value: u8) -> u8 {
+    (value & 0x0F) + (value >> 4) * 10
+}
+
+/// Returns the CMOS register holding the century as named by the ACPI FADT.
+fn century_register() -> u8 {
+    // the `century` field is at offset 108 of the FADT, zero if not supported
+    acpi::find_table(b"FACP")
+        .filter(|&fadt| acpi::read_header(fadt).length > 108)
+        .map(|fadt| unsafe { *phys_to_virt(fadt + 108u64).as_ptr::<u8>() })
+        .filter(|&register| register != 0)
+        .unwrap_or(DEFAULT_CENTURY_REGISTER)
+}
+
+/// Reads the current date and time from the CMOS real-time clock.
+///
+/// Since the registers may change between two reads, they are read
+/// repeatedly until two consecutive reads return the same values.
+pub fn read() -> DateTime {
+    use x86_64::instructions::interrupts;
+
+    let century_register = century_register();
+    let (raw, status_b) = interrupts::without_interrupts(|| {
+        let mut cmos = CMOS.lock();
+        let mut raw = cmos.read_raw(century_register);
+        loop {
+            let again = cmos.read_raw(century_register);
+            if again == raw {
+                break;
+            }
+            raw = again;
+        }
+        (raw, cmos.read(REGISTER_STATUS_B))
+    });
+
+    let [mut second, mut minute, hour_raw, mut day, mut month, mut year, mut century] = raw;
+    let pm = hour_raw & HOUR_PM_BIT != 0;
+    let mut hour = hour_raw & !HOUR_PM_BIT;
+
+    if status_b & STATUS_B_BINARY_MODE == 0 {
+        second = bcd_to_binary(second);
+        minute = bcd_to_binary(minute);
+        hour = bcd_to_binary(hour);
+        day = bcd_to_binary(day);
+        month = bcd_to_binary(month);
+        year = bcd_to_binary(year);
+        century = bcd_to_binary(century);
+    }
+
+    // 12 AM is midnight and 12 PM is noon
+    if status_b & STATUS_B_24_HOUR_MODE == 0 {
+        hour %= 12;
+        if pm {
+            hour += 12;
+        }
+    }
+
+    // assume the 21st century if the century register is missing or bogus
+    let century = if (19..=29).contains(&century) {
+        century
+    } else {
+        20
+    };
+
+    DateTime {
+        year: u16::from(century) * 100 + u16::from(year),
+        month,
+        day,
+        hour,
+        minute,
+        second,
+    }
+}
+
+/// Reads the real-time clock once and anchors the wall-clock time to the
+/// monotonic TSC clock, so that `unix_time` does not need to access the CMOS.
+///
+/// The TSC must have been calibrated through `time::init` before.
+pub fn init() -> DateTime {
+    let now = read();
+    BOOT_TIME.call_once(|| (now.to_unix_timestamp(), Instant::now()));
+    now
+}
+
+/// Returns the current time as duration since the UNIX epoch, or `None` if
+/// `init` was not called yet.
+pub fn unix_time() -> Option<Duration> {
+    BOOT_TIME
+        .get()
+        .map(|&(timestamp, instant)| Duration::from_secs(timestamp) + instant.elapsed())
+}
+
+/// Returns the current date and time, or `None` if `init` was not called yet.
+pub fn now() -> Option<DateTime> {
+    unix_time().map(|time| DateTime::from_unix_timestamp(time.as_secs()))
+}
+
+/// Enables the periodic interrupt of the RTC on IRQ 8 with a frequency of
+/// `32768 >> (rate - 1)` Hz.
+///
+/// Panics if `rate` is not within `3..=15` (8 kHz down to 2 Hz).
+pub fn enable_periodic_interrupt(rate: u8) -> Result<(), IrqError> {
+    use x86_64::instructions::interrupts::without_interrupts;
+
+    assert!((3..=15).contains(&rate), "invalid RTC interrupt rate");
+
+    let mut irq_handle = IRQ_HANDLE.lock();
+    if irq_handle.is_none() {
+        *irq_handle = Some(irq::request_irq(RTC_IRQ, |_| {
+            handle_interrupt();
+            IrqReturn::Handled
+        })?);
+    }
+
+    without_interrupts(|| {
+        let mut cmos = CMOS.lock();
+        let status_a = cmos.read(REGISTER_STATUS_A);
+        cmos.write(REGISTER_STATUS_A, (status_a & 0xF0) | rate);
+        let status_b = cmos.read(REGISTER_STATUS_B);
+        cmos.write(REGISTER_STATUS_B, status_b | STATUS_B_PERIODIC_INTERRUPT);
+        // a pending interrupt is only raised again after register C was read
+        cmos.read(REGISTER_STATUS_C);
+    });
+    Ok(())
+}
+
+/// Disables the periodic interrupt of the RTC and unregisters its handler.
+pub fn disable_periodic_interrupt() {
+    use x86_64::instructions::interrupts::without_interrupts;
+
+    without_interrupts(|| {
+        let mut cmos = CMOS.lock();
+        let status_b = cmos.read(REGISTER_STATUS_B);
+        cmos.write(REGISTER_STATUS_B, status_b & !STATUS_B_PERIODIC_INTERRUPT);
+    });
+    if let Some(handle) = IRQ_HANDLE.lock().take() {
+        irq::free_irq(handle);
+    }
+}
+
+/// Returns the number of periodic interrupts received so far.
+pub fn periodic_ticks() -> u64 {
+    PERIODIC_TICKS.load(Ordering::Relaxed)
+}
+
+fn handle_interrupt() {
+    // reading register C acknowledges the interrupt at the RTC
+    let status_c = CMOS.lock().read(REGISTER_STATUS_C);
+    if status_c & STATUS_C_PERIODIC_INTERRUPT != 0 {
+        PERIODIC_TICKS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[test_case]
+fn test_unix_timestamp_conversion() {
+    let date = DateTime {
+        year: 2021,
+        month: 6,
+        day: 1,
+        hour: 12,
+        minute: 30,
+        second: 15,
+    };
+    assert_eq!(date.to_unix_timestamp(), 1_622_550_615);
+    assert_eq!(DateTime::from_unix_timestamp(1_622_550_615), date);
+    assert_eq!(DateTime::from_unix_timestamp(0).year, 1970);
+}
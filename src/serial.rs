@@ -21,6 +21,17 @@ pub fn _print(args: ::core::fmt::Arguments) {
     });
 }
 
+/// A `fmt::Write` handle for the serial interface, for code that writes to
+/// any `fmt::Write`, e.g. the statistics tables.
+pub struct Writer;
+
+impl core::fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        _print(format_args!("{}", s));
+        Ok(())
+    }
+}
+
 /// Prints to the host through the serial interface.
 #[macro_export]
 macro_rules! serial_print {
@@ -0,0 +1,349 @@
+//! Bring-up of the application processors (APs).
+//!
+//! The bootstrap processor (BSP) finds the other processors in the ACPI MADT
+//! and starts them one after another with the INIT-SIPI-SIPI sequence. A
+//! started AP begins in real mode at a copy of the trampoline below, which
+//! switches straight to long mode with the kernel's page tables and calls
+//! `ap_main` on a fresh stack. There the AP installs its `percpu` area, loads
+//! its own GDT and TSS and the shared IDT, enables its local APIC and reports
+//! itself online.
+//!
+//! The APs do not run threads yet; they only wait for interrupts.
+//!
+//! APs run with interrupts enabled on the shared IDT, but the PICs and the
+//! timer only interrupt the BSP, so APs only receive IPIs. Their handlers
+//! must stick to atomics and spin locks such as the handler table of
+//! `interrupts::irq`; a spin lock is exclusive across CPUs, and taking it
+//! with interrupts disabled (as `without_interrupts` sections and
+//! `sync::with_state` do) only keeps a handler on the same CPU from
+//! deadlocking on it. Anything that needs a current thread, like the
+//! scheduler and the sleeping `sync` primitives, is off limits on APs.
+
+use crate::acpi::madt;
+use crate::memory::{self, phys_to_virt, BootInfoFrameAllocator};
+use crate::percpu::{self, PerCpu};
+use crate::time::Instant;
+use crate::{apic, gdt, interrupts, serial_println, syscall};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::arch::global_asm;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+use core::{mem, ptr};
+use spin::Once;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{Mapper, Page, PageTableFlags, Size4KiB};
+use x86_64::VirtAddr;
+
+/// The maximum number of processors, which lets CPU sets fit into a `u64`.
+/// Further processors in the MADT are not started.
+pub const MAX_CPUS: usize = 64;
+
+/// The size of the stack an AP runs `ap_main` on.
+const AP_STACK_SIZE: usize = 4096 * 4;
+
+/// How long an AP may take to come online before it is given up.
+const STARTUP_TIMEOUT: Duration = Duration::from_millis(100);
+
+// The trampoline is position independent: the real-mode part addresses its
+// data relative to CS, which the startup IPI sets to the page it runs in, and
+// the 64-bit part uses RIP-relative addressing. `init` fills in the data
+// block at the end, which is laid out like `TrampolineData`.
+global_asm!(
+    ".pushsection .rodata.smp_trampoline, \"a\"",
+    ".global smp_trampoline_start",
+    ".global smp_trampoline_long_mode",
+    ".global smp_trampoline_data",
+    ".global smp_trampoline_end",
+    ".code16",
+    "smp_trampoline_start:",
+    "cli",
+    "cld",
+    "mov %cs, %ax",
+    "mov %ax, %ds",
+    "lgdtl smp_trampoline_data + 2 - smp_trampoline_start",
+    // enable PAE and load the kernel's level 4 table
+    "mov $0x20, %eax",
+    "mov %eax, %cr4",
+    "mov smp_trampoline_data + 16 - smp_trampoline_start, %eax",
+    "mov %eax, %cr3",
+    // set EFER.LME and EFER.NXE, which the kernel's page tables rely on
+    "mov $0xC0000080, %ecx",
+    "rdmsr",
+    "or $0x900, %eax",
+    "wrmsr",
+    // enable paging, write protection and protected mode at once, which
+    // activates long mode; the far jump then switches to 64-bit code
+    "mov %cr0, %eax",
+    "or $0x80010001, %eax",
+    "mov %eax, %cr0",
+    "ljmpl *smp_trampoline_data + 8 - smp_trampoline_start",
+    ".code64",
+    "smp_trampoline_long_mode:",
+    "mov $0x10, %ax",
+    "mov %ax, %ds",
+    "mov %ax, %es",
+    "mov %ax, %ss",
+    "xor %ax, %ax",
+    "mov %ax, %fs",
+    "mov %ax, %gs",
+    "mov smp_trampoline_data + 24(%rip), %rsp",
+    "mov smp_trampoline_data + 40(%rip), %rdi",
+    "mov smp_trampoline_data + 32(%rip), %rax",
+    "call *%rax",
+    "ud2",
+    ".balign 8",
+    "smp_trampoline_data:",
+    ".skip 72",
+    "smp_trampoline_end:",
+    ".popsection",
+    options(att_syntax)
+);
+
+extern "C" {
+    static smp_trampoline_start: u8;
+    static smp_trampoline_long_mode: u8;
+    static smp_trampoline_data: u8;
+    static smp_trampoline_end: u8;
+}
+
+/// The data block at the end of the trampoline.
+#[repr(C)]
+struct TrampolineData {
+    _padding: u16,
+    /// The pseudo-descriptor loaded by `lgdt`.
+    gdt_limit: u16,
+    gdt_base: u32,
+    /// The far pointer to the 64-bit part.
+    long_mode_offset: u32,
+    long_mode_selector: u32,
+    cr3: u64,
+    stack_top: u64,
+    entry: u64,
+    /// The `PerCpu` area passed to `ap_main`.
+    percpu_area: u64,
+    /// Null, 64-bit code (selector 0x08) and data (selector 0x10) segments,
+    /// at offset 48.
+    gdt: [u64; 3],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmpError {
+    AlreadyInitialized,
+    /// ACPI is not initialized or there is no MADT.
+    NoMadt,
+    /// There is no free frame below 1 MiB for the trampoline.
+    NoLowMemory,
+    /// The level 4 page table is above 4 GiB, where the trampoline cannot
+    /// load it from real mode.
+    PageTableTooHigh,
+    /// Identity-mapping the trampoline failed.
+    MappingFailed,
+}
+
+/// A processor listed in the MADT.
+#[derive(Debug)]
+pub struct Cpu {
+    apic_id: u8,
+    is_bootstrap: bool,
+    online: AtomicBool,
+}
+
+impl Cpu {
+    pub fn apic_id(&self) -> u8 {
+        self.apic_id
+    }
+
+    /// Returns `true` for the processor that booted the kernel.
+    pub fn is_bootstrap(&self) -> bool {
+        self.is_bootstrap
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.online.load(Ordering::SeqCst)
+    }
+}
+
+/// The bootstrap processor first, then the APs that came online, in the
+/// order of the MADT.
+static CPUS: Once<Vec<Cpu>> = Once::new();
+
+/// Set by the AP that is being started once it is online.
+static AP_ONLINE: AtomicBool = AtomicBool::new(false);
+
+/// Starts all enabled processors listed in the MADT and returns the number
+/// of processors that are online afterwards.
+///
+/// APs that do not come online in time are reported, put back to sleep with
+/// an INIT IPI and left out of `cpus`. ACPI must be initialized and the TSC
+/// calibrated (see `time::init`).
+pub fn init(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut BootInfoFrameAllocator,
+) -> Result<usize, SmpError> {
+    if CPUS.is_completed() {
+        return Err(SmpError::AlreadyInitialized);
+    }
+
+    let madt = madt::parse().ok_or(SmpError::NoMadt)?;
+    unsafe { apic::init(madt.local_apic_address) };
+    let bsp_id = apic::id();
+
+    let mut cpus = vec![Cpu {
+        apic_id: bsp_id,
+        is_bootstrap: true,
+        online: AtomicBool::new(true),
+    }];
+    let ap_ids: Vec<u8> = madt
+        .processors
+        .iter()
+        .filter(|processor| processor.enabled && processor.apic_id != bsp_id)
+        .map(|processor| processor.apic_id)
+        .take(MAX_CPUS - 1)
+        .collect();
+    if ap_ids.is_empty() {
+        CPUS.call_once(|| cpus);
+        return Ok(1);
+    }
+
+    let (level_4_table, _) = Cr3::read();
+    let cr3 = level_4_table.start_address().as_u64();
+    if cr3 > u64::from(u32::MAX) {
+        return Err(SmpError::PageTableTooHigh);
+    }
+
+    // the trampoline enables paging while running from its physical address
+    let frame = frame_allocator
+        .allocate_low_frame()
+        .ok_or(SmpError::NoLowMemory)?;
+    let page = Page::<Size4KiB>::containing_address(VirtAddr::new(frame.start_address().as_u64()));
+    // the bootloader may have left a mapping of its own there
+    if let Ok((_, flush)) = mapper.unmap(page) {
+        flush.flush();
+    }
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    unsafe { mapper.identity_map(frame, flags, frame_allocator) }
+        .map_err(|_| SmpError::MappingFailed)?
+        .flush();
+
+    let base = frame.start_address().as_u64();
+    let data = unsafe { install_trampoline(phys_to_virt(frame.start_address())) };
+    for apic_id in ap_ids {
+        // the index the AP gets in `cpus` if it comes online
+        let index = cpus.len();
+        let stack = vec![0u8; AP_STACK_SIZE];
+        let stack_top = (stack.as_ptr() as u64 + stack.len() as u64) & !0xF;
+        let percpu_area = percpu::new_area(index);
+        unsafe {
+            ptr::write_volatile(
+                data,
+                TrampolineData {
+                    _padding: 0,
+                    gdt_limit: (mem::size_of::<[u64; 3]>() - 1) as u16,
+                    gdt_base: (base + trampoline_offset(&smp_trampoline_data) + 48) as u32,
+                    long_mode_offset: (base + trampoline_offset(&smp_trampoline_long_mode)) as u32,
+                    long_mode_selector: 0x08,
+                    cr3,
+                    stack_top,
+                    entry: ap_main as u64,
+                    percpu_area: percpu_area as *const PerCpu as u64,
+                    gdt: [0, 0x00af_9a00_0000_ffff, 0x00cf_9200_0000_ffff],
+                },
+            );
+        }
+        if start_ap(apic_id, (base >> 12) as u8) {
+            mem::forget(stack);
+            cpus.push(Cpu {
+                apic_id,
+                is_bootstrap: false,
+                online: AtomicBool::new(true),
+            });
+        } else {
+            serial_println!(
+                "WARNING: processor with APIC ID {} did not come online",
+                apic_id
+            );
+            // make sure the AP does not run the trampoline, or uses its
+            // stack or area, after they are gone
+            apic::send_init(apic_id);
+            delay(Duration::from_millis(10));
+            unsafe { percpu::free_area(percpu_area) };
+        }
+    }
+
+    // TLB shootdowns reach the APs once they are listed, so this has to
+    // come before the unmapping below
+    CPUS.call_once(|| cpus);
+    // the APs may have cached the mapping as well
+    memory::unmap(mapper, page).expect("trampoline page was unmapped");
+    Ok(online_cpus())
+}
+
+/// Copies the trampoline to `dest` and returns a pointer to its data block.
+unsafe fn install_trampoline(dest: VirtAddr) -> *mut TrampolineData {
+    let start = &smp_trampoline_start as *const u8;
+    let length = trampoline_offset(&smp_trampoline_end) as usize;
+    ptr::copy_nonoverlapping(start, dest.as_mut_ptr(), length);
+    (dest + trampoline_offset(&smp_trampoline_data)).as_mut_ptr()
+}
+
+/// Returns the offset of the given trampoline label from its start.
+fn trampoline_offset(label: &u8) -> u64 {
+    label as *const u8 as u64 - unsafe { &smp_trampoline_start as *const u8 as u64 }
+}
+
+/// Sends INIT-SIPI-SIPI to the AP and waits until it is online. The
+/// trampoline must be at physical address `page * 4096`.
+fn start_ap(apic_id: u8, page: u8) -> bool {
+    AP_ONLINE.store(false, Ordering::SeqCst);
+    apic::send_init(apic_id);
+    delay(Duration::from_millis(10));
+    for _ in 0..2 {
+        apic::send_startup(apic_id, page);
+        delay(Duration::from_micros(200));
+        if AP_ONLINE.load(Ordering::SeqCst) {
+            return true;
+        }
+    }
+
+    let start = Instant::now();
+    while start.elapsed() < STARTUP_TIMEOUT {
+        if AP_ONLINE.load(Ordering::SeqCst) {
+            return true;
+        }
+        core::hint::spin_loop();
+    }
+    false
+}
+
+fn delay(duration: Duration) {
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        core::hint::spin_loop();
+    }
+}
+
+/// The entry point of an AP, called by the trampoline on the AP's own stack.
+extern "C" fn ap_main(percpu_area: &'static PerCpu) -> ! {
+    // first of all, since even the allocator relies on it
+    percpu::init_ap(percpu_area);
+    gdt::init_ap();
+    syscall::init();
+    interrupts::init_idt();
+    apic::enable();
+    AP_ONLINE.store(true, Ordering::SeqCst);
+
+    x86_64::instructions::interrupts::enable();
+    crate::hlt_loop();
+}
+
+/// Returns all processors found by `init`, or an empty slice before.
+pub fn cpus() -> &'static [Cpu] {
+    CPUS.get().map_or(&[], |cpus| cpus.as_slice())
+}
+
+/// Returns the number of processors that are online, which is 1 before
+/// `init` is called.
+pub fn online_cpus() -> usize {
+    cpus().iter().filter(|cpu| cpu.is_online()).count().max(1)
+}
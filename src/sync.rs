@@ -0,0 +1,52 @@
+//! Sleeping synchronization primitives for kernel threads.
+//!
+//! Unlike `spin::Mutex`, waiters are put to sleep with `thread::park` and
+//! queued in FIFO order, so contended locks are handed over fairly. All
+//! primitives need `thread::init`; only `Event::set`, `Semaphore::release`
+//! and the `try_` methods may be used from interrupt handlers.
+
+mod channel;
+mod condvar;
+mod event;
+mod mutex;
+mod rwlock;
+mod semaphore;
+
+pub use channel::{channel, Receiver, RecvError, SendError, Sender, TryRecvError, TrySendError};
+pub use condvar::Condvar;
+pub use event::Event;
+pub use mutex::{Mutex, MutexGuard};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::Semaphore;
+
+use crate::thread::{self, ThreadId};
+use alloc::vec::Vec;
+use x86_64::instructions::interrupts::without_interrupts;
+
+/// Runs `f` on the internal state of a primitive.
+///
+/// The state is guarded by a spin lock that is only held with interrupts
+/// disabled, so that its holder is never preempted and interrupt handlers
+/// can wake up waiters.
+fn with_state<S, R>(state: &spin::Mutex<S>, f: impl FnOnce(&mut S) -> R) -> R {
+    without_interrupts(|| f(&mut state.lock()))
+}
+
+/// Parks the current thread until `waiting` returns `false`.
+fn wait_while(mut waiting: impl FnMut() -> bool) {
+    while waiting() {
+        thread::park();
+    }
+}
+
+/// Removes the longest waiting thread from a wait queue.
+///
+/// Wait queues are plain vectors so that the primitives can be created in
+/// `const` contexts; they are short enough for the linear removal.
+fn pop_front(waiters: &mut Vec<ThreadId>) -> Option<ThreadId> {
+    if waiters.is_empty() {
+        None
+    } else {
+        Some(waiters.remove(0))
+    }
+}
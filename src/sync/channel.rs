@@ -0,0 +1,227 @@
+use super::{pop_front, with_state};
+use crate::thread::{self, ThreadId};
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::mem;
+
+/// Creates a bounded multi-producer multi-consumer channel.
+///
+/// `send` sleeps while `capacity` messages are queued and `recv` sleeps while
+/// the channel is empty. Both ends can be cloned.
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "channel capacity must not be zero");
+    let channel = Arc::new(Channel {
+        state: spin::Mutex::new(ChannelState {
+            messages: VecDeque::with_capacity(capacity),
+            capacity,
+            senders: 1,
+            receivers: 1,
+            waiting_senders: Vec::new(),
+            waiting_receivers: Vec::new(),
+        }),
+    });
+    (
+        Sender {
+            channel: channel.clone(),
+        },
+        Receiver { channel },
+    )
+}
+
+/// The message could not be sent because all receivers are gone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+/// The channel is empty and all senders are gone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    Full(T),
+    Disconnected(T),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Disconnected,
+}
+
+struct Channel<T> {
+    state: spin::Mutex<ChannelState<T>>,
+}
+
+struct ChannelState<T> {
+    messages: VecDeque<T>,
+    capacity: usize,
+    senders: usize,
+    receivers: usize,
+    waiting_senders: Vec<ThreadId>,
+    waiting_receivers: Vec<ThreadId>,
+}
+
+/// Queues the thread unless it is queued already.
+fn enqueue(waiters: &mut Vec<ThreadId>, thread: ThreadId) {
+    if !waiters.contains(&thread) {
+        waiters.push(thread);
+    }
+}
+
+/// Removes a thread that no longer waits, so that it doesn't swallow a
+/// wakeup meant for another waiter.
+fn dequeue(waiters: &mut Vec<ThreadId>, thread: Option<ThreadId>) {
+    if let Some(thread) = thread {
+        waiters.retain(|&waiter| waiter != thread);
+    }
+}
+
+fn wake_one(waiters: &mut Vec<ThreadId>) {
+    if let Some(waiter) = pop_front(waiters) {
+        thread::unpark(waiter);
+    }
+}
+
+fn wake_all(waiters: &mut Vec<ThreadId>) {
+    for waiter in mem::take(waiters) {
+        thread::unpark(waiter);
+    }
+}
+
+impl<T> Channel<T> {
+    fn try_send(&self, value: T, wait_as: Option<ThreadId>) -> Result<(), TrySendError<T>> {
+        with_state(&self.state, |state| {
+            if state.receivers == 0 {
+                dequeue(&mut state.waiting_senders, wait_as);
+                return Err(TrySendError::Disconnected(value));
+            }
+            if state.messages.len() == state.capacity {
+                if let Some(thread) = wait_as {
+                    enqueue(&mut state.waiting_senders, thread);
+                }
+                return Err(TrySendError::Full(value));
+            }
+            dequeue(&mut state.waiting_senders, wait_as);
+            state.messages.push_back(value);
+            wake_one(&mut state.waiting_receivers);
+            Ok(())
+        })
+    }
+
+    fn try_recv(&self, wait_as: Option<ThreadId>) -> Result<T, TryRecvError> {
+        with_state(&self.state, |state| match state.messages.pop_front() {
+            Some(value) => {
+                dequeue(&mut state.waiting_receivers, wait_as);
+                wake_one(&mut state.waiting_senders);
+                Ok(value)
+            }
+            None if state.senders == 0 => {
+                dequeue(&mut state.waiting_receivers, wait_as);
+                Err(TryRecvError::Disconnected)
+            }
+            None => {
+                if let Some(thread) = wait_as {
+                    enqueue(&mut state.waiting_receivers, thread);
+                }
+                Err(TryRecvError::Empty)
+            }
+        })
+    }
+}
+
+/// The sending half of a channel.
+pub struct Sender<T> {
+    channel: Arc<Channel<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Sends a message, sleeping while the channel is full.
+    pub fn send(&self, mut value: T) -> Result<(), SendError<T>> {
+        let current = thread::current();
+        loop {
+            // a full channel queues us for a wakeup by the next `recv`
+            match self.channel.try_send(value, Some(current)) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Disconnected(v)) => return Err(SendError(v)),
+                Err(TrySendError::Full(v)) => value = v,
+            }
+            thread::park();
+        }
+    }
+
+    /// Sends a message if the channel has room for it.
+    ///
+    /// Can be called from interrupt handlers.
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        self.channel.try_send(value, None)
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        with_state(&self.channel.state, |state| state.senders += 1);
+        Sender {
+            channel: self.channel.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        with_state(&self.channel.state, |state| {
+            state.senders -= 1;
+            if state.senders == 0 {
+                wake_all(&mut state.waiting_receivers);
+            }
+        });
+    }
+}
+
+/// The receiving half of a channel.
+pub struct Receiver<T> {
+    channel: Arc<Channel<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Receives the oldest message, sleeping while the channel is empty.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        let current = thread::current();
+        loop {
+            // an empty channel queues us for a wakeup by the next `send`
+            match self.channel.try_recv(Some(current)) {
+                Ok(value) => return Ok(value),
+                Err(TryRecvError::Disconnected) => return Err(RecvError),
+                Err(TryRecvError::Empty) => {}
+            }
+            thread::park();
+        }
+    }
+
+    /// Receives the oldest message if there is one.
+    ///
+    /// Can be called from interrupt handlers.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.channel.try_recv(None)
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        with_state(&self.channel.state, |state| state.receivers += 1);
+        Receiver {
+            channel: self.channel.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        with_state(&self.channel.state, |state| {
+            state.receivers -= 1;
+            if state.receivers == 0 {
+                wake_all(&mut state.waiting_senders);
+            }
+        });
+    }
+}
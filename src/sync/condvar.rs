@@ -0,0 +1,63 @@
+use super::{pop_front, wait_while, with_state, MutexGuard};
+use crate::thread::{self, ThreadId};
+use alloc::vec::Vec;
+
+/// A condition variable to wait for changes of data protected by a `Mutex`.
+///
+/// Unlike `std::sync::Condvar`, `wait` never returns spuriously; it returns
+/// only after a `notify_one` or `notify_all` that chose the thread.
+pub struct Condvar {
+    waiters: spin::Mutex<Vec<ThreadId>>,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Condvar {
+            waiters: spin::Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Releases the lock of `guard`, sleeps until notified and acquires the
+    /// lock again.
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let current = thread::current();
+        let mutex = guard.mutex;
+        // queue up before unlocking, so that no notification gets lost
+        with_state(&self.waiters, |waiters| waiters.push(current));
+        drop(guard);
+        wait_while(|| with_state(&self.waiters, |waiters| waiters.contains(&current)));
+        mutex.lock()
+    }
+
+    /// Waits until `condition` returns `false` for the protected data.
+    pub fn wait_while<'a, T: ?Sized>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    /// Wakes up the longest waiting thread.
+    pub fn notify_one(&self) {
+        if let Some(waiter) = with_state(&self.waiters, pop_front) {
+            thread::unpark(waiter);
+        }
+    }
+
+    /// Wakes up all waiting threads.
+    pub fn notify_all(&self) {
+        for waiter in with_state(&self.waiters, core::mem::take) {
+            thread::unpark(waiter);
+        }
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Condvar::new()
+    }
+}
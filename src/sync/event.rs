@@ -0,0 +1,63 @@
+use super::{wait_while, with_state};
+use crate::thread::{self, ThreadId};
+use alloc::vec::Vec;
+use core::mem;
+
+/// A one-shot event: threads calling `wait` sleep until `set` is called once,
+/// after which `wait` returns immediately.
+pub struct Event {
+    state: spin::Mutex<EventState>,
+}
+
+struct EventState {
+    set: bool,
+    waiters: Vec<ThreadId>,
+}
+
+impl Event {
+    pub const fn new() -> Self {
+        Event {
+            state: spin::Mutex::new(EventState {
+                set: false,
+                waiters: Vec::new(),
+            }),
+        }
+    }
+
+    /// Signals the event and wakes up all waiting threads.
+    ///
+    /// Can be called from interrupt handlers.
+    pub fn set(&self) {
+        let waiters = with_state(&self.state, |state| {
+            state.set = true;
+            mem::take(&mut state.waiters)
+        });
+        for waiter in waiters {
+            thread::unpark(waiter);
+        }
+    }
+
+    pub fn is_set(&self) -> bool {
+        with_state(&self.state, |state| state.set)
+    }
+
+    /// Sleeps until the event is set.
+    pub fn wait(&self) {
+        let current = thread::current();
+        let set = with_state(&self.state, |state| {
+            if !state.set {
+                state.waiters.push(current);
+            }
+            state.set
+        });
+        if !set {
+            wait_while(|| !self.is_set());
+        }
+    }
+}
+
+impl Default for Event {
+    fn default() -> Self {
+        Event::new()
+    }
+}
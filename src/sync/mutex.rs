@@ -0,0 +1,142 @@
+use super::{pop_front, wait_while, with_state};
+use crate::thread::{self, ThreadId};
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+
+/// A mutual exclusion lock that puts contending threads to sleep.
+///
+/// On unlock, the lock is handed directly to the thread that has waited
+/// longest, so no thread can be starved.
+pub struct Mutex<T: ?Sized> {
+    state: spin::Mutex<MutexState>,
+    data: UnsafeCell<T>,
+}
+
+struct MutexState {
+    owner: Option<ThreadId>,
+    waiters: Vec<ThreadId>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Mutex {
+            state: spin::Mutex::new(MutexState {
+                owner: None,
+                waiters: Vec::new(),
+            }),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// Acquires the lock, sleeping until it is available.
+    ///
+    /// Locking a mutex that the current thread already holds deadlocks.
+    pub fn lock(&self) -> MutexGuard<T> {
+        let current = thread::current();
+        let acquired = with_state(&self.state, |state| {
+            if state.owner.is_none() {
+                state.owner = Some(current);
+                true
+            } else {
+                state.waiters.push(current);
+                false
+            }
+        });
+        if !acquired {
+            // `unlock` makes us the owner before waking us up
+            wait_while(|| with_state(&self.state, |state| state.owner != Some(current)));
+        }
+        MutexGuard { mutex: self }
+    }
+
+    /// Acquires the lock if it is free and nobody is waiting for it.
+    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+        let current = thread::current();
+        with_state(&self.state, |state| {
+            if state.owner.is_none() {
+                state.owner = Some(current);
+                Some(MutexGuard { mutex: self })
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        with_state(&self.state, |state| state.owner.is_some())
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    /// Hands the lock to the longest waiting thread, if any.
+    pub(super) fn unlock(&self) {
+        let next = with_state(&self.state, |state| {
+            state.owner = pop_front(&mut state.waiters);
+            state.owner
+        });
+        if let Some(next) = next {
+            thread::unpark(next);
+        }
+    }
+}
+
+/// Without a running thread, the data cannot be locked and is not shown.
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if thread::try_current().is_none() {
+            return if self.is_locked() {
+                f.write_str("Mutex { <locked> }")
+            } else {
+                f.write_str("Mutex { <unknown> }")
+            };
+        }
+        match self.try_lock() {
+            Some(guard) => f.debug_struct("Mutex").field("data", &&*guard).finish(),
+            None => f.write_str("Mutex { <locked> }"),
+        }
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Mutex::new(T::default())
+    }
+}
+
+/// Releases the lock of a `Mutex` when dropped.
+pub struct MutexGuard<'a, T: ?Sized> {
+    pub(super) mutex: &'a Mutex<T>,
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
@@ -0,0 +1,202 @@
+use super::{wait_while, with_state};
+use crate::thread::{self, ThreadId};
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+
+/// A reader-writer lock that puts contending threads to sleep.
+///
+/// Waiters are served in FIFO order: once a writer waits, later readers
+/// queue up behind it instead of joining the current readers, and a group of
+/// readers at the front of the queue is admitted together.
+pub struct RwLock<T: ?Sized> {
+    state: spin::Mutex<RwLockState>,
+    data: UnsafeCell<T>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Read,
+    Write,
+}
+
+struct RwLockState {
+    readers: usize,
+    writer: bool,
+    waiters: Vec<(ThreadId, Access)>,
+}
+
+impl RwLockState {
+    fn is_waiting(&self, thread: ThreadId) -> bool {
+        self.waiters.iter().any(|&(waiter, _)| waiter == thread)
+    }
+
+    /// Admits the waiters at the front of the queue that can run now and
+    /// returns them for waking up.
+    fn admit_waiters(&mut self) -> Vec<ThreadId> {
+        let mut admitted = Vec::new();
+        while let Some(&(waiter, access)) = self.waiters.first() {
+            match access {
+                Access::Write if self.readers == 0 && !self.writer => {
+                    self.writer = true;
+                }
+                Access::Read if !self.writer => {
+                    self.readers += 1;
+                }
+                _ => break,
+            }
+            self.waiters.remove(0);
+            admitted.push(waiter);
+            if access == Access::Write {
+                break;
+            }
+        }
+        admitted
+    }
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub const fn new(value: T) -> Self {
+        RwLock {
+            state: spin::Mutex::new(RwLockState {
+                readers: 0,
+                writer: false,
+                waiters: Vec::new(),
+            }),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Acquires shared access, sleeping while a writer holds or waits for
+    /// the lock.
+    pub fn read(&self) -> RwLockReadGuard<T> {
+        self.acquire(Access::Read);
+        RwLockReadGuard { lock: self }
+    }
+
+    /// Acquires exclusive access, sleeping while anybody else holds the lock.
+    pub fn write(&self) -> RwLockWriteGuard<T> {
+        self.acquire(Access::Write);
+        RwLockWriteGuard { lock: self }
+    }
+
+    pub fn try_read(&self) -> Option<RwLockReadGuard<T>> {
+        with_state(&self.state, |state| {
+            if state.writer || !state.waiters.is_empty() {
+                return None;
+            }
+            state.readers += 1;
+            Some(RwLockReadGuard { lock: self })
+        })
+    }
+
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<T>> {
+        with_state(&self.state, |state| {
+            if state.writer || state.readers > 0 {
+                return None;
+            }
+            state.writer = true;
+            Some(RwLockWriteGuard { lock: self })
+        })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    fn acquire(&self, access: Access) {
+        let current = thread::current();
+        let acquired = with_state(&self.state, |state| {
+            let free = match access {
+                Access::Read => !state.writer,
+                Access::Write => !state.writer && state.readers == 0,
+            };
+            if free && state.waiters.is_empty() {
+                match access {
+                    Access::Read => state.readers += 1,
+                    Access::Write => state.writer = true,
+                }
+                true
+            } else {
+                state.waiters.push((current, access));
+                false
+            }
+        });
+        if !acquired {
+            // `release` removes us from the queue once it admitted us
+            wait_while(|| with_state(&self.state, |state| state.is_waiting(current)));
+        }
+    }
+
+    fn release(&self, access: Access) {
+        let admitted = with_state(&self.state, |state| {
+            match access {
+                Access::Read => state.readers -= 1,
+                Access::Write => state.writer = false,
+            }
+            state.admit_waiters()
+        });
+        for waiter in admitted {
+            thread::unpark(waiter);
+        }
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        RwLock::new(T::default())
+    }
+}
+
+/// Releases shared access to a `RwLock` when dropped.
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.release(Access::Read);
+    }
+}
+
+/// Releases exclusive access to a `RwLock` when dropped.
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.release(Access::Write);
+    }
+}
@@ -0,0 +1,77 @@
+use super::{pop_front, wait_while, with_state};
+use crate::thread::{self, ThreadId};
+use alloc::vec::Vec;
+
+/// A counting semaphore that puts threads to sleep while no permit is
+/// available.
+///
+/// Released permits are handed directly to the longest waiting thread.
+pub struct Semaphore {
+    state: spin::Mutex<SemaphoreState>,
+}
+
+struct SemaphoreState {
+    permits: usize,
+    waiters: Vec<ThreadId>,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Semaphore {
+            state: spin::Mutex::new(SemaphoreState {
+                permits,
+                waiters: Vec::new(),
+            }),
+        }
+    }
+
+    /// Takes a permit, sleeping until one is available.
+    pub fn acquire(&self) {
+        let current = thread::current();
+        let acquired = with_state(&self.state, |state| {
+            if state.permits > 0 && state.waiters.is_empty() {
+                state.permits -= 1;
+                true
+            } else {
+                state.waiters.push(current);
+                false
+            }
+        });
+        if !acquired {
+            // `release` removes us from the queue when handing us its permit
+            wait_while(|| with_state(&self.state, |state| state.waiters.contains(&current)));
+        }
+    }
+
+    /// Takes a permit if one is available and nobody is waiting for it.
+    pub fn try_acquire(&self) -> bool {
+        with_state(&self.state, |state| {
+            if state.permits > 0 && state.waiters.is_empty() {
+                state.permits -= 1;
+                true
+            } else {
+                false
+            }
+        })
+    }
+
+    /// Returns a permit, waking up the longest waiting thread if any.
+    ///
+    /// Can be called from interrupt handlers.
+    pub fn release(&self) {
+        let next = with_state(&self.state, |state| {
+            let next = pop_front(&mut state.waiters);
+            if next.is_none() {
+                state.permits += 1;
+            }
+            next
+        });
+        if let Some(next) = next {
+            thread::unpark(next);
+        }
+    }
+
+    pub fn available_permits(&self) -> usize {
+        with_state(&self.state, |state| state.permits)
+    }
+}
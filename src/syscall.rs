@@ -0,0 +1,817 @@
+//! System calls from user mode through `syscall` and `sysret`.
+//!
+//! User code puts the number of the system call into `rax` and up to six
+//! arguments into `rdi`, `rsi`, `rdx`, `r10`, `r8` and `r9`, like on Linux.
+//! The result comes back in `rax`: a negated `Errno` on failure, anything
+//! else on success. `rcx` and `r11` are clobbered, all other registers are
+//! preserved.
+//!
+//! The entry stub switches to the kernel stack of the thread (see
+//! `gdt::set_kernel_stack`), saves the user registers and calls
+//! `syscall_dispatch`, which looks up the handler in `TABLE`. Handlers run
+//! with interrupts enabled and may block. They access user memory only
+//! through `usermode::copy_from_user` and its siblings.
+
+mod errno;
+
+pub use errno::Errno;
+
+use crate::elf::ElfError;
+use crate::ipc::{self, ChannelError, Message, PipeWriter};
+use crate::memory::SharedMemory;
+use crate::process::{self, Handle, Object, Pid};
+use crate::{gdt, print, serial_print, thread, usermode};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::arch::global_asm;
+use core::convert::TryFrom;
+use core::str;
+use core::time::Duration;
+use x86_64::registers::model_specific::{Efer, EferFlags, LStar, SFMask, Star};
+use x86_64::registers::rflags::RFlags;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+pub const EXIT: u64 = 0;
+pub const WRITE: u64 = 1;
+pub const GETPID: u64 = 2;
+pub const YIELD: u64 = 3;
+pub const SLEEP: u64 = 4;
+pub const WAIT: u64 = 5;
+pub const SBRK: u64 = 6;
+pub const READ: u64 = 7;
+pub const CLOSE: u64 = 8;
+pub const PIPE: u64 = 9;
+pub const CHANNEL: u64 = 10;
+pub const SEND: u64 = 11;
+pub const RECV: u64 = 12;
+pub const SHM_CREATE: u64 = 13;
+pub const SHM_MAP: u64 = 14;
+pub const SHM_UNMAP: u64 = 15;
+pub const SPAWN: u64 = 16;
+
+/// The `pid` argument of `wait` that waits for any child.
+pub const ANY_CHILD: u64 = u64::MAX;
+
+/// The `flags` of `shm_map` that make the memory writable and executable.
+/// It is always readable.
+pub const SHM_WRITE: u64 = 1 << 1;
+pub const SHM_EXEC: u64 = 1 << 2;
+
+/// The handle of the standard output, which refers to the console in new
+/// processes (see `process::HandleTable::standard`).
+pub const STDOUT: u32 = 1;
+/// The handle of the standard error output, which goes to the same place as
+/// `STDOUT`.
+pub const STDERR: u32 = 2;
+
+pub type SyscallResult = Result<u64, Errno>;
+
+type Handler = fn(&SyscallArgs) -> SyscallResult;
+
+/// An entry of the system call table.
+struct Syscall {
+    number: u64,
+    name: &'static str,
+    handler: Handler,
+}
+
+/// The system calls, indexed by their number.
+static TABLE: [Syscall; 17] = [
+    Syscall {
+        number: EXIT,
+        name: "exit",
+        handler: sys_exit,
+    },
+    Syscall {
+        number: WRITE,
+        name: "write",
+        handler: sys_write,
+    },
+    Syscall {
+        number: GETPID,
+        name: "getpid",
+        handler: sys_getpid,
+    },
+    Syscall {
+        number: YIELD,
+        name: "yield",
+        handler: sys_yield,
+    },
+    Syscall {
+        number: SLEEP,
+        name: "sleep",
+        handler: sys_sleep,
+    },
+    Syscall {
+        number: WAIT,
+        name: "wait",
+        handler: sys_wait,
+    },
+    Syscall {
+        number: SBRK,
+        name: "sbrk",
+        handler: sys_sbrk,
+    },
+    Syscall {
+        number: READ,
+        name: "read",
+        handler: sys_read,
+    },
+    Syscall {
+        number: CLOSE,
+        name: "close",
+        handler: sys_close,
+    },
+    Syscall {
+        number: PIPE,
+        name: "pipe",
+        handler: sys_pipe,
+    },
+    Syscall {
+        number: CHANNEL,
+        name: "channel",
+        handler: sys_channel,
+    },
+    Syscall {
+        number: SEND,
+        name: "send",
+        handler: sys_send,
+    },
+    Syscall {
+        number: RECV,
+        name: "recv",
+        handler: sys_recv,
+    },
+    Syscall {
+        number: SHM_CREATE,
+        name: "shm_create",
+        handler: sys_shm_create,
+    },
+    Syscall {
+        number: SHM_MAP,
+        name: "shm_map",
+        handler: sys_shm_map,
+    },
+    Syscall {
+        number: SHM_UNMAP,
+        name: "shm_unmap",
+        handler: sys_shm_unmap,
+    },
+    Syscall {
+        number: SPAWN,
+        name: "spawn",
+        handler: sys_spawn,
+    },
+];
+
+// The stub runs with interrupts disabled (see `init`) and the GS base of user
+// mode, so it uses `swapgs` to reach the `PerCpu` area, whose
+// `syscall_stack` is at offset 8 and `user_rsp` at offset 16, and swaps back
+// right before `sysretq`. It pushes the user rsp, rflags and rip followed by
+// a `SyscallFrame`, which keeps the stack 16-byte aligned for the call.
+global_asm!(
+    ".global syscall_entry",
+    "syscall_entry:",
+    "swapgs",
+    "mov gs:[16], rsp",
+    "mov rsp, gs:[8]",
+    "push qword ptr gs:[16]",
+    "push r11",
+    "push rcx",
+    "push r9",
+    "push r8",
+    "push r10",
+    "push rdx",
+    "push rsi",
+    "push rdi",
+    "push rax",
+    "mov rdi, rsp",
+    "call syscall_dispatch",
+    "cli",
+    // skip the number, which the result in rax replaces
+    "add rsp, 8",
+    "pop rdi",
+    "pop rsi",
+    "pop rdx",
+    "pop r10",
+    "pop r8",
+    "pop r9",
+    "pop rcx",
+    "pop r11",
+    "pop rsp",
+    "swapgs",
+    "sysretq",
+);
+
+extern "C" {
+    fn syscall_entry();
+}
+
+/// The registers saved by the entry stub.
+#[repr(C)]
+struct SyscallFrame {
+    number: u64,
+    args: SyscallArgs,
+}
+
+/// The raw arguments of a system call, which handlers decode with `get`.
+#[repr(C)]
+pub struct SyscallArgs([u64; 6]);
+
+impl SyscallArgs {
+    /// Decodes argument `index` (0-5) as a `T`.
+    pub fn get<T: FromArg>(&self, index: usize) -> Result<T, Errno> {
+        T::from_arg(self.0[index])
+    }
+}
+
+/// A type that a raw system call argument can be decoded into.
+pub trait FromArg: Sized {
+    fn from_arg(raw: u64) -> Result<Self, Errno>;
+}
+
+impl FromArg for u64 {
+    fn from_arg(raw: u64) -> Result<Self, Errno> {
+        Ok(raw)
+    }
+}
+
+impl FromArg for usize {
+    fn from_arg(raw: u64) -> Result<Self, Errno> {
+        usize::try_from(raw).map_err(|_| Errno::EINVAL)
+    }
+}
+
+impl FromArg for u32 {
+    fn from_arg(raw: u64) -> Result<Self, Errno> {
+        u32::try_from(raw).map_err(|_| Errno::EINVAL)
+    }
+}
+
+impl FromArg for i32 {
+    fn from_arg(raw: u64) -> Result<Self, Errno> {
+        i32::try_from(raw as i64).map_err(|_| Errno::EINVAL)
+    }
+}
+
+/// Addresses must be canonical, but are not checked any further.
+impl FromArg for VirtAddr {
+    fn from_arg(raw: u64) -> Result<Self, Errno> {
+        VirtAddr::try_new(raw).map_err(|_| Errno::EFAULT)
+    }
+}
+
+/// Enables `syscall` on the calling processor, which must have loaded its
+/// GDT (see `gdt::init`).
+pub fn init() {
+    let selectors = gdt::selectors();
+    Star::write(
+        selectors.user_code,
+        selectors.user_data,
+        selectors.kernel_code,
+        selectors.kernel_data,
+    )
+    .expect("GDT segments do not fit sysret");
+    LStar::write(VirtAddr::new(syscall_entry as usize as u64));
+    // the stub must not be interrupted before it is on the kernel stack
+    SFMask::write(
+        RFlags::INTERRUPT_FLAG
+            | RFlags::TRAP_FLAG
+            | RFlags::DIRECTION_FLAG
+            | RFlags::ALIGNMENT_CHECK,
+    );
+    unsafe { Efer::update(|flags| flags.insert(EferFlags::SYSTEM_CALL_EXTENSIONS)) };
+}
+
+/// Called by the entry stub on the kernel stack with interrupts disabled.
+#[no_mangle]
+extern "C" fn syscall_dispatch(frame: &SyscallFrame) -> u64 {
+    x86_64::instructions::interrupts::enable();
+    let result = match usize::try_from(frame.number)
+        .ok()
+        .and_then(|number| TABLE.get(number))
+    {
+        Some(syscall) => (syscall.handler)(&frame.args),
+        None => Err(Errno::ENOSYS),
+    };
+    match result {
+        Ok(value) => value,
+        Err(errno) => errno.as_return_value(),
+    }
+}
+
+/// Returns the name of the system call with the given number.
+pub fn name(number: u64) -> Option<&'static str> {
+    let syscall = TABLE.get(usize::try_from(number).ok()?)?;
+    Some(syscall.name)
+}
+
+/// `exit(code: i32) -> !`: terminates the calling process with `code`, which
+/// its parent gets from `wait`. Threads outside of processes just exit.
+fn sys_exit(args: &SyscallArgs) -> SyscallResult {
+    // like on Linux, only the low 32 bits count
+    let code = args.get::<u64>(0)? as i32;
+    if process::current().is_some() {
+        process::exit(code);
+    }
+    thread::exit();
+}
+
+/// `write(handle: u32, buffer: *const u8, len: usize) -> usize`: writes
+/// `len` bytes to the console or a pipe and returns how many were written.
+/// Bytes that are not valid UTF-8 are printed as U+FFFD. Writes to a full
+/// pipe block until it has room.
+///
+/// Fails with `EFAULT` if the buffer is not mapped and with `EPIPE` if the
+/// read ends of the pipe are closed. Like on Linux, the bytes before an
+/// unmapped page or the closing of the read ends are written anyway, and
+/// their count is returned.
+fn sys_write(args: &SyscallArgs) -> SyscallResult {
+    let handle: u32 = args.get(0)?;
+    let start: VirtAddr = args.get(1)?;
+    let len: usize = args.get(2)?;
+    let object = process::with_handles(|handles| handles.get(handle).cloned());
+    let writer = match object {
+        Some(Object::Console) => None,
+        Some(Object::PipeWriter(writer)) => Some(writer),
+        _ => return Err(Errno::EBADF),
+    };
+    if !usermode::is_user_range(start, len) {
+        return Err(Errno::EFAULT);
+    }
+    match writer {
+        None => write_console(start, len),
+        Some(writer) => write_pipe(&writer, start, len),
+    }
+}
+
+fn write_console(start: VirtAddr, len: usize) -> SyscallResult {
+    let mut buffer = [0; WRITE_CHUNK_SIZE];
+    // the bytes of a character that was cut off at the end of the last chunk
+    let mut pending = 0;
+    let mut written = 0;
+    while written < len {
+        let address = start + written;
+        // chunks do not cross pages, so that all bytes before an unmapped
+        // page are written
+        let page_left = 4096 - (address.as_u64() % 4096) as usize;
+        let chunk = (len - written)
+            .min(WRITE_CHUNK_SIZE - pending)
+            .min(page_left);
+        let end = pending + chunk;
+        if let Err(errno) = usermode::copy_from_user(&mut buffer[pending..end], address) {
+            return partial(written, errno);
+        }
+        written += chunk;
+        pending = print_lossy(&buffer[..end], written == len);
+        buffer.copy_within(end - pending..end, 0);
+    }
+    Ok(len as u64)
+}
+
+fn write_pipe(writer: &PipeWriter, start: VirtAddr, len: usize) -> SyscallResult {
+    let mut buffer = [0; WRITE_CHUNK_SIZE];
+    let mut written = 0;
+    while written < len {
+        let address = start + written;
+        let page_left = 4096 - (address.as_u64() % 4096) as usize;
+        let chunk = (len - written).min(WRITE_CHUNK_SIZE).min(page_left);
+        if let Err(errno) = usermode::copy_from_user(&mut buffer[..chunk], address) {
+            return partial(written, errno);
+        }
+        match writer.write(&buffer[..chunk]) {
+            Ok(n) if n == chunk => written += n,
+            Ok(n) => return Ok((written + n) as u64),
+            Err(ipc::BrokenPipe) => return partial(written, Errno::EPIPE),
+        }
+    }
+    Ok(len as u64)
+}
+
+/// Returns the count of the bytes written before `errno` happened, or the
+/// error if there are none.
+fn partial(written: usize, errno: Errno) -> SyscallResult {
+    if written > 0 {
+        Ok(written as u64)
+    } else {
+        Err(errno)
+    }
+}
+
+/// How many bytes `write` copies from user memory at a time.
+const WRITE_CHUNK_SIZE: usize = 256;
+
+/// Prints `bytes` to the console and the serial port with invalid UTF-8 as
+/// U+FFFD. Unless `last` is set, a character cut off at the end is not
+/// printed; returns its length.
+fn print_lossy(mut bytes: &[u8], last: bool) -> usize {
+    loop {
+        let err = match str::from_utf8(bytes) {
+            Ok(valid) => {
+                print!("{}", valid);
+                serial_print!("{}", valid);
+                return 0;
+            }
+            Err(err) => err,
+        };
+        let (valid, rest) = bytes.split_at(err.valid_up_to());
+        let valid = unsafe { str::from_utf8_unchecked(valid) };
+        print!("{}", valid);
+        serial_print!("{}", valid);
+        if err.error_len().is_none() && !last {
+            return rest.len();
+        }
+        print!("{}", char::REPLACEMENT_CHARACTER);
+        serial_print!("{}", char::REPLACEMENT_CHARACTER);
+        bytes = &rest[err.error_len().unwrap_or(rest.len())..];
+    }
+}
+
+/// `getpid() -> u64`: returns the PID of the calling process, or fails with
+/// `ESRCH` outside of processes.
+fn sys_getpid(_args: &SyscallArgs) -> SyscallResult {
+    let pid = process::current().ok_or(Errno::ESRCH)?;
+    Ok(pid.as_u64())
+}
+
+/// `yield() -> 0`: lets other ready threads run first.
+fn sys_yield(_args: &SyscallArgs) -> SyscallResult {
+    thread::yield_now();
+    Ok(0)
+}
+
+/// `sleep(nanoseconds: u64) -> 0`: blocks the calling thread for at least
+/// the given time.
+fn sys_sleep(args: &SyscallArgs) -> SyscallResult {
+    let nanos: u64 = args.get(0)?;
+    thread::sleep(Duration::from_nanos(nanos));
+    Ok(0)
+}
+
+/// `wait(pid: u64, status: *mut i32) -> u64`: blocks until the child `pid`
+/// (or any child for `ANY_CHILD`) exits and returns its PID. Stores the exit
+/// code at `status` unless it is null. Fails with `ECHILD` if there is no
+/// such child.
+fn sys_wait(args: &SyscallArgs) -> SyscallResult {
+    let pid = match args.get::<u64>(0)? {
+        ANY_CHILD => None,
+        pid => Some(Pid::from_u64(pid)),
+    };
+    let status: VirtAddr = args.get(1)?;
+    let (child, code) = process::wait(pid).ok_or(Errno::ECHILD)?;
+    // the child is gone either way, like on Linux
+    if !status.is_null() {
+        usermode::copy_to_user(status, &code.to_ne_bytes())?;
+    }
+    Ok(child.as_u64())
+}
+
+/// `sbrk(increment: usize) -> u64`: grows the heap of the calling process by
+/// `increment` bytes, rounded up to whole pages, and returns the old end of
+/// the heap, where the new memory starts (see `process::grow_heap`). Fails
+/// with `ENOMEM` if no memory is left and with `ESRCH` outside of processes.
+fn sys_sbrk(args: &SyscallArgs) -> SyscallResult {
+    let increment: usize = args.get(0)?;
+    process::current().ok_or(Errno::ESRCH)?;
+    let start = process::grow_heap(increment).ok_or(Errno::ENOMEM)?;
+    Ok(start.as_u64())
+}
+
+/// `read(handle: u32, buffer: *mut u8, len: usize) -> usize`: reads up to
+/// `len` bytes from a pipe, blocking while it is empty, and returns how many
+/// were read, 0 once all write ends are closed.
+///
+/// Fails with `EBADF` unless the handle is the read end of a pipe, and with
+/// `EFAULT` if the buffer is not mapped writable, in which case the bytes
+/// are lost.
+fn sys_read(args: &SyscallArgs) -> SyscallResult {
+    let handle: u32 = args.get(0)?;
+    let start: VirtAddr = args.get(1)?;
+    let len: usize = args.get(2)?;
+    let reader = match process::with_handles(|handles| handles.get(handle).cloned()) {
+        Some(Object::PipeReader(reader)) => reader,
+        _ => return Err(Errno::EBADF),
+    };
+    if !usermode::is_user_range(start, len) {
+        return Err(Errno::EFAULT);
+    }
+    let mut buffer = vec![0; len.min(ipc::PIPE_CAPACITY)];
+    let read = reader.read(&mut buffer);
+    usermode::copy_to_user(start, &buffer[..read])?;
+    Ok(read as u64)
+}
+
+/// `close(handle: u32) -> 0`: closes the handle. Closing the last handle of
+/// a pipe end or channel endpoint closes it.
+fn sys_close(args: &SyscallArgs) -> SyscallResult {
+    let handle: u32 = args.get(0)?;
+    let object = process::with_handles(|handles| handles.remove(handle));
+    // dropped outside of the handle table, since closing may wake up threads
+    // that use it
+    drop(object.ok_or(Errno::EBADF)?);
+    Ok(0)
+}
+
+/// `pipe(handles: *mut [u32; 2]) -> 0`: creates a pipe and stores the
+/// handles of its read and its write end (see `ipc::pipe`).
+///
+/// Fails with `EMFILE` if the process has no two free handles and with
+/// `ENOMEM` if no memory is left.
+fn sys_pipe(args: &SyscallArgs) -> SyscallResult {
+    let (reader, writer) = ipc::pipe().ok_or(Errno::ENOMEM)?;
+    install_pair(
+        args.get(0)?,
+        Object::PipeReader(reader),
+        Object::PipeWriter(writer),
+    )
+}
+
+/// `channel(handles: *mut [u32; 2]) -> 0`: creates a channel and stores the
+/// handles of its two endpoints (see `ipc::channel`).
+///
+/// Fails with `EMFILE` if the process has no two free handles.
+fn sys_channel(args: &SyscallArgs) -> SyscallResult {
+    let (first, second) = ipc::channel();
+    install_pair(
+        args.get(0)?,
+        Object::Channel(first),
+        Object::Channel(second),
+    )
+}
+
+/// Adds two objects to the handle table and stores their handles at `dst`.
+fn install_pair(dst: VirtAddr, first: Object, second: Object) -> SyscallResult {
+    if !usermode::is_user_range(dst, 8) {
+        return Err(Errno::EFAULT);
+    }
+    let handles = process::with_handles(|handles| handles.insert_all(vec![first, second]))
+        .map_err(|_| Errno::EMFILE)?;
+    let mut bytes = [0; 8];
+    bytes[..4].copy_from_slice(&handles[0].to_ne_bytes());
+    bytes[4..].copy_from_slice(&handles[1].to_ne_bytes());
+    if let Err(errno) = usermode::copy_to_user(dst, &bytes) {
+        close_all(&handles);
+        return Err(errno);
+    }
+    Ok(0)
+}
+
+/// Closes the given handles, which must be open.
+fn close_all(handles: &[Handle]) {
+    let objects: Vec<_> = process::with_handles(|table| {
+        handles
+            .iter()
+            .filter_map(|&handle| table.remove(handle))
+            .collect()
+    });
+    drop(objects);
+}
+
+/// Reads `count` handles from user memory at `src`.
+fn copy_handles_from_user(src: VirtAddr, count: usize) -> Result<Vec<Handle>, Errno> {
+    // `src` may be null then
+    if count == 0 {
+        return Ok(Vec::new());
+    }
+    let mut bytes = [0; 4 * ipc::MAX_MESSAGE_HANDLES];
+    let bytes = &mut bytes[..4 * count];
+    usermode::copy_from_user(bytes, src)?;
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|chunk| Handle::from_ne_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect())
+}
+
+/// `send(handle: u32, data: *const u8, len: usize, handles: *const u32,
+/// count: usize) -> 0`: sends a message with `len` bytes and the objects of
+/// `count` handles over a channel, blocking while the queue of the other
+/// endpoint is full. The handles move with the message: they are closed in
+/// the sender once it is sent.
+///
+/// Fails with `EMSGSIZE` if the message exceeds `ipc::MAX_MESSAGE_SIZE` or
+/// `ipc::MAX_MESSAGE_HANDLES`, with `EPIPE` if the other endpoint is closed,
+/// with `EBADF` if a handle is not open or `handle` is no channel, and with
+/// `EINVAL` if a handle is listed twice or refers to an endpoint of the
+/// channel itself.
+fn sys_send(args: &SyscallArgs) -> SyscallResult {
+    let handle: u32 = args.get(0)?;
+    let data: VirtAddr = args.get(1)?;
+    let len: usize = args.get(2)?;
+    let handles: VirtAddr = args.get(3)?;
+    let count: usize = args.get(4)?;
+    let channel = match process::with_handles(|table| table.get(handle).cloned()) {
+        Some(Object::Channel(channel)) => channel,
+        _ => return Err(Errno::EBADF),
+    };
+    if len > ipc::MAX_MESSAGE_SIZE || count > ipc::MAX_MESSAGE_HANDLES {
+        return Err(Errno::EMSGSIZE);
+    }
+    let mut bytes = vec![0; len];
+    // `data` may be null for empty messages
+    if len > 0 {
+        usermode::copy_from_user(&mut bytes, data)?;
+    }
+    let handles = copy_handles_from_user(handles, count)?;
+    for (index, &moved) in handles.iter().enumerate() {
+        if moved == handle || handles[..index].contains(&moved) {
+            return Err(Errno::EINVAL);
+        }
+    }
+    let objects = process::with_handles(|table| {
+        handles
+            .iter()
+            .map(|&handle| table.get(handle).cloned())
+            .collect::<Option<Vec<_>>>()
+    })
+    .ok_or(Errno::EBADF)?;
+    // a queued endpoint would keep its own channel alive forever
+    let sends_itself = objects.iter().any(|object| match object {
+        Object::Channel(moved) => moved.same_channel(&channel),
+        _ => false,
+    });
+    if sends_itself {
+        return Err(Errno::EINVAL);
+    }
+
+    channel
+        .send(Message::new(bytes, objects))
+        .map_err(|err| match err.error {
+            ChannelError::PeerClosed => Errno::EPIPE,
+            ChannelError::TooLarge => Errno::EMSGSIZE,
+        })?;
+    close_all(&handles);
+    Ok(0)
+}
+
+/// `recv(handle: u32, buffer: *mut u8, len: usize, handles: *mut u32,
+/// count: *mut usize) -> usize`: receives the oldest message of a channel,
+/// blocking while there is none, and returns its length. The objects of the
+/// message get new handles, which are stored at `handles`; `count` holds how
+/// many fit there when called and how many were stored on return. A null
+/// `count` receives no handles.
+///
+/// Fails with `EMSGSIZE` if the message does not fit, which leaves it
+/// queued, with `EPIPE` if no message is left and the other endpoint is
+/// closed, and with `EBADF` unless `handle` is a channel. If the message does
+/// not fit into user memory after all, it is lost and `EFAULT` returned; if
+/// the process has too few free handles for its objects, it is lost and
+/// `EMFILE` returned.
+fn sys_recv(args: &SyscallArgs) -> SyscallResult {
+    let handle: u32 = args.get(0)?;
+    let buffer: VirtAddr = args.get(1)?;
+    let len: usize = args.get(2)?;
+    let handles: VirtAddr = args.get(3)?;
+    let count: VirtAddr = args.get(4)?;
+    let channel = match process::with_handles(|table| table.get(handle).cloned()) {
+        Some(Object::Channel(channel)) => channel,
+        _ => return Err(Errno::EBADF),
+    };
+    let max_handles = if count.is_null() {
+        0
+    } else {
+        let mut bytes = [0; 8];
+        usermode::copy_from_user(&mut bytes, count)?;
+        u64::from_ne_bytes(bytes) as usize
+    };
+    let max_handles = max_handles.min(ipc::MAX_MESSAGE_HANDLES);
+    if (len > 0 && !usermode::is_user_range(buffer, len))
+        || (max_handles > 0 && !usermode::is_user_range(handles, max_handles * 4))
+    {
+        return Err(Errno::EFAULT);
+    }
+
+    let Message { data, objects } =
+        channel
+            .recv_within(len, max_handles)
+            .map_err(|err| match err {
+                ChannelError::PeerClosed => Errno::EPIPE,
+                ChannelError::TooLarge => Errno::EMSGSIZE,
+            })?;
+    if !data.is_empty() {
+        usermode::copy_to_user(buffer, &data)?;
+    }
+    if count.is_null() {
+        return Ok(data.len() as u64);
+    }
+    let received =
+        process::with_handles(|table| table.insert_all(objects)).map_err(|_| Errno::EMFILE)?;
+    let bytes: Vec<u8> = received
+        .iter()
+        .flat_map(|handle| handle.to_ne_bytes())
+        .collect();
+    let stored = if bytes.is_empty() {
+        Ok(())
+    } else {
+        usermode::copy_to_user(handles, &bytes)
+    };
+    let stored =
+        stored.and_then(|()| usermode::copy_to_user(count, &(received.len() as u64).to_ne_bytes()));
+    if let Err(errno) = stored {
+        close_all(&received);
+        return Err(errno);
+    }
+    Ok(data.len() as u64)
+}
+
+/// `shm_create(size: usize) -> u32`: creates a zeroed shared memory object
+/// of `size` bytes, rounded up to whole pages, and returns a handle to it.
+/// Its frames are freed once all handles are closed and all mappings are
+/// gone.
+///
+/// Fails with `EINVAL` if `size` is 0, with `ENOMEM` if no memory is left
+/// and with `EMFILE` if the process has no free handle.
+fn sys_shm_create(args: &SyscallArgs) -> SyscallResult {
+    let size: usize = args.get(0)?;
+    if size == 0 {
+        return Err(Errno::EINVAL);
+    }
+    let memory = SharedMemory::new(size).ok_or(Errno::ENOMEM)?;
+    let handle = process::with_handles(|handles| handles.insert(Object::SharedMemory(memory)))
+        .map_err(|_| Errno::EMFILE)?;
+    Ok(u64::from(handle))
+}
+
+/// `shm_map(handle: u32, flags: u64) -> u64`: maps the shared memory object
+/// into the calling process and returns its address. The memory is readable,
+/// and writable and executable with `SHM_WRITE` and `SHM_EXEC`. The mapping
+/// stays when the handle is closed.
+///
+/// Fails with `EBADF` unless the handle is a shared memory object, with
+/// `EINVAL` for unknown flags, with `ENOMEM` if the process has no room for
+/// it and with `ESRCH` outside of processes.
+fn sys_shm_map(args: &SyscallArgs) -> SyscallResult {
+    let handle: u32 = args.get(0)?;
+    let flags: u64 = args.get(1)?;
+    process::current().ok_or(Errno::ESRCH)?;
+    let memory = match process::with_handles(|handles| handles.get(handle).cloned()) {
+        Some(Object::SharedMemory(memory)) => memory,
+        _ => return Err(Errno::EBADF),
+    };
+    if flags & !(SHM_WRITE | SHM_EXEC) != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let mut page_flags = PageTableFlags::empty();
+    if flags & SHM_WRITE != 0 {
+        page_flags |= PageTableFlags::WRITABLE;
+    }
+    if flags & SHM_EXEC == 0 {
+        page_flags |= PageTableFlags::NO_EXECUTE;
+    }
+    let address = process::map_shared(memory, page_flags).ok_or(Errno::ENOMEM)?;
+    Ok(address.as_u64())
+}
+
+/// `shm_unmap(address: u64) -> 0`: unmaps the shared memory that `shm_map`
+/// mapped at `address`. Fails with `EINVAL` if there is none and with
+/// `ESRCH` outside of processes.
+fn sys_shm_unmap(args: &SyscallArgs) -> SyscallResult {
+    let address: VirtAddr = args.get(0)?;
+    process::current().ok_or(Errno::ESRCH)?;
+    if !process::unmap_shared(address) {
+        return Err(Errno::EINVAL);
+    }
+    Ok(0)
+}
+
+/// How long the name of a program passed to `spawn` may be, without the
+/// null byte.
+const MAX_PROGRAM_NAME: usize = 63;
+
+/// `spawn(name: *const u8) -> u64`: starts the executable registered under
+/// the null-terminated `name` (see `process::register_program`) as a child
+/// of the calling process, with the name as its only argument, and returns
+/// its PID.
+///
+/// Fails with `ENOENT` if there is no such executable, with `ENOEXEC` if it
+/// cannot be loaded and with `ENOMEM` if no memory is left.
+fn sys_spawn(args: &SyscallArgs) -> SyscallResult {
+    let start: VirtAddr = args.get(0)?;
+    let mut buffer = [0; MAX_PROGRAM_NAME + 1];
+    let len = usermode::strncpy_from_user(&mut buffer, start)?;
+    if len > MAX_PROGRAM_NAME {
+        return Err(Errno::ENOENT);
+    }
+    let name = str::from_utf8(&buffer[..len]).map_err(|_| Errno::ENOENT)?;
+    let data = process::program(name).ok_or(Errno::ENOENT)?;
+    let pid = process::spawn(data, &[name], &[]).map_err(|err| match err {
+        ElfError::OutOfMemory => Errno::ENOMEM,
+        _ => Errno::ENOEXEC,
+    })?;
+    Ok(pid.as_u64())
+}
+
+#[test_case]
+fn test_table_is_indexed_by_number() {
+    for (index, syscall) in TABLE.iter().enumerate() {
+        assert_eq!(syscall.number, index as u64, "{}", syscall.name);
+    }
+}
+
+#[test_case]
+fn test_errno_round_trip() {
+    let value = Errno::EFAULT.as_return_value();
+    assert_eq!(value as i64, -14);
+    assert_eq!(Errno::from_return_value(value), Some(Errno::EFAULT));
+    assert_eq!(Errno::from_return_value(42), None);
+}
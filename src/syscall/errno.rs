@@ -0,0 +1,93 @@
+use core::fmt;
+
+/// The error numbers returned by system calls, with the values Linux uses.
+///
+/// A failed system call returns the negated error number in `rax`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i64)]
+pub enum Errno {
+    /// The operation is not permitted.
+    EPERM = 1,
+    /// No such file or object.
+    ENOENT = 2,
+    /// No such process.
+    ESRCH = 3,
+    /// The call was interrupted.
+    EINTR = 4,
+    EIO = 5,
+    /// The file is not a valid executable.
+    ENOEXEC = 8,
+    /// The handle is not open or does not allow the operation.
+    EBADF = 9,
+    /// The process has no such child.
+    ECHILD = 10,
+    /// The operation would block.
+    EAGAIN = 11,
+    ENOMEM = 12,
+    /// An address argument points outside of the accessible user memory.
+    EFAULT = 14,
+    EINVAL = 22,
+    /// The process has the maximum number of handles open.
+    EMFILE = 24,
+    /// The other end of a pipe or channel is closed.
+    EPIPE = 32,
+    /// There is no system call with the requested number.
+    ENOSYS = 38,
+    /// A message exceeds the limits of the channel or the receive buffer.
+    EMSGSIZE = 90,
+}
+
+impl Errno {
+    /// Returns the value a failed system call returns in `rax`.
+    pub fn as_return_value(self) -> u64 {
+        (-(self as i64)) as u64
+    }
+
+    /// Decodes the return value of a system call, which is an error if it
+    /// is in the range of negated error numbers.
+    pub fn from_return_value(value: u64) -> Option<Self> {
+        let errno = match -(value as i64) {
+            1 => Errno::EPERM,
+            2 => Errno::ENOENT,
+            3 => Errno::ESRCH,
+            4 => Errno::EINTR,
+            5 => Errno::EIO,
+            8 => Errno::ENOEXEC,
+            9 => Errno::EBADF,
+            10 => Errno::ECHILD,
+            11 => Errno::EAGAIN,
+            12 => Errno::ENOMEM,
+            14 => Errno::EFAULT,
+            22 => Errno::EINVAL,
+            24 => Errno::EMFILE,
+            32 => Errno::EPIPE,
+            38 => Errno::ENOSYS,
+            90 => Errno::EMSGSIZE,
+            _ => return None,
+        };
+        Some(errno)
+    }
+}
+
+impl fmt::Display for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            Errno::EPERM => "operation not permitted",
+            Errno::ENOENT => "no such object",
+            Errno::ESRCH => "no such process",
+            Errno::EINTR => "interrupted",
+            Errno::EIO => "I/O error",
+            Errno::ENOEXEC => "not an executable",
+            Errno::EBADF => "bad handle",
+            Errno::ECHILD => "no child process",
+            Errno::EAGAIN => "try again",
+            Errno::ENOMEM => "out of memory",
+            Errno::EFAULT => "bad address",
+            Errno::EINVAL => "invalid argument",
+            Errno::EMFILE => "too many open handles",
+            Errno::EPIPE => "broken pipe",
+            Errno::ENOSYS => "no such system call",
+            Errno::EMSGSIZE => "message too long",
+        })
+    }
+}
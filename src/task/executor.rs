@@ -0,0 +1,203 @@
+use super::{Task, TaskId};
+use crate::{serial_println, workqueue};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use core::future::Future;
+use core::task::{Context, Poll, Waker};
+use crossbeam_queue::ArrayQueue;
+use spin::Mutex;
+
+/// The maximum number of tasks that can be woken up between two polls.
+const TASK_QUEUE_SIZE: usize = 100;
+
+pub struct Executor {
+    tasks: BTreeMap<TaskId, Task>,
+    task_queue: Arc<ArrayQueue<TaskId>>,
+    waker_cache: BTreeMap<TaskId, Waker>,
+    spawned: Arc<Mutex<VecDeque<Task>>>,
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Executor {
+            tasks: BTreeMap::new(),
+            task_queue: Arc::new(ArrayQueue::new(TASK_QUEUE_SIZE)),
+            waker_cache: BTreeMap::new(),
+            spawned: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    pub fn spawn(&mut self, task: Task) {
+        let task_id = task.id;
+        if self.tasks.insert(task.id, task).is_some() {
+            panic!("task with same ID already in tasks");
+        }
+        self.task_queue.push(task_id).expect("task queue full");
+    }
+
+    /// Returns a handle through which running tasks can spawn new tasks.
+    pub fn spawner(&self) -> Spawner {
+        Spawner {
+            spawned: self.spawned.clone(),
+        }
+    }
+
+    /// Runs the tasks forever, halting the CPU whenever none is ready.
+    pub fn run(&mut self) -> ! {
+        loop {
+            self.run_once();
+            self.sleep_if_idle();
+        }
+    }
+
+    /// Runs the tasks until all of them have completed.
+    pub fn run_until_complete(&mut self) {
+        loop {
+            self.run_once();
+            if self.tasks.is_empty() && self.spawned.lock().is_empty() {
+                break;
+            }
+            self.sleep_if_idle();
+        }
+    }
+
+    /// Runs `future` together with the spawned tasks and returns its output
+    /// as soon as it completes.
+    pub fn block_on<F>(&mut self, future: F) -> F::Output
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let output = Arc::new(Mutex::new(None));
+        let task_output = output.clone();
+        self.spawn(Task::new(async move {
+            let value = future.await;
+            *task_output.lock() = Some(value);
+        }));
+
+        loop {
+            self.run_once();
+            if let Some(value) = output.lock().take() {
+                return value;
+            }
+            self.sleep_if_idle();
+        }
+    }
+
+    fn run_once(&mut self) {
+        self.spawn_pending();
+        self.run_ready_tasks();
+    }
+
+    fn spawn_pending(&mut self) {
+        loop {
+            // don't hold the lock while spawning, which may panic
+            let task = self.spawned.lock().pop_front();
+            match task {
+                Some(task) => self.spawn(task),
+                None => break,
+            }
+        }
+    }
+
+    fn run_ready_tasks(&mut self) {
+        // destructure `self` to avoid borrow checker errors
+        let Self {
+            tasks,
+            task_queue,
+            waker_cache,
+            spawned: _,
+        } = self;
+
+        while let Some(task_id) = task_queue.pop() {
+            let task = match tasks.get_mut(&task_id) {
+                Some(task) => task,
+                None => continue, // task no longer exists
+            };
+            let waker = waker_cache
+                .entry(task_id)
+                .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
+            let mut context = Context::from_waker(waker);
+            match task.poll(&mut context) {
+                Poll::Ready(()) => {
+                    // task done -> remove it and its cached waker
+                    tasks.remove(&task_id);
+                    waker_cache.remove(&task_id);
+                }
+                Poll::Pending => {}
+            }
+        }
+    }
+
+    /// Halts the CPU until the next interrupt if no task is ready to run.
+    fn sleep_if_idle(&self) {
+        use x86_64::instructions::interrupts::{self, enable_and_hlt};
+
+        // an interrupt between the check and `hlt` could wake a task without
+        // waking us up, so the check is done with interrupts disabled
+        interrupts::disable();
+        if self.task_queue.is_empty() && self.spawned.lock().is_empty() {
+            enable_and_hlt();
+        } else {
+            interrupts::enable();
+        }
+    }
+}
+
+/// Spawns tasks onto an `Executor` from within running tasks.
+#[derive(Clone)]
+pub struct Spawner {
+    spawned: Arc<Mutex<VecDeque<Task>>>,
+}
+
+impl Spawner {
+    /// Queues the task; the executor starts polling it on its next iteration.
+    pub fn spawn(&self, task: Task) {
+        use x86_64::instructions::interrupts::without_interrupts;
+
+        // `sleep_if_idle` takes the lock with interrupts disabled, so it must
+        // never be held by a preempted thread
+        without_interrupts(|| self.spawned.lock().push_back(task));
+    }
+}
+
+struct TaskWaker {
+    task_id: TaskId,
+    task_queue: Arc<ArrayQueue<TaskId>>,
+}
+
+impl TaskWaker {
+    fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
+        Waker::from(Arc::new(TaskWaker {
+            task_id,
+            task_queue,
+        }))
+    }
+
+    /// Queues the task for polling. Wakers are called from interrupt
+    /// handlers, so a full queue drops the wake-up and logs it instead of
+    /// panicking.
+    fn wake_task(&self) {
+        if self.task_queue.push(self.task_id).is_err() {
+            workqueue::schedule_or_run(warn_queue_full, self.task_id.0);
+        }
+    }
+}
+
+fn warn_queue_full(task_id: u64) {
+    serial_println!(
+        "WARNING: task queue full; dropping wake-up of task {}",
+        task_id
+    );
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_task();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wake_task();
+    }
+}
@@ -0,0 +1,104 @@
+use crate::time::{self, Instant};
+use crate::timer::{self, TimerHandle};
+use alloc::sync::Arc;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use core::time::Duration;
+use futures_util::task::AtomicWaker;
+
+/// A future that completes once the given deadline has passed.
+///
+/// The deadline is tracked by a kernel timer (see `timer`), so the
+/// resolution is limited by the timer interrupt frequency and `timer::init`
+/// must have been called.
+pub struct Sleep {
+    /// The tick at which the future completes.
+    deadline: u64,
+    timer: Option<(TimerHandle, Arc<AtomicWaker>)>,
+}
+
+impl Sleep {
+    fn new(delay: Duration) -> Self {
+        Sleep {
+            // the current tick has partly elapsed already, so wait for one more
+            deadline: time::ticks() + time::duration_to_ticks(delay) + 1,
+            timer: None,
+        }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let now = time::ticks();
+        if now >= self.deadline {
+            return Poll::Ready(());
+        }
+        match &self.timer {
+            Some((_, waker)) => waker.register(cx.waker()),
+            None => {
+                let waker = Arc::new(AtomicWaker::new());
+                waker.register(cx.waker());
+                let timer_waker = waker.clone();
+                let delay = time::ticks_to_duration(self.deadline - now - 1);
+                let handle = timer::add_oneshot(delay, move || timer_waker.wake());
+                self.timer = Some((handle, waker));
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some((handle, _)) = &self.timer {
+            handle.cancel();
+        }
+    }
+}
+
+/// Completes after the given duration has elapsed.
+pub fn sleep(duration: Duration) -> Sleep {
+    Sleep::new(duration)
+}
+
+/// Completes once `deadline` has passed.
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep::new(deadline.duration_since(Instant::now()))
+}
+
+/// The error returned by `Timeout` when the wrapped future did not complete
+/// in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+/// A future that runs the wrapped future until it completes or the time
+/// limit passes, whichever happens first.
+pub struct Timeout<F> {
+    future: F,
+    sleep: Sleep,
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        // `future` is structurally pinned, `sleep` is `Unpin`
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        if let Poll::Ready(output) = future.poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        Pin::new(&mut this.sleep).poll(cx).map(|()| Err(Elapsed))
+    }
+}
+
+/// Runs `future`, giving up with `Elapsed` after `duration`.
+pub fn timeout<F: Future>(duration: Duration, future: F) -> Timeout<F> {
+    Timeout {
+        future,
+        sleep: sleep(duration),
+    }
+}
@@ -0,0 +1,48 @@
+//! Setup and helpers shared by the integration tests in `tests/`.
+
+use crate::memory::{self, BootInfoFrameAllocator};
+use crate::time::Instant;
+use crate::{allocator, thread, vga_buffer};
+use bootloader::BootInfo;
+use core::time::Duration;
+use x86_64::structures::paging::OffsetPageTable;
+use x86_64::VirtAddr;
+
+/// Sets up the screen, calls `crate::init` and initializes paging and the
+/// heap. Returns the mapper and the frame allocator for the rest of the
+/// setup, e.g. `memory::frames::init`.
+pub fn init(
+    boot_info: &'static mut BootInfo,
+) -> (OffsetPageTable<'static>, BootInfoFrameAllocator) {
+    if let Some(fb) = boot_info.framebuffer.as_mut() {
+        let fb_info = fb.info();
+
+        unsafe {
+            vga_buffer::init_global_writer(fb.buffer_mut(), fb_info);
+        }
+    }
+    crate::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset.into_option().unwrap());
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_regions) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    (mapper, frame_allocator)
+}
+
+/// Waits until `condition` holds and panics after a second. Yields to other
+/// threads once `thread::init` was called, and spins before.
+pub fn wait_for(condition: impl Fn() -> bool) {
+    let start = Instant::now();
+    while !condition() {
+        assert!(
+            start.elapsed() < Duration::from_secs(1),
+            "timed out waiting for the condition"
+        );
+        if thread::is_initialized() {
+            thread::yield_now();
+        } else {
+            core::hint::spin_loop();
+        }
+    }
+}
@@ -0,0 +1,377 @@
+mod context;
+mod scheduler;
+mod stack;
+pub mod stats;
+
+use crate::percpu::{self, this_cpu};
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
+use scheduler::Thread;
+use spin::Mutex;
+use stack::Stack;
+use x86_64::instructions::interrupts;
+use x86_64::structures::paging::PhysFrame;
+use x86_64::VirtAddr;
+
+/// The default stack size of spawned threads.
+///
+/// Stacks come from `allocator::STACK_ALLOCATOR`, below each is an unmapped
+/// guard page (see `stack`). A thread that runs out of stack ends in a double fault.
+pub const STACK_SIZE: usize = 16 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        ThreadId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadState {
+    /// Waiting in the run queue.
+    Ready,
+    Running,
+    /// Waiting for `unpark`, e.g. in `park` or `JoinHandle::join`.
+    Blocked,
+    /// Finished, but its stack is not freed yet.
+    Exited,
+}
+
+impl fmt::Display for ThreadState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            ThreadState::Ready => "ready",
+            ThreadState::Running => "run",
+            ThreadState::Blocked => "blocked",
+            ThreadState::Exited => "exited",
+        })
+    }
+}
+
+/// The scheduling priority of a thread.
+///
+/// The scheduler always runs a ready thread of the highest priority, so
+/// threads of lower priority only run while all higher ones are blocked.
+/// Threads of the same priority share the CPU round-robin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    Low,
+    Normal,
+    High,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 3] = [Priority::Low, Priority::Normal, Priority::High];
+    const COUNT: usize = Self::ALL.len();
+
+    /// Returns the default time slice in timer ticks. Higher priorities get
+    /// shorter slices, as they are meant for short interactive bursts.
+    pub fn time_slice(self) -> u32 {
+        match self {
+            Priority::Low => 8,
+            Priority::Normal => 4,
+            Priority::High => 2,
+        }
+    }
+}
+
+impl Default for Priority {
+    fn default() -> Self {
+        Priority::Normal
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+        })
+    }
+}
+
+/// Turns the calling code into the boot thread and starts preempting it
+/// from the timer interrupt.
+///
+/// The heap must be initialized.
+pub fn init() {
+    let boot = ThreadId::new();
+    let idle = Thread::new(
+        ThreadId::new(),
+        String::from("idle"),
+        Priority::Low,
+        Priority::Low.time_slice(),
+        Stack::new(STACK_SIZE),
+        Box::new(|| crate::hlt_loop()),
+        thread_start,
+    );
+    scheduler::init(boot, idle);
+}
+
+/// Configures the name, priority, time slice and stack size of a new thread.
+#[derive(Debug, Clone, Default)]
+pub struct Builder {
+    name: Option<String>,
+    priority: Priority,
+    time_slice: Option<u32>,
+    stack_size: Option<usize>,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Builder::default()
+    }
+
+    /// Names the thread for the `stats` table.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Overrides the default time slice of the priority, in timer ticks.
+    pub fn time_slice(mut self, ticks: u32) -> Self {
+        assert!(ticks > 0, "time slice must not be zero");
+        self.time_slice = Some(ticks);
+        self
+    }
+
+    /// Sets the stack size, which is rounded up to whole pages.
+    pub fn stack_size(mut self, size: usize) -> Self {
+        self.stack_size = Some(size);
+        self
+    }
+
+    /// Spawns a new kernel thread running `f`.
+    ///
+    /// The thread is appended to the run queue of its priority and starts
+    /// running on the next switch, e.g. when the calling thread calls
+    /// `yield_now` or is preempted.
+    pub fn spawn<F, T>(self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        reap();
+
+        let result = Arc::new(Mutex::new(None));
+        let thread_result = result.clone();
+        let entry = Box::new(move || {
+            let value = f();
+            *thread_result.lock() = Some(value);
+        });
+        let id = ThreadId::new();
+        let thread = Thread::new(
+            id,
+            self.name
+                .unwrap_or_else(|| alloc::format!("thread-{}", id.as_u64())),
+            self.priority,
+            self.time_slice
+                .unwrap_or_else(|| self.priority.time_slice()),
+            Stack::new(self.stack_size.unwrap_or(STACK_SIZE)),
+            entry,
+            thread_start,
+        );
+        scheduler::with(|scheduler| scheduler.add(thread));
+
+        JoinHandle { id, result }
+    }
+}
+
+/// Spawns a new kernel thread of normal priority running `f`.
+pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    Builder::new().spawn(f)
+}
+
+/// The first code every spawned thread runs, entered through `context::switch`
+/// with interrupts disabled.
+extern "C" fn thread_start() -> ! {
+    let entry = scheduler::with(|scheduler| scheduler.take_entry());
+    interrupts::enable();
+    entry();
+    exit();
+}
+
+/// Terminates the current thread and wakes up threads joining it.
+pub fn exit() -> ! {
+    interrupts::disable();
+    scheduler::with(|scheduler| scheduler.exit_current());
+    scheduler::schedule();
+    unreachable!("exited thread was scheduled again");
+}
+
+/// Returns the ID of the running thread.
+///
+/// Panics on a CPU that does not run threads.
+pub fn current() -> ThreadId {
+    let id = this_cpu!(current_thread).load(Ordering::Relaxed);
+    assert_ne!(id, percpu::NO_THREAD, "no thread is running on this CPU");
+    ThreadId(id)
+}
+
+/// Returns the ID of the running thread, or `None` before `percpu::init` and
+/// on a CPU that does not run threads.
+pub fn try_current() -> Option<ThreadId> {
+    if !percpu::is_initialized() {
+        return None;
+    }
+    let id = this_cpu!(current_thread).load(Ordering::Relaxed);
+    if id == percpu::NO_THREAD {
+        None
+    } else {
+        Some(ThreadId(id))
+    }
+}
+
+/// Returns the state of the given thread, or `None` if it has exited and
+/// was cleaned up.
+pub fn state(id: ThreadId) -> Option<ThreadState> {
+    scheduler::with(|scheduler| scheduler.state(id))
+}
+
+/// Returns the priority of the given thread, or `None` if it has exited
+/// and was cleaned up.
+pub fn priority(id: ThreadId) -> Option<Priority> {
+    scheduler::with(|scheduler| scheduler.priority(id))
+}
+
+/// Changes the priority of a thread and resets its time slice to the default
+/// of the new priority. Returns `false` if there is no such thread.
+pub fn set_priority(id: ThreadId, priority: Priority) -> bool {
+    scheduler::with(|scheduler| scheduler.set_priority(id, priority))
+}
+
+/// Moves the current thread to the end of the run queue and runs the next
+/// ready thread, if any.
+pub fn yield_now() {
+    reap();
+    interrupts::without_interrupts(scheduler::schedule);
+}
+
+/// Blocks the current thread until `unpark` is called for it.
+///
+/// If `unpark` was called since the last `park`, returns immediately. Like
+/// `std::thread::park`, this function may also return spuriously, so callers
+/// should check their wakeup condition in a loop.
+pub fn park() {
+    debug_assert!(
+        !crate::interrupts::in_interrupt(),
+        "park called from an interrupt handler"
+    );
+    interrupts::without_interrupts(|| {
+        if scheduler::with(|scheduler| scheduler.block_current()) {
+            scheduler::schedule();
+        }
+    });
+}
+
+/// Blocks the current thread for at least `duration`.
+///
+/// `timer::init` must have been called.
+pub fn sleep(duration: Duration) {
+    let id = current();
+    let expired = Arc::new(AtomicBool::new(false));
+    let _timer = crate::timer::add_oneshot(duration, {
+        let expired = expired.clone();
+        move || {
+            expired.store(true, Ordering::SeqCst);
+            unpark(id);
+        }
+    });
+    while !expired.load(Ordering::SeqCst) {
+        park();
+    }
+}
+
+/// Wakes up the given thread if it is blocked in `park`, or makes its next
+/// `park` return immediately otherwise.
+///
+/// Can be called from interrupt handlers.
+pub fn unpark(id: ThreadId) {
+    scheduler::with(|scheduler| scheduler.unpark(id));
+}
+
+/// Called by the timer interrupt handler after the EOI; switches threads once
+/// the time slice of the current one is used up.
+pub(crate) fn tick() {
+    scheduler::tick();
+}
+
+/// Sets the stack the current thread uses for interrupts in user mode (see
+/// `gdt::set_kernel_stack`), also after it was switched out.
+pub(crate) fn set_kernel_stack(stack_end: VirtAddr) {
+    if is_initialized() {
+        scheduler::with(|scheduler| scheduler.set_kernel_stack(stack_end));
+    }
+    crate::gdt::set_kernel_stack(stack_end);
+}
+
+/// Switches the current thread to the address space with the given level 4
+/// table, also after it was switched out.
+///
+/// This function is unsafe because the caller must guarantee that the table
+/// maps the kernel (see `memory::AddressSpace`) and outlives the thread.
+pub(crate) unsafe fn set_address_space(level_4_frame: PhysFrame) {
+    interrupts::without_interrupts(|| {
+        if is_initialized() {
+            scheduler::with(|scheduler| scheduler.set_address_space(level_4_frame));
+        }
+        scheduler::switch_address_space(level_4_frame);
+    });
+}
+
+/// Returns `true` once `init` has been called.
+pub fn is_initialized() -> bool {
+    scheduler::is_initialized()
+}
+
+/// Frees the stacks of exited threads.
+fn reap() {
+    // dropped after the scheduler lock is released
+    let _exited = scheduler::with(|scheduler| scheduler.take_exited());
+}
+
+/// An owned permission to wait for a thread and get its result.
+pub struct JoinHandle<T> {
+    id: ThreadId,
+    result: Arc<Mutex<Option<T>>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+
+    /// Blocks until the thread exited and returns the value of its closure.
+    pub fn join(self) -> T {
+        // registering fails once the thread has exited
+        while scheduler::with(|scheduler| scheduler.add_joiner(self.id)) {
+            park();
+        }
+        reap();
+        self.result
+            .lock()
+            .take()
+            .expect("thread exited without a result")
+    }
+}
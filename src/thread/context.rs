@@ -0,0 +1,53 @@
+use core::arch::global_asm;
+
+// Saves the callee-saved registers on the current stack, stores the stack
+// pointer to `*old_rsp` (rdi) and continues on the stack `new_rsp` (rsi),
+// which must have been saved by an earlier switch or set up by `init_stack`.
+//
+// All other registers are saved by the caller according to the System V ABI,
+// or by the interrupt handler if the switch happens in the timer interrupt.
+global_asm!(
+    ".global thread_switch_context",
+    "thread_switch_context:",
+    "push rbp",
+    "push rbx",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "mov [rdi], rsp",
+    "mov rsp, rsi",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop rbx",
+    "pop rbp",
+    "ret",
+);
+
+extern "C" {
+    fn thread_switch_context(old_rsp: *mut u64, new_rsp: u64);
+}
+
+/// Switches to the thread whose stack pointer is `new_rsp`, saving the
+/// current stack pointer to `old_rsp`. Returns once a later switch continues
+/// on the saved stack.
+///
+/// This function is unsafe because `new_rsp` must point to a saved context,
+/// and `old_rsp` must stay valid until the current thread is resumed.
+pub(super) unsafe fn switch(old_rsp: *mut u64, new_rsp: u64) {
+    thread_switch_context(old_rsp, new_rsp);
+}
+
+/// Prepares `stack` so that switching to the returned stack pointer starts
+/// executing `entry`.
+pub(super) fn init_stack(stack: &mut [u8], entry: extern "C" fn() -> !) -> u64 {
+    let top = (stack.as_mut_ptr() as u64 + stack.len() as u64) & !0xF;
+    // r15, r14, r13, r12, rbx and rbp, then the return address of the switch
+    // and a fake return address of `entry`, which keeps its stack aligned
+    let frame: [u64; 8] = [0, 0, 0, 0, 0, 0, entry as u64, 0];
+    let rsp = top - core::mem::size_of_val(&frame) as u64;
+    unsafe { (rsp as *mut [u64; 8]).write(frame) };
+    rsp
+}
@@ -0,0 +1,456 @@
+use super::stats::ThreadInfo;
+use super::{context, Priority, Stack, ThreadId, ThreadState};
+use crate::percpu::this_cpu;
+use crate::time::Instant;
+use crate::{gdt, memory};
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::mem;
+use core::sync::atomic::Ordering;
+use core::time::Duration;
+use spin::{Mutex, Once};
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::PhysFrame;
+use x86_64::VirtAddr;
+
+/// The scheduler state. It is only locked with interrupts disabled, so the
+/// timer interrupt never finds it locked on a single CPU.
+static SCHEDULER: Once<Mutex<Scheduler>> = Once::new();
+
+pub(super) struct Thread {
+    pub(super) id: ThreadId,
+    name: String,
+    priority: Priority,
+    /// The number of timer ticks the thread may run before it is preempted.
+    time_slice: u32,
+    state: ThreadState,
+    /// The saved stack pointer while the thread is not running.
+    rsp: u64,
+    /// Owns the stack memory; `None` for the boot thread, which runs on the
+    /// bootloader's stack.
+    _stack: Option<Stack>,
+    entry: Option<Box<dyn FnOnce() + Send>>,
+    /// The stack for interrupts while the thread runs in user mode, loaded
+    /// into the TSS whenever the thread is switched to.
+    kernel_stack: Option<VirtAddr>,
+    /// The level 4 table of the thread's address space, or `None` for the
+    /// kernel's.
+    address_space: Option<PhysFrame>,
+    joiners: Vec<ThreadId>,
+    /// Set by `unpark` while the thread is not blocked, so that its next
+    /// `park` returns immediately.
+    wakeup_pending: bool,
+    accounting: Accounting,
+}
+
+/// The time a thread spent running and waiting in the run queue.
+struct Accounting {
+    /// When the thread entered its current state.
+    since: Instant,
+    cpu_time: Duration,
+    wait_time: Duration,
+    voluntary_switches: u64,
+    involuntary_switches: u64,
+}
+
+impl Accounting {
+    fn new() -> Self {
+        Accounting {
+            since: Instant::now(),
+            cpu_time: Duration::ZERO,
+            wait_time: Duration::ZERO,
+            voluntary_switches: 0,
+            involuntary_switches: 0,
+        }
+    }
+}
+
+impl Thread {
+    pub(super) fn new(
+        id: ThreadId,
+        name: String,
+        priority: Priority,
+        time_slice: u32,
+        mut stack: Stack,
+        entry: Box<dyn FnOnce() + Send>,
+        start: extern "C" fn() -> !,
+    ) -> Self {
+        let rsp = context::init_stack(stack.as_mut_slice(), start);
+        Thread {
+            id,
+            name,
+            priority,
+            time_slice,
+            state: ThreadState::Ready,
+            rsp,
+            _stack: Some(stack),
+            entry: Some(entry),
+            kernel_stack: None,
+            address_space: None,
+            joiners: Vec::new(),
+            wakeup_pending: false,
+            accounting: Accounting::new(),
+        }
+    }
+
+    fn boot(id: ThreadId) -> Self {
+        Thread {
+            id,
+            name: String::from("main"),
+            priority: Priority::Normal,
+            time_slice: Priority::Normal.time_slice(),
+            state: ThreadState::Running,
+            rsp: 0,
+            _stack: None,
+            entry: None,
+            kernel_stack: None,
+            address_space: None,
+            joiners: Vec::new(),
+            wakeup_pending: false,
+            accounting: Accounting::new(),
+        }
+    }
+
+    /// Changes the state, charging the time since the last change to the
+    /// CPU or wait time.
+    fn set_state(&mut self, state: ThreadState, now: Instant) {
+        let accounting = &mut self.accounting;
+        let elapsed = now.duration_since(accounting.since);
+        match self.state {
+            ThreadState::Running => accounting.cpu_time += elapsed,
+            ThreadState::Ready => accounting.wait_time += elapsed,
+            ThreadState::Blocked | ThreadState::Exited => {}
+        }
+        accounting.since = now;
+        self.state = state;
+    }
+
+    fn info(&self, now: Instant) -> ThreadInfo {
+        let accounting = &self.accounting;
+        // include the time spent in the current state so far
+        let elapsed = now.duration_since(accounting.since);
+        let (cpu_time, wait_time) = match self.state {
+            ThreadState::Running => (accounting.cpu_time + elapsed, accounting.wait_time),
+            ThreadState::Ready => (accounting.cpu_time, accounting.wait_time + elapsed),
+            ThreadState::Blocked | ThreadState::Exited => {
+                (accounting.cpu_time, accounting.wait_time)
+            }
+        };
+        ThreadInfo {
+            id: self.id,
+            name: self.name.clone(),
+            priority: self.priority,
+            state: self.state,
+            cpu_time,
+            wait_time,
+            voluntary_switches: accounting.voluntary_switches,
+            involuntary_switches: accounting.involuntary_switches,
+        }
+    }
+}
+
+pub(super) struct Scheduler {
+    threads: BTreeMap<ThreadId, Box<Thread>>,
+    /// One FIFO queue of ready threads per priority level.
+    run_queues: [VecDeque<ThreadId>; Priority::COUNT],
+    current: ThreadId,
+    idle: ThreadId,
+    slice_left: u32,
+}
+
+impl Scheduler {
+    fn current_mut(&mut self) -> &mut Thread {
+        let current = self.current;
+        self.threads
+            .get_mut(&current)
+            .expect("current thread not in thread table")
+    }
+
+    pub(super) fn state(&self, id: ThreadId) -> Option<ThreadState> {
+        self.threads.get(&id).map(|thread| thread.state)
+    }
+
+    pub(super) fn priority(&self, id: ThreadId) -> Option<Priority> {
+        self.threads.get(&id).map(|thread| thread.priority)
+    }
+
+    /// Changes the priority and time slice of a thread, moving it to the
+    /// matching run queue if it is ready. Returns `false` if there is no such
+    /// thread.
+    pub(super) fn set_priority(&mut self, id: ThreadId, priority: Priority) -> bool {
+        let idle = self.idle;
+        let thread = match self.threads.get_mut(&id) {
+            Some(thread) => thread,
+            None => return false,
+        };
+        let old_priority = mem::replace(&mut thread.priority, priority);
+        thread.time_slice = priority.time_slice();
+        if thread.state == ThreadState::Ready && id != idle {
+            self.run_queues[old_priority as usize].retain(|&queued| queued != id);
+            self.run_queues[priority as usize].push_back(id);
+        }
+        true
+    }
+
+    pub(super) fn threads(&self) -> Vec<ThreadInfo> {
+        let now = Instant::now();
+        self.threads
+            .values()
+            .map(|thread| thread.info(now))
+            .collect()
+    }
+
+    fn enqueue(&mut self, id: ThreadId) {
+        let priority = self.threads[&id].priority;
+        self.run_queues[priority as usize].push_back(id);
+    }
+
+    fn highest_ready_priority(&self) -> Option<Priority> {
+        Priority::ALL
+            .iter()
+            .rev()
+            .copied()
+            .find(|&priority| !self.run_queues[priority as usize].is_empty())
+    }
+
+    fn pop_ready(&mut self) -> Option<ThreadId> {
+        let priority = self.highest_ready_priority()?;
+        self.run_queues[priority as usize].pop_front()
+    }
+
+    /// Adds a new thread to the end of the run queue of its priority.
+    pub(super) fn add(&mut self, thread: Thread) {
+        let id = thread.id;
+        self.threads.insert(id, Box::new(thread));
+        // every thread fits into every run queue, so that the timer interrupt
+        // never has to allocate
+        let thread_count = self.threads.len();
+        for queue in self.run_queues.iter_mut() {
+            queue.reserve(thread_count);
+        }
+        self.enqueue(id);
+    }
+
+    /// Removes exited threads other than the current one, whose stack might
+    /// still be in use, and returns them for deallocation.
+    pub(super) fn take_exited(&mut self) -> Vec<Box<Thread>> {
+        let current = self.current;
+        let exited: Vec<ThreadId> = self
+            .threads
+            .values()
+            .filter(|thread| thread.state == ThreadState::Exited && thread.id != current)
+            .map(|thread| thread.id)
+            .collect();
+        exited
+            .into_iter()
+            .filter_map(|id| self.threads.remove(&id))
+            .collect()
+    }
+
+    pub(super) fn take_entry(&mut self) -> Box<dyn FnOnce() + Send> {
+        self.current_mut()
+            .entry
+            .take()
+            .expect("thread started twice")
+    }
+
+    pub(super) fn set_kernel_stack(&mut self, stack_end: VirtAddr) {
+        self.current_mut().kernel_stack = Some(stack_end);
+    }
+
+    pub(super) fn set_address_space(&mut self, level_4_frame: PhysFrame) {
+        self.current_mut().address_space = Some(level_4_frame);
+    }
+
+    /// Marks the current thread as blocked unless a wakeup is pending.
+    /// Returns `false` if the thread should keep running.
+    pub(super) fn block_current(&mut self) -> bool {
+        let thread = self.current_mut();
+        if mem::replace(&mut thread.wakeup_pending, false) {
+            return false;
+        }
+        thread.set_state(ThreadState::Blocked, Instant::now());
+        true
+    }
+
+    /// Makes a blocked thread ready again, or lets its next `park` return
+    /// immediately if it is not blocked.
+    pub(super) fn unpark(&mut self, id: ThreadId) {
+        let thread = match self.threads.get_mut(&id) {
+            Some(thread) => thread,
+            None => return,
+        };
+        match thread.state {
+            ThreadState::Blocked => {
+                thread.set_state(ThreadState::Ready, Instant::now());
+                self.enqueue(id);
+            }
+            ThreadState::Ready | ThreadState::Running => thread.wakeup_pending = true,
+            ThreadState::Exited => {}
+        }
+    }
+
+    /// Registers the current thread to be unparked when `id` exits. Returns
+    /// `false` if `id` already exited.
+    pub(super) fn add_joiner(&mut self, id: ThreadId) -> bool {
+        let current = self.current;
+        match self.threads.get_mut(&id) {
+            Some(thread) if thread.state != ThreadState::Exited => {
+                thread.joiners.push(current);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub(super) fn exit_current(&mut self) {
+        let thread = self.current_mut();
+        thread.set_state(ThreadState::Exited, Instant::now());
+        for joiner in mem::take(&mut thread.joiners) {
+            self.unpark(joiner);
+        }
+    }
+
+    /// Accounts one timer tick and returns `true` if the current thread
+    /// should be preempted, either because its time slice is used up or
+    /// because a thread of higher priority is ready.
+    fn tick(&mut self) -> bool {
+        self.slice_left = self.slice_left.saturating_sub(1);
+        // the idle thread ranks below every priority
+        let current_priority = if self.current == self.idle {
+            None
+        } else {
+            self.priority(self.current)
+        };
+        self.slice_left == 0 || self.highest_ready_priority() > current_priority
+    }
+
+    /// Picks the next thread to run and returns the location to save the
+    /// current stack pointer to and the stack pointer to continue on, or
+    /// `None` if the current thread continues to run.
+    ///
+    /// `preempted` tells whether the timer forced the switch, which counts as
+    /// an involuntary context switch if the current thread is still running.
+    fn next_switch(&mut self, preempted: bool) -> Option<(*mut u64, u64)> {
+        let now = Instant::now();
+        let current = self.current;
+        let idle = self.idle;
+        let thread = self.current_mut();
+        let still_running = thread.state == ThreadState::Running;
+        if still_running {
+            thread.set_state(ThreadState::Ready, now);
+            // the idle thread only runs if nothing else is ready
+            if current != idle {
+                self.enqueue(current);
+            }
+        }
+
+        let next = self.pop_ready().unwrap_or(idle);
+        let next_thread = self
+            .threads
+            .get_mut(&next)
+            .expect("thread in run queue not in thread table");
+        next_thread.set_state(ThreadState::Running, now);
+        self.slice_left = next_thread.time_slice;
+        if next == current {
+            return None;
+        }
+        let new_rsp = next_thread.rsp;
+        if let Some(stack_end) = next_thread.kernel_stack {
+            gdt::set_kernel_stack(stack_end);
+        }
+        if let Some(level_4_frame) = next_thread
+            .address_space
+            .or_else(memory::kernel_level_4_frame)
+        {
+            switch_address_space(level_4_frame);
+        }
+        self.current = next;
+        this_cpu!(current_thread).store(next.as_u64(), Ordering::Relaxed);
+
+        let old_thread = self
+            .threads
+            .get_mut(&current)
+            .expect("previous thread not in thread table");
+        if still_running && preempted {
+            old_thread.accounting.involuntary_switches += 1;
+        } else {
+            old_thread.accounting.voluntary_switches += 1;
+        }
+        Some((&mut old_thread.rsp as *mut u64, new_rsp))
+    }
+}
+
+/// Loads the given level 4 table unless it is active already, which would
+/// flush the TLB for nothing.
+pub(super) fn switch_address_space(level_4_frame: PhysFrame) {
+    let (active, flags) = Cr3::read();
+    if active != level_4_frame {
+        unsafe { Cr3::write(level_4_frame, flags) };
+    }
+}
+
+/// Sets up the scheduler with the calling code as the boot thread and the
+/// given idle thread, which runs whenever no other thread is ready.
+pub(super) fn init(boot: ThreadId, idle: Thread) {
+    this_cpu!(current_thread).store(boot.as_u64(), Ordering::Relaxed);
+    SCHEDULER.call_once(|| {
+        let mut threads = BTreeMap::new();
+        let idle_id = idle.id;
+        threads.insert(boot, Box::new(Thread::boot(boot)));
+        threads.insert(idle_id, Box::new(idle));
+        Mutex::new(Scheduler {
+            threads,
+            run_queues: Default::default(),
+            current: boot,
+            idle: idle_id,
+            slice_left: Priority::Normal.time_slice(),
+        })
+    });
+}
+
+pub(super) fn is_initialized() -> bool {
+    SCHEDULER.is_completed()
+}
+
+/// Runs `f` on the scheduler with interrupts disabled.
+pub(super) fn with<T>(f: impl FnOnce(&mut Scheduler) -> T) -> T {
+    use x86_64::instructions::interrupts::without_interrupts;
+
+    let scheduler = SCHEDULER.get().expect("thread::init not called");
+    without_interrupts(|| f(&mut scheduler.lock()))
+}
+
+/// Switches to the next ready thread, which may be the current one if it is
+/// still running and nothing else is ready.
+///
+/// Interrupts must be disabled; they keep their state across the switch.
+pub(super) fn schedule() {
+    switch(false);
+}
+
+fn switch(preempted: bool) {
+    let switch = SCHEDULER
+        .get()
+        .expect("thread::init not called")
+        .lock()
+        .next_switch(preempted);
+    if let Some((old_rsp, new_rsp)) = switch {
+        // the lock is released, and the saved stack pointer lives in a boxed
+        // thread that is not freed while it is the current one
+        unsafe { context::switch(old_rsp, new_rsp) };
+    }
+}
+
+/// Called from the timer interrupt handler after the EOI.
+pub(super) fn tick() {
+    let preempt = match SCHEDULER.get() {
+        Some(scheduler) => scheduler.lock().tick(),
+        None => false,
+    };
+    if preempt {
+        switch(true);
+    }
+}
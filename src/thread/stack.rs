@@ -0,0 +1,91 @@
+//! Stacks of kernel threads.
+//!
+//! A stack is allocated from `allocator::STACK_ALLOCATOR` together with a
+//! guard page below it, which is unmapped while the stack exists. A thread
+//! that overflows its stack faults on the guard page instead of overwriting whatever lies
+//! below; since the page fault handler cannot push its frame either, this
+//! ends in a double fault, which runs on a stack of its own.
+
+use crate::allocator::STACK_ALLOCATOR;
+use crate::memory::{self, EmptyFrameAllocator};
+use alloc::alloc::{handle_alloc_error, GlobalAlloc, Layout};
+use core::ptr::NonNull;
+use core::slice;
+use x86_64::structures::paging::mapper::TranslateResult;
+use x86_64::structures::paging::{Mapper, Page, PageTableFlags, PhysFrame, Translate};
+use x86_64::VirtAddr;
+
+const PAGE_SIZE: usize = 4096;
+
+pub(super) struct Stack {
+    /// The start of the allocation, which is the guard page.
+    start: NonNull<u8>,
+    layout: Layout,
+    /// The frame and flags the guard page was mapped with, to restore them
+    /// before the memory goes back to the allocator.
+    guard: (PhysFrame, PageTableFlags),
+}
+
+// the memory is owned like a `Box<[u8]>`
+unsafe impl Send for Stack {}
+
+impl Stack {
+    /// Allocates a zeroed stack of at least `size` bytes, rounded up to whole
+    /// pages.
+    ///
+    /// `memory::init` must have been called.
+    pub(super) fn new(size: usize) -> Self {
+        let size = (size.max(1) + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE;
+        let layout = Layout::from_size_align(PAGE_SIZE + size, PAGE_SIZE).expect("stack too large");
+        let start = NonNull::new(unsafe { STACK_ALLOCATOR.alloc_zeroed(layout) })
+            .unwrap_or_else(|| handle_alloc_error(layout));
+
+        let page = guard_page(start);
+        let mut mapper = unsafe { memory::kernel_mapper() };
+        let flags = match mapper.translate(page.start_address()) {
+            TranslateResult::Mapped { flags, .. } => flags,
+            _ => panic!("stack page {:?} is not mapped", page),
+        };
+        // nothing else uses the page while it belongs to the allocation
+        let frame = memory::unmap(&mut mapper, page).expect("failed to unmap guard page");
+        Stack {
+            start,
+            layout,
+            guard: (frame, flags),
+        }
+    }
+
+    /// Returns the usable memory above the guard page.
+    pub(super) fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe {
+            slice::from_raw_parts_mut(
+                self.start.as_ptr().add(PAGE_SIZE),
+                self.layout.size() - PAGE_SIZE,
+            )
+        }
+    }
+}
+
+impl Drop for Stack {
+    fn drop(&mut self) {
+        let (frame, flags) = self.guard;
+        let mut mapper = unsafe { memory::kernel_mapper() };
+        // the page table of the page still exists, so no frame is needed
+        unsafe {
+            mapper
+                .map_to(
+                    guard_page(self.start),
+                    frame,
+                    flags,
+                    &mut EmptyFrameAllocator,
+                )
+                .expect("failed to map guard page")
+                .flush();
+            STACK_ALLOCATOR.dealloc(self.start.as_ptr(), self.layout);
+        }
+    }
+}
+
+fn guard_page(start: NonNull<u8>) -> Page {
+    Page::containing_address(VirtAddr::from_ptr(start.as_ptr()))
+}
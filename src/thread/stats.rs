@@ -0,0 +1,77 @@
+use super::{scheduler, Priority, ThreadId, ThreadState};
+use crate::serial;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::time::Duration;
+
+/// A snapshot of the scheduling statistics of one thread.
+#[derive(Debug, Clone)]
+pub struct ThreadInfo {
+    pub id: ThreadId,
+    pub name: String,
+    pub priority: Priority,
+    pub state: ThreadState,
+    /// The time the thread was running.
+    pub cpu_time: Duration,
+    /// The time the thread was ready to run but waited in the run queue.
+    pub wait_time: Duration,
+    /// Switches away from the thread because it blocked, yielded or exited.
+    pub voluntary_switches: u64,
+    /// Switches away from the thread because the timer preempted it.
+    pub involuntary_switches: u64,
+}
+
+/// Returns a snapshot of all threads that have not been cleaned up yet.
+pub fn threads() -> Vec<ThreadInfo> {
+    scheduler::with(|scheduler| scheduler.threads())
+}
+
+/// Formats a duration as milliseconds with three decimals.
+struct Millis(Duration);
+
+impl fmt::Display for Millis {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let micros = self.0.as_micros();
+        f.pad(&alloc::format!("{}.{:03}", micros / 1000, micros % 1000))
+    }
+}
+
+/// Writes a `ps`-like table of all threads.
+pub fn write_table(f: &mut impl Write) -> fmt::Result {
+    let threads = threads();
+    let total_cpu: Duration = threads.iter().map(|thread| thread.cpu_time).sum();
+
+    writeln!(
+        f,
+        "{:>4} {:<12} {:<6} {:<7} {:>5} {:>11} {:>11} {:>8} {:>8}",
+        "TID", "NAME", "PRIO", "STATE", "%CPU", "CPU(ms)", "WAIT(ms)", "VCSW", "ICSW"
+    )?;
+    for thread in &threads {
+        // in tenths of a percent
+        let share = match total_cpu.as_nanos() {
+            0 => 0,
+            total => thread.cpu_time.as_nanos() * 1000 / total,
+        };
+        writeln!(
+            f,
+            "{:>4} {:<12} {:<6} {:<7} {:>3}.{} {:>11} {:>11} {:>8} {:>8}",
+            thread.id.as_u64(),
+            thread.name,
+            thread.priority,
+            thread.state,
+            share / 10,
+            share % 10,
+            Millis(thread.cpu_time),
+            Millis(thread.wait_time),
+            thread.voluntary_switches,
+            thread.involuntary_switches,
+        )?;
+    }
+    Ok(())
+}
+
+/// Prints the thread table to the serial port.
+pub fn dump() {
+    write_table(&mut serial::Writer).unwrap();
+}
@@ -0,0 +1,158 @@
+pub mod hpet;
+pub mod pit;
+pub mod tsc;
+
+use core::fmt;
+use core::ops::{Add, Sub};
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+/// Timer interrupts received since `pit::init`.
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// TSC value at the time `init` calibrated the counter.
+static BOOT_TSC: AtomicU64 = AtomicU64::new(0);
+
+/// Starts the periodic timer interrupt and calibrates the TSC against the PIT.
+///
+/// Once ACPI is initialized, `init_hpet` can be used to calibrate the TSC
+/// again against the more precise HPET.
+pub fn init() {
+    pit::init();
+    tsc::calibrate();
+    BOOT_TSC.store(tsc::read(), Ordering::Relaxed);
+}
+
+/// Starts the HPET if the ACPI tables describe one and re-calibrates the TSC
+/// against it. Returns `false` if no HPET is present.
+pub fn init_hpet() -> bool {
+    if !hpet::init() {
+        return false;
+    }
+    tsc::calibrate();
+    true
+}
+
+/// Called by the timer interrupt handler on every tick.
+pub(crate) fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns the number of timer interrupts received so far.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Converts a duration to timer ticks, rounding up.
+pub fn duration_to_ticks(duration: Duration) -> u64 {
+    let tick_nanos = 1_000_000_000 / u128::from(pit::TIMER_FREQUENCY_HZ);
+    ((duration.as_nanos() + tick_nanos - 1) / tick_nanos) as u64
+}
+
+/// Returns the duration of the given number of timer ticks.
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    Duration::from_nanos(ticks * (1_000_000_000 / pit::TIMER_FREQUENCY_HZ))
+}
+
+/// Returns the time elapsed since `init` was called.
+pub fn since_boot() -> Duration {
+    Instant::now() - Instant(BOOT_TSC.load(Ordering::Relaxed))
+}
+
+/// A measurement of the time stamp counter, comparable to `std::time::Instant`.
+///
+/// Conversions to `Duration` require a calibrated TSC (see `init`). The
+/// measurements are only meaningful on a single CPU unless `tsc::is_invariant`
+/// returns `true`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    /// Returns an instant corresponding to "now".
+    #[inline(always)]
+    pub fn now() -> Self {
+        Instant(tsc::read())
+    }
+
+    /// Returns the raw TSC value of this instant.
+    pub fn as_ticks(&self) -> u64 {
+        self.0
+    }
+
+    /// Returns the time elapsed from `earlier` to `self`, or zero if `earlier`
+    /// is later than `self`.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_nanos(tsc::ticks_to_nanos(self.0.saturating_sub(earlier.0)))
+    }
+
+    /// Returns the time elapsed since this instant was created.
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    /// Returns the time elapsed since `time::init` in nanoseconds.
+    pub fn as_nanos_since_boot(&self) -> u64 {
+        tsc::ticks_to_nanos(self.0.saturating_sub(BOOT_TSC.load(Ordering::Relaxed)))
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Instant {
+        Instant(self.0 + tsc::nanos_to_ticks(rhs.as_nanos() as u64))
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, rhs: Duration) -> Instant {
+        Instant(
+            self.0
+                .saturating_sub(tsc::nanos_to_ticks(rhs.as_nanos() as u64)),
+        )
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Instant) -> Duration {
+        self.duration_since(rhs)
+    }
+}
+
+/// Formats the instant as seconds since boot, as used in log timestamps.
+impl fmt::Display for Instant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let nanos = self.as_nanos_since_boot();
+        write!(
+            f,
+            "{:>5}.{:06}",
+            nanos / 1_000_000_000,
+            nanos % 1_000_000_000 / 1000
+        )
+    }
+}
+
+#[test_case]
+fn test_instant_is_monotonic() {
+    let earlier = Instant::now();
+    let later = Instant::now();
+    assert!(later >= earlier);
+    assert!(later.duration_since(earlier) < Duration::from_secs(1));
+}
+
+#[test_case]
+fn test_tick_conversion() {
+    assert_eq!(duration_to_ticks(Duration::from_millis(0)), 0);
+    assert_eq!(duration_to_ticks(Duration::from_millis(1)), 1);
+    assert_eq!(duration_to_ticks(Duration::from_millis(50)), 5);
+    assert_eq!(ticks_to_duration(5), Duration::from_millis(50));
+}
+
+#[test_case]
+fn test_tsc_is_calibrated() {
+    assert_ne!(tsc::frequency_hz(), 0);
+}
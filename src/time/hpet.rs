@@ -0,0 +1,82 @@
+use crate::acpi;
+use crate::memory::phys_to_virt;
+use core::ptr;
+use spin::Once;
+use x86_64::{PhysAddr, VirtAddr};
+
+const CAPABILITIES_REGISTER: usize = 0x00;
+const CONFIGURATION_REGISTER: usize = 0x10;
+const MAIN_COUNTER_REGISTER: usize = 0xF0;
+
+const ENABLE_CNF: u64 = 1 << 0;
+
+/// Femtoseconds per second.
+const FS_PER_SECOND: u64 = 1_000_000_000_000_000;
+
+/// A High Precision Event Timer found through the ACPI `HPET` table.
+pub struct Hpet {
+    base: VirtAddr,
+    /// The period of the main counter in femtoseconds.
+    period_fs: u64,
+}
+
+static HPET: Once<Hpet> = Once::new();
+
+impl Hpet {
+    fn read(&self, register: usize) -> u64 {
+        unsafe { ptr::read_volatile((self.base + register).as_ptr()) }
+    }
+
+    fn write(&self, register: usize, value: u64) {
+        unsafe { ptr::write_volatile((self.base + register).as_mut_ptr(), value) }
+    }
+
+    /// Returns the current value of the main counter.
+    pub fn counter(&self) -> u64 {
+        self.read(MAIN_COUNTER_REGISTER)
+    }
+
+    /// Returns the frequency of the main counter in Hz.
+    pub fn frequency_hz(&self) -> u64 {
+        FS_PER_SECOND / self.period_fs
+    }
+}
+
+/// Looks for an HPET in the ACPI tables and starts its main counter.
+///
+/// Returns `false` if ACPI is not initialized or there is no usable HPET.
+pub fn init() -> bool {
+    if HPET.is_completed() {
+        return true;
+    }
+
+    let table = match acpi::find_table(b"HPET") {
+        Some(table) => table,
+        None => return false,
+    };
+    // the base address is the `address` field of the Generic Address
+    // Structure that follows the event timer block id
+    let base: u64 = unsafe { ptr::read_unaligned(phys_to_virt(table + 44u64).as_ptr()) };
+    let mut hpet = Hpet {
+        base: phys_to_virt(PhysAddr::new(base)),
+        period_fs: 0,
+    };
+
+    // the upper half of the capabilities register holds the counter period,
+    // which must not be zero or above 100 ns according to the specification
+    hpet.period_fs = hpet.read(CAPABILITIES_REGISTER) >> 32;
+    if hpet.period_fs == 0 || hpet.period_fs > 100_000_000 {
+        return false;
+    }
+
+    let config = hpet.read(CONFIGURATION_REGISTER);
+    hpet.write(CONFIGURATION_REGISTER, config | ENABLE_CNF);
+
+    HPET.call_once(|| hpet);
+    true
+}
+
+/// Returns the HPET if `init` found one.
+pub fn get() -> Option<&'static Hpet> {
+    HPET.get()
+}
@@ -0,0 +1,69 @@
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+/// The fixed input frequency of the 8253/8254 Programmable Interval Timer.
+pub const BASE_FREQUENCY_HZ: u64 = 1_193_182;
+
+/// The frequency at which channel 0 raises the timer interrupt.
+pub const TIMER_FREQUENCY_HZ: u64 = 100;
+
+struct Pit {
+    channel_0: Port<u8>,
+    channel_2: Port<u8>,
+    command: Port<u8>,
+    /// Gate and output of channel 2 live in the PC speaker control port.
+    speaker_control: Port<u8>,
+}
+
+static PIT: Mutex<Pit> = Mutex::new(Pit {
+    channel_0: Port::new(0x40),
+    channel_2: Port::new(0x42),
+    command: Port::new(0x43),
+    speaker_control: Port::new(0x61),
+});
+
+/// Programs channel 0 to fire the timer interrupt at `TIMER_FREQUENCY_HZ`.
+pub fn init() {
+    let divisor = (BASE_FREQUENCY_HZ / TIMER_FREQUENCY_HZ) as u16;
+    let mut pit = PIT.lock();
+
+    unsafe {
+        // channel 0, access lobyte/hibyte, mode 3 (square wave), binary
+        pit.command.write(0b0011_0110);
+        pit.channel_0.write(divisor as u8);
+        pit.channel_0.write((divisor >> 8) as u8);
+    }
+}
+
+/// Busy-waits for the given number of PIT ticks using channel 2, calling
+/// `before` right after the countdown started and returning the result of
+/// `after`, which is called as soon as the countdown reached zero.
+///
+/// Channel 2 does not raise an interrupt, so this works with interrupts
+/// disabled. The speaker output is kept off while counting.
+pub fn wait_ticks<T>(ticks: u16, before: impl FnOnce(), after: impl FnOnce() -> T) -> T {
+    let mut pit = PIT.lock();
+
+    unsafe {
+        // disable the gate and the speaker
+        let control = pit.speaker_control.read() & !0b11;
+        pit.speaker_control.write(control);
+
+        // channel 2, access lobyte/hibyte, mode 0 (interrupt on terminal count), binary
+        pit.command.write(0b1011_0000);
+        pit.channel_2.write(ticks as u8);
+        pit.channel_2.write((ticks >> 8) as u8);
+
+        // raising the gate starts the countdown
+        pit.speaker_control.write(control | 0b1);
+        before();
+        // bit 5 reflects the output of channel 2, which goes high at terminal count
+        while pit.speaker_control.read() & 0b10_0000 == 0 {
+            core::hint::spin_loop();
+        }
+        let result = after();
+
+        pit.speaker_control.write(control);
+        result
+    }
+}
@@ -0,0 +1,133 @@
+use super::{hpet, pit};
+use core::arch::x86_64::{__cpuid, _rdtsc};
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+/// The PIT ticks to count during one calibration round (about 10 ms).
+const PIT_CALIBRATION_TICKS: u16 = 11_932;
+
+/// The duration of one HPET calibration round in nanoseconds.
+const HPET_CALIBRATION_NANOS: u64 = 10_000_000;
+
+const CALIBRATION_ROUNDS: usize = 5;
+
+static FREQUENCY_HZ: AtomicU64 = AtomicU64::new(0);
+static SOURCE: AtomicU8 = AtomicU8::new(CalibrationSource::None as u8);
+
+/// The reference timer the TSC frequency was measured against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum CalibrationSource {
+    None,
+    Pit,
+    Hpet,
+}
+
+/// Reads the current value of the time stamp counter.
+#[inline(always)]
+pub fn read() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// Checks through CPUID whether the processor has a time stamp counter.
+pub fn is_present() -> bool {
+    let features = unsafe { __cpuid(0x1) };
+    features.edx & (1 << 4) != 0
+}
+
+/// Checks through CPUID whether the TSC runs at a constant rate in all
+/// ACPI P-, C- and T-states, so that it can be used as a wall-clock source.
+pub fn is_invariant() -> bool {
+    let max_extended_leaf = unsafe { __cpuid(0x8000_0000) }.eax;
+    if max_extended_leaf < 0x8000_0007 {
+        return false;
+    }
+    let power_management = unsafe { __cpuid(0x8000_0007) };
+    power_management.edx & (1 << 8) != 0
+}
+
+/// Measures the TSC frequency against the HPET if `hpet::init` found one,
+/// and against channel 2 of the PIT otherwise.
+///
+/// Every round is measured with interrupts disabled and the shortest round is
+/// used, since it is the one least disturbed by SMIs and emulator scheduling.
+///
+/// Panics if the processor has no TSC, which all timekeeping relies on.
+pub fn calibrate() -> u64 {
+    use x86_64::instructions::interrupts;
+
+    assert!(is_present(), "the processor has no time stamp counter");
+
+    let (source, frequency) = interrupts::without_interrupts(|| match hpet::get() {
+        Some(hpet) => (CalibrationSource::Hpet, calibrate_with_hpet(hpet)),
+        None => (CalibrationSource::Pit, calibrate_with_pit()),
+    });
+
+    FREQUENCY_HZ.store(frequency, Ordering::Relaxed);
+    SOURCE.store(source as u8, Ordering::Relaxed);
+    frequency
+}
+
+fn calibrate_with_pit() -> u64 {
+    let min_ticks = (0..CALIBRATION_ROUNDS)
+        .map(|_| {
+            let mut start = 0;
+            let end = pit::wait_ticks(PIT_CALIBRATION_TICKS, || start = read(), read);
+            end - start
+        })
+        .min()
+        .unwrap();
+
+    min_ticks * pit::BASE_FREQUENCY_HZ / u64::from(PIT_CALIBRATION_TICKS)
+}
+
+fn calibrate_with_hpet(hpet: &hpet::Hpet) -> u64 {
+    let hpet_frequency = hpet.frequency_hz();
+    let hpet_ticks = HPET_CALIBRATION_NANOS * hpet_frequency / 1_000_000_000;
+
+    (0..CALIBRATION_ROUNDS)
+        .map(|_| {
+            let hpet_start = hpet.counter();
+            let tsc_start = read();
+            let mut hpet_now = hpet_start;
+            while hpet_now.wrapping_sub(hpet_start) < hpet_ticks {
+                hpet_now = hpet.counter();
+            }
+            let tsc_ticks = read() - tsc_start;
+            let elapsed = hpet_now.wrapping_sub(hpet_start);
+            (u128::from(tsc_ticks) * u128::from(hpet_frequency) / u128::from(elapsed)) as u64
+        })
+        .min()
+        .unwrap()
+}
+
+/// Returns the calibrated TSC frequency in Hz, or 0 if `calibrate` was not called.
+pub fn frequency_hz() -> u64 {
+    FREQUENCY_HZ.load(Ordering::Relaxed)
+}
+
+/// Returns the reference timer used by the last calibration.
+pub fn calibration_source() -> CalibrationSource {
+    match SOURCE.load(Ordering::Relaxed) {
+        1 => CalibrationSource::Pit,
+        2 => CalibrationSource::Hpet,
+        _ => CalibrationSource::None,
+    }
+}
+
+/// Converts a number of TSC ticks into nanoseconds.
+///
+/// Panics if the TSC has not been calibrated yet.
+pub fn ticks_to_nanos(ticks: u64) -> u64 {
+    let frequency = frequency_hz();
+    assert!(frequency != 0, "TSC is not calibrated");
+    (u128::from(ticks) * 1_000_000_000 / u128::from(frequency)) as u64
+}
+
+/// Converts nanoseconds into a number of TSC ticks.
+///
+/// Panics if the TSC has not been calibrated yet.
+pub fn nanos_to_ticks(nanos: u64) -> u64 {
+    let frequency = frequency_hz();
+    assert!(frequency != 0, "TSC is not calibrated");
+    (u128::from(nanos) * u128::from(frequency) / 1_000_000_000) as u64
+}
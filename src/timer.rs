@@ -0,0 +1,256 @@
+//! Kernel timers: callbacks that run once or periodically after a delay.
+//!
+//! Timers are kept in a hashed timing wheel with one slot per timer tick.
+//! The timer interrupt only checks whether the earliest deadline has passed
+//! and wakes up the high-priority `timer` thread, which runs the expired
+//! callbacks in thread context. Callbacks may therefore block, but should be
+//! short since they delay all other timers.
+//!
+//! The wheel is guarded by a sleeping `sync::Mutex` and callbacks are boxed,
+//! so timers can only be added by threads with interrupts enabled; interrupt
+//! handlers can defer that to the work queue. `TimerHandle::cancel` works in
+//! any context.
+
+use crate::sync::Mutex;
+use crate::thread::{self, Priority, ThreadId};
+use crate::time;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use core::time::Duration;
+use lazy_static::lazy_static;
+use spin::Once;
+use x86_64::instructions::interrupts;
+
+/// The number of slots of the wheel. Timers further in the future than this
+/// many ticks share slots with earlier ones and are skipped until their round.
+const WHEEL_SIZE: usize = 256;
+
+const STATE_ACTIVE: u8 = 0;
+const STATE_FIRED: u8 = 1;
+const STATE_CANCELLED: u8 = 2;
+
+lazy_static! {
+    static ref WHEEL: Mutex<Wheel> = Mutex::new(Wheel::new());
+}
+
+/// The earliest deadline in the wheel, checked by the timer interrupt.
+static NEXT_DEADLINE: AtomicU64 = AtomicU64::new(u64::MAX);
+
+static TIMER_THREAD: Once<ThreadId> = Once::new();
+
+enum Callback {
+    Once(Box<dyn FnOnce() + Send>),
+    Periodic(Box<dyn FnMut() + Send>),
+}
+
+struct Entry {
+    deadline: u64,
+    /// The interval in ticks of a periodic timer.
+    period: Option<u64>,
+    callback: Callback,
+    state: Arc<AtomicU8>,
+}
+
+struct Wheel {
+    slots: Vec<Vec<Entry>>,
+    /// The last tick whose slot was processed.
+    processed: u64,
+}
+
+impl Wheel {
+    fn new() -> Self {
+        Wheel {
+            slots: (0..WHEEL_SIZE).map(|_| Vec::new()).collect(),
+            processed: time::ticks(),
+        }
+    }
+
+    fn insert(&mut self, entry: Entry) {
+        NEXT_DEADLINE.fetch_min(entry.deadline, Ordering::SeqCst);
+        self.slots[entry.deadline as usize % WHEEL_SIZE].push(entry);
+    }
+
+    /// Removes and returns all entries whose deadline is at or before `now`.
+    /// Cancelled entries are dropped on the way.
+    fn take_expired(&mut self, now: u64) -> Vec<Entry> {
+        let mut expired = Vec::new();
+        // after a long delay every slot may hold expired timers
+        let first = now
+            .saturating_sub(WHEEL_SIZE as u64 - 1)
+            .max(self.processed + 1);
+        for tick in first..=now {
+            let slot = &mut self.slots[tick as usize % WHEEL_SIZE];
+            let mut i = 0;
+            while i < slot.len() {
+                if slot[i].state.load(Ordering::SeqCst) == STATE_CANCELLED {
+                    slot.swap_remove(i);
+                } else if slot[i].deadline <= now {
+                    expired.push(slot.swap_remove(i));
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        self.processed = self.processed.max(now);
+        expired.sort_by_key(|entry| entry.deadline);
+        expired
+    }
+
+    fn next_deadline(&self) -> u64 {
+        self.slots
+            .iter()
+            .flatten()
+            .map(|entry| entry.deadline)
+            .min()
+            .unwrap_or(u64::MAX)
+    }
+}
+
+/// A handle to cancel a timer. Dropping it leaves the timer running.
+#[must_use = "dropping the handle does not cancel the timer"]
+pub struct TimerHandle {
+    state: Arc<AtomicU8>,
+}
+
+impl TimerHandle {
+    /// Cancels the timer. Returns `false` if a one-shot timer already fired
+    /// or the timer was cancelled before.
+    ///
+    /// A callback that is running concurrently finishes, but a cancelled
+    /// periodic timer does not run again.
+    pub fn cancel(&self) -> bool {
+        self.state
+            .compare_exchange(
+                STATE_ACTIVE,
+                STATE_CANCELLED,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            )
+            .is_ok()
+    }
+
+    /// Returns `true` until a one-shot timer fired or the timer is cancelled.
+    pub fn is_active(&self) -> bool {
+        self.state.load(Ordering::SeqCst) == STATE_ACTIVE
+    }
+}
+
+/// Starts the `timer` thread that runs expired callbacks.
+///
+/// `thread::init` must have been called.
+pub fn init() {
+    TIMER_THREAD.call_once(|| {
+        let handle = thread::Builder::new()
+            .name("timer")
+            .priority(Priority::High)
+            .spawn(run);
+        handle.id()
+    });
+}
+
+/// Runs `f` once after `delay`.
+///
+/// The callback runs on the timer thread at the first timer tick after the
+/// delay has passed, so the resolution is that of `time::pit`.
+pub fn add_oneshot(delay: Duration, f: impl FnOnce() + Send + 'static) -> TimerHandle {
+    add(delay, None, Callback::Once(Box::new(f)))
+}
+
+/// Runs `f` every `period`, starting one period from now.
+///
+/// If the timer thread falls behind, missed runs are skipped instead of
+/// being run in a burst.
+pub fn add_periodic(period: Duration, f: impl FnMut() + Send + 'static) -> TimerHandle {
+    let period_ticks = time::duration_to_ticks(period).max(1);
+    add(period, Some(period_ticks), Callback::Periodic(Box::new(f)))
+}
+
+fn add(delay: Duration, period: Option<u64>, callback: Callback) -> TimerHandle {
+    assert!(
+        !crate::interrupts::in_interrupt() && interrupts::are_enabled(),
+        "timers must be added by a thread with interrupts enabled"
+    );
+    let state = Arc::new(AtomicU8::new(STATE_ACTIVE));
+    // the current tick has partly elapsed already, so wait for one more
+    let deadline = time::ticks() + time::duration_to_ticks(delay) + 1;
+    WHEEL.lock().insert(Entry {
+        deadline,
+        period,
+        callback,
+        state: state.clone(),
+    });
+    TimerHandle { state }
+}
+
+/// Called by the timer interrupt handler on every tick.
+pub(crate) fn tick() {
+    if time::ticks() < NEXT_DEADLINE.load(Ordering::SeqCst) {
+        return;
+    }
+    if let Some(&timer_thread) = TIMER_THREAD.get() {
+        thread::unpark(timer_thread);
+    }
+}
+
+/// The main loop of the timer thread.
+fn run() {
+    loop {
+        let now = time::ticks();
+        let expired = WHEEL.lock().take_expired(now);
+        for entry in expired {
+            fire(entry, now);
+        }
+
+        let next_deadline = {
+            let wheel = WHEEL.lock();
+            let next_deadline = wheel.next_deadline();
+            NEXT_DEADLINE.store(next_deadline, Ordering::SeqCst);
+            next_deadline
+        };
+        // `tick` unparks us if the deadline passes before we sleep
+        if time::ticks() < next_deadline {
+            thread::park();
+        }
+    }
+}
+
+fn fire(entry: Entry, now: u64) {
+    let Entry {
+        deadline,
+        period,
+        callback,
+        state,
+    } = entry;
+    match callback {
+        Callback::Once(f) => {
+            let fired = state
+                .compare_exchange(
+                    STATE_ACTIVE,
+                    STATE_FIRED,
+                    Ordering::SeqCst,
+                    Ordering::SeqCst,
+                )
+                .is_ok();
+            if fired {
+                f();
+            }
+        }
+        Callback::Periodic(mut f) => {
+            if state.load(Ordering::SeqCst) != STATE_ACTIVE {
+                return;
+            }
+            f();
+            let period = period.expect("periodic timer without period");
+            // skip the runs we missed
+            let missed = now.saturating_sub(deadline) / period;
+            WHEEL.lock().insert(Entry {
+                deadline: deadline + (missed + 1) * period,
+                period: Some(period),
+                callback: Callback::Periodic(f),
+                state,
+            });
+        }
+    }
+}
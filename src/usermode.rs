@@ -0,0 +1,180 @@
+//! Running code in user mode (ring 3).
+//!
+//! User programs live in the lower half of the shared address space, between
+//! `USER_SPACE_START` and `USER_SPACE_END`, which the kernel and the
+//! bootloader leave free. Pages mapped there with `map_user_pages` are the
+//! only ones user code can access.
+//!
+//! A thread enters user mode with `enter` and never returns from it. On every
+//! interrupt or exception in user mode the processor switches to the kernel
+//! stack of the thread (see `gdt::set_kernel_stack`), so the handlers run as
+//! usual and return to the user code with `iretq`. A fault in user mode kills
+//! the process instead of the kernel (see `kill_current`).
+//!
+//! The kernel accesses user memory only through `copy_from_user` and its
+//! siblings, which survive unmapped addresses.
+
+mod access;
+
+pub(crate) use access::fixup_address;
+pub use access::{copy_from_user, copy_to_user, strncpy_from_user};
+
+use crate::memory::phys_to_virt;
+use crate::{gdt, println, process, thread};
+use core::arch::asm;
+use core::fmt;
+use core::ptr;
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::page::PageRange;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, PageTableFlags, Size4KiB,
+};
+use x86_64::VirtAddr;
+
+/// The lowest address of user space, the start of level 4 entry 32.
+pub const USER_SPACE_START: u64 = 0x0000_1000_0000_0000;
+
+/// The end of user space (exclusive), the end of level 4 entry 63.
+pub const USER_SPACE_END: u64 = 0x0000_2000_0000_0000;
+
+/// Interrupts are enabled in user mode; bit 1 is reserved and always set.
+const USER_RFLAGS: u64 = 0x202;
+
+/// Returns `true` if `address` lies in user space.
+pub fn is_user_address(address: VirtAddr) -> bool {
+    (USER_SPACE_START..USER_SPACE_END).contains(&address.as_u64())
+}
+
+/// Returns `true` if the `len` bytes starting at `start` lie in user space.
+pub fn is_user_range(start: VirtAddr, len: usize) -> bool {
+    match start.as_u64().checked_add(len as u64) {
+        Some(end) => start.as_u64() >= USER_SPACE_START && end <= USER_SPACE_END,
+        None => false,
+    }
+}
+
+/// Returns `true` if the interrupt or exception arrived in user mode.
+pub fn is_user_frame(stack_frame: &InterruptStackFrame) -> bool {
+    stack_frame.code_segment & 3 == 3
+}
+
+/// Maps `pages` to newly allocated, zeroed frames that user code can access
+/// with the given `flags`, to which `PRESENT` and `USER_ACCESSIBLE` are added.
+///
+/// On errors, the frame of the failing page goes back to `frame_allocator`;
+/// the pages mapped before stay mapped.
+///
+/// Panics if a page lies outside of user space.
+pub fn map_user_pages(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>),
+    pages: PageRange<Size4KiB>,
+    flags: PageTableFlags,
+) -> Result<(), MapToError<Size4KiB>> {
+    let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    // the user bit only takes effect if it is set on every level
+    let table_flags =
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+    for page in pages {
+        assert!(
+            is_user_address(page.start_address()),
+            "{:?} is outside of user space",
+            page
+        );
+        let frame = frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        // the frame may still hold kernel data
+        unsafe {
+            ptr::write_bytes(
+                phys_to_virt(frame.start_address()).as_mut_ptr::<u8>(),
+                0,
+                4096,
+            );
+            match mapper.map_to_with_table_flags(page, frame, flags, table_flags, frame_allocator) {
+                Ok(flush) => flush.flush(),
+                Err(error) => {
+                    frame_allocator.deallocate_frame(frame);
+                    return Err(error);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Switches the calling thread to user mode, where it continues at `entry`
+/// with its stack pointer at `stack_top` and interrupts enabled.
+///
+/// The current stack becomes the kernel stack of the thread, on which the
+/// interrupt handlers run while the thread is in user mode.
+///
+/// This function is unsafe because the caller must guarantee that `entry`
+/// and the stack below `stack_top` are mapped with `map_user_pages`.
+pub unsafe fn enter(entry: VirtAddr, stack_top: VirtAddr) -> ! {
+    x86_64::instructions::interrupts::disable();
+    let rsp: u64;
+    asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack, preserves_flags));
+    // nothing on the stack is needed anymore once we are in user mode
+    thread::set_kernel_stack(VirtAddr::new(rsp & !0xF));
+
+    let selectors = gdt::selectors();
+    // the register state after `iretq` must not leak kernel data
+    asm!(
+        "push {ss}",
+        "push {rsp}",
+        "push {rflags}",
+        "push {cs}",
+        "push {rip}",
+        "xor eax, eax",
+        "xor ebx, ebx",
+        "xor ecx, ecx",
+        "xor edx, edx",
+        "xor esi, esi",
+        "xor edi, edi",
+        "xor ebp, ebp",
+        "xor r8d, r8d",
+        "xor r9d, r9d",
+        "xor r10d, r10d",
+        "xor r11d, r11d",
+        "xor r12d, r12d",
+        "xor r13d, r13d",
+        "xor r14d, r14d",
+        "xor r15d, r15d",
+        // interrupts stay disabled until `iretq`, so no handler runs with
+        // the GS base of user mode in between
+        "swapgs",
+        "iretq",
+        ss = in(reg) u64::from(selectors.user_data.0),
+        rsp = in(reg) stack_top.as_u64(),
+        rflags = in(reg) USER_RFLAGS,
+        cs = in(reg) u64::from(selectors.user_code.0),
+        rip = in(reg) entry.as_u64(),
+        options(noreturn)
+    );
+}
+
+/// Terminates the current process with `process::EXIT_KILLED` after a fault
+/// in user mode, printing `reason`. User threads outside of processes just
+/// exit.
+///
+/// Called by the exception handlers once they no longer count as running
+/// (see `interrupts::in_interrupt`).
+pub(crate) fn kill_current(reason: fmt::Arguments) -> ! {
+    assert!(
+        thread::is_initialized(),
+        "fault in user mode without threads: {}",
+        reason
+    );
+    match process::current() {
+        Some(pid) => {
+            println!("killed process {}: {}", pid, reason);
+            process::exit(process::EXIT_KILLED);
+        }
+        None => {
+            println!("killed thread {}: {}", thread::current().as_u64(), reason);
+            thread::exit();
+        }
+    }
+}
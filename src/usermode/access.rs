@@ -0,0 +1,161 @@
+//! Copying from and to user memory on behalf of user programs.
+//!
+//! The user addresses are checked to lie in user space, but may still be
+//! unmapped or lack permissions. So the copies run in assembly routines whose
+//! accessing instructions are listed in `EXCEPTION_TABLE` together with a
+//! fixup address. When one of them page faults, the page fault handler
+//! continues at the fixup (see `fixup_address`), which returns an error
+//! instead of crashing the kernel.
+
+use super::{is_user_address, is_user_range, USER_SPACE_END, USER_SPACE_START};
+use crate::syscall::Errno;
+use core::arch::global_asm;
+use core::convert::TryFrom;
+use x86_64::VirtAddr;
+
+// `copy_user(dst, src, len) -> usize` copies `len` bytes and returns how many
+// were left when it faulted, 0 on success. `rep movsb` keeps the remaining
+// count in rcx, also when it faults.
+//
+// `strncpy_user(dst, src, max) -> isize` copies bytes up to and including the
+// first null byte, but at most `max`. It returns the length of the string
+// without the null byte, `max` if there was none, or -1 if it faulted.
+//
+// The direction flag is clear in the kernel, both by the calling convention
+// and because `syscall` clears it.
+global_asm!(
+    ".global copy_user",
+    ".global copy_user_access",
+    ".global copy_user_fixup",
+    "copy_user:",
+    "mov rcx, rdx",
+    "copy_user_access:",
+    "rep movsb",
+    "xor eax, eax",
+    "ret",
+    "copy_user_fixup:",
+    "mov rax, rcx",
+    "ret",
+    ".global strncpy_user",
+    ".global strncpy_user_access",
+    ".global strncpy_user_fixup",
+    "strncpy_user:",
+    "xor eax, eax",
+    ".Lstrncpy_user_loop:",
+    "cmp rax, rdx",
+    "je .Lstrncpy_user_done",
+    "strncpy_user_access:",
+    "mov cl, byte ptr [rsi + rax]",
+    "mov byte ptr [rdi + rax], cl",
+    "test cl, cl",
+    "je .Lstrncpy_user_done",
+    "inc rax",
+    "jmp .Lstrncpy_user_loop",
+    ".Lstrncpy_user_done:",
+    "ret",
+    "strncpy_user_fixup:",
+    "mov rax, -1",
+    "ret",
+);
+
+extern "C" {
+    fn copy_user(dst: *mut u8, src: *const u8, len: usize) -> usize;
+    fn copy_user_access();
+    fn copy_user_fixup();
+    fn strncpy_user(dst: *mut u8, src: *const u8, max: usize) -> isize;
+    fn strncpy_user_access();
+    fn strncpy_user_fixup();
+}
+
+/// An instruction that may fault on user memory and where to continue if it
+/// does. The functions only serve as addresses and are never called.
+struct ExceptionTableEntry {
+    instruction: unsafe extern "C" fn(),
+    fixup: unsafe extern "C" fn(),
+}
+
+static EXCEPTION_TABLE: [ExceptionTableEntry; 2] = [
+    ExceptionTableEntry {
+        instruction: copy_user_access,
+        fixup: copy_user_fixup,
+    },
+    ExceptionTableEntry {
+        instruction: strncpy_user_access,
+        fixup: strncpy_user_fixup,
+    },
+];
+
+/// Returns where to continue after a page fault at `instruction_pointer` in
+/// the kernel, or `None` if the instruction does not access user memory.
+pub(crate) fn fixup_address(instruction_pointer: VirtAddr) -> Option<VirtAddr> {
+    EXCEPTION_TABLE
+        .iter()
+        .find(|entry| entry.instruction as usize as u64 == instruction_pointer.as_u64())
+        .map(|entry| VirtAddr::new(entry.fixup as usize as u64))
+}
+
+/// Fills `dst` with the bytes at `src` in user memory.
+///
+/// Fails with `EFAULT` if the bytes do not lie in user space or are not all
+/// mapped.
+pub fn copy_from_user(dst: &mut [u8], src: VirtAddr) -> Result<(), Errno> {
+    if !is_user_range(src, dst.len()) {
+        return Err(Errno::EFAULT);
+    }
+    match unsafe { copy_user(dst.as_mut_ptr(), src.as_ptr(), dst.len()) } {
+        0 => Ok(()),
+        _ => Err(Errno::EFAULT),
+    }
+}
+
+/// Copies `src` to `dst` in user memory.
+///
+/// Fails with `EFAULT` if the bytes do not lie in user space or are not all
+/// mapped writable. The bytes before the first unwritable one are copied
+/// anyway.
+pub fn copy_to_user(dst: VirtAddr, src: &[u8]) -> Result<(), Errno> {
+    if !is_user_range(dst, src.len()) {
+        return Err(Errno::EFAULT);
+    }
+    match unsafe { copy_user(dst.as_mut_ptr(), src.as_ptr(), src.len()) } {
+        0 => Ok(()),
+        _ => Err(Errno::EFAULT),
+    }
+}
+
+/// Copies the null-terminated string at `src` in user memory to `dst`,
+/// including the null byte, and returns its length without it.
+///
+/// If the string does not fit, only `dst.len()` bytes are copied, without a
+/// null byte, and `dst.len()` is returned. Fails with `EFAULT` if a byte of
+/// the string lies outside of user space or is not mapped.
+pub fn strncpy_from_user(dst: &mut [u8], src: VirtAddr) -> Result<usize, Errno> {
+    if !is_user_address(src) {
+        return Err(Errno::EFAULT);
+    }
+    // the string must end in user space
+    let max = dst.len().min((USER_SPACE_END - src.as_u64()) as usize);
+    let len = unsafe { strncpy_user(dst.as_mut_ptr(), src.as_ptr(), max) };
+    match usize::try_from(len) {
+        Ok(len) if len == max && max < dst.len() => Err(Errno::EFAULT),
+        Ok(len) => Ok(len),
+        Err(_) => Err(Errno::EFAULT),
+    }
+}
+
+#[test_case]
+fn test_kernel_addresses_are_rejected() {
+    let mut buffer = [0u8; 8];
+    let kernel = VirtAddr::from_ptr(&buffer);
+    assert_eq!(copy_from_user(&mut [0; 8], kernel), Err(Errno::EFAULT));
+    assert_eq!(copy_to_user(kernel, &[1; 8]), Err(Errno::EFAULT));
+    assert_eq!(strncpy_from_user(&mut buffer, kernel), Err(Errno::EFAULT));
+    assert_eq!(buffer, [0; 8]);
+}
+
+#[test_case]
+fn test_empty_copies_succeed() {
+    let start = VirtAddr::new(USER_SPACE_START);
+    assert_eq!(copy_from_user(&mut [], start), Ok(()));
+    assert_eq!(copy_to_user(start, &[]), Ok(()));
+}
@@ -0,0 +1,183 @@
+//! Deferred work ("bottom halves") for interrupt handlers.
+//!
+//! Interrupt handlers should only acknowledge their device and save what they
+//! need; everything else, such as logging or drawing to the screen, is queued
+//! as a `WorkItem` with `schedule` and runs later with interrupts enabled on
+//! the high-priority `kworker` thread.
+//!
+//! Work items are small and `Copy`, and the queue is preallocated by `init`,
+//! so scheduling work never allocates and is safe in any interrupt handler.
+
+use crate::serial;
+use crate::thread::{self, Priority, ThreadId};
+use crate::time::Instant;
+use conquer_once::spin::OnceCell;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use core::time::Duration;
+use crossbeam_queue::ArrayQueue;
+use spin::Once;
+
+/// The number of work items that can be pending at the same time.
+pub const QUEUE_SIZE: usize = 256;
+
+static QUEUE: OnceCell<ArrayQueue<WorkItem>> = OnceCell::uninit();
+static WORKER: Once<ThreadId> = Once::new();
+
+static QUEUED: AtomicU64 = AtomicU64::new(0);
+static EXECUTED: AtomicU64 = AtomicU64::new(0);
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+static MAX_DEPTH: AtomicUsize = AtomicUsize::new(0);
+static TOTAL_LATENCY_NANOS: AtomicU64 = AtomicU64::new(0);
+static MAX_LATENCY_NANOS: AtomicU64 = AtomicU64::new(0);
+
+/// A deferred function call with one argument.
+#[derive(Debug, Clone, Copy)]
+pub struct WorkItem {
+    func: fn(u64),
+    arg: u64,
+    queued_at: Instant,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkError {
+    /// `init` has not been called yet.
+    NotInitialized,
+    /// `QUEUE_SIZE` items are pending already; the item was dropped.
+    QueueFull,
+}
+
+/// Creates the work queue and starts the `kworker` thread that runs it.
+///
+/// `thread::init` must have been called.
+pub fn init() {
+    QUEUE
+        .try_init_once(|| ArrayQueue::new(QUEUE_SIZE))
+        .expect("workqueue::init should only be called once");
+    WORKER.call_once(|| {
+        let handle = thread::Builder::new()
+            .name("kworker")
+            .priority(Priority::High)
+            .spawn(worker);
+        handle.id()
+    });
+}
+
+/// Queues `func(arg)` to run later in thread context with interrupts enabled.
+///
+/// Can be called from interrupt handlers.
+pub fn schedule(func: fn(u64), arg: u64) -> Result<(), WorkError> {
+    let queue = QUEUE.try_get().map_err(|_| WorkError::NotInitialized)?;
+    let item = WorkItem {
+        func,
+        arg,
+        queued_at: Instant::now(),
+    };
+    if queue.push(item).is_err() {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+        return Err(WorkError::QueueFull);
+    }
+    QUEUED.fetch_add(1, Ordering::Relaxed);
+    MAX_DEPTH.fetch_max(queue.len(), Ordering::Relaxed);
+    if let Some(&worker) = WORKER.get() {
+        thread::unpark(worker);
+    }
+    Ok(())
+}
+
+/// Like `schedule`, but runs `func(arg)` right away if it cannot be queued,
+/// e.g. for diagnostics that should not get lost before `init`.
+pub fn schedule_or_run(func: fn(u64), arg: u64) {
+    if schedule(func, arg).is_err() {
+        func(arg);
+    }
+}
+
+/// Runs all pending work items on the calling thread and returns how many
+/// ran.
+///
+/// The `kworker` thread does this automatically; this function is meant for
+/// code that runs before threads are set up, e.g. a polling loop.
+pub fn run_pending() -> usize {
+    let queue = match QUEUE.try_get() {
+        Ok(queue) => queue,
+        Err(_) => return 0,
+    };
+    let mut count = 0;
+    while let Some(item) = queue.pop() {
+        let latency = item.queued_at.elapsed().as_nanos() as u64;
+        TOTAL_LATENCY_NANOS.fetch_add(latency, Ordering::Relaxed);
+        MAX_LATENCY_NANOS.fetch_max(latency, Ordering::Relaxed);
+        (item.func)(item.arg);
+        EXECUTED.fetch_add(1, Ordering::Relaxed);
+        count += 1;
+    }
+    count
+}
+
+/// The main loop of the `kworker` thread.
+fn worker() {
+    let queue = QUEUE.try_get().expect("work queue not initialized");
+    loop {
+        run_pending();
+        // `schedule` unparks us if work arrives before we sleep
+        if queue.is_empty() {
+            thread::park();
+        }
+    }
+}
+
+/// A snapshot of the work queue statistics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorkQueueStats {
+    pub queued: u64,
+    pub executed: u64,
+    /// Items rejected because the queue was full.
+    pub dropped: u64,
+    /// The number of items pending right now.
+    pub depth: usize,
+    pub max_depth: usize,
+    /// The mean time from `schedule` until the item started running.
+    pub average_latency: Duration,
+    pub max_latency: Duration,
+}
+
+pub fn stats() -> WorkQueueStats {
+    let executed = EXECUTED.load(Ordering::Relaxed);
+    let total_latency = TOTAL_LATENCY_NANOS.load(Ordering::Relaxed);
+    WorkQueueStats {
+        queued: QUEUED.load(Ordering::Relaxed),
+        executed,
+        dropped: DROPPED.load(Ordering::Relaxed),
+        depth: QUEUE.try_get().map_or(0, |queue| queue.len()),
+        max_depth: MAX_DEPTH.load(Ordering::Relaxed),
+        average_latency: Duration::from_nanos(total_latency.checked_div(executed).unwrap_or(0)),
+        max_latency: Duration::from_nanos(MAX_LATENCY_NANOS.load(Ordering::Relaxed)),
+    }
+}
+
+/// Writes the work queue statistics.
+pub fn write_stats(f: &mut impl Write) -> fmt::Result {
+    let stats = stats();
+    writeln!(
+        f,
+        "work queue: {} queued, {} executed, {} dropped",
+        stats.queued, stats.executed, stats.dropped
+    )?;
+    writeln!(
+        f,
+        "depth: {} now, {} max (of {})",
+        stats.depth, stats.max_depth, QUEUE_SIZE
+    )?;
+    writeln!(
+        f,
+        "latency: {} us average, {} us max",
+        stats.average_latency.as_micros(),
+        stats.max_latency.as_micros()
+    )
+}
+
+/// Prints the work queue statistics to the serial port.
+pub fn dump() {
+    write_stats(&mut serial::Writer).unwrap();
+}
@@ -0,0 +1,133 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bootloader::boot_info::FrameBuffer;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use rust_os::task::{timer, Executor, Task};
+use rust_os::thread;
+use rust_os::time::Instant;
+use spin::Mutex;
+
+entry_point!(ktest_main);
+
+fn ktest_main(boot_info: &'static mut BootInfo) -> ! {
+    use rust_os::allocator;
+    use rust_os::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    if let Some(fb) = boot_info.framebuffer.as_mut() {
+        init(fb);
+    }
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset.into_option().unwrap());
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_regions) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    thread::init();
+    rust_os::timer::init();
+
+    test_main();
+    loop {}
+}
+
+pub fn init(fb: &'static mut FrameBuffer) {
+    let fb_info = fb.info();
+
+    unsafe {
+        rust_os::vga_buffer::init_global_writer(fb.buffer_mut(), fb_info);
+    }
+
+    rust_os::init();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn runs_all_tasks() {
+    let counter = Arc::new(AtomicUsize::new(0));
+    let mut executor = Executor::new();
+    for _ in 0..10 {
+        let counter = counter.clone();
+        executor.spawn(Task::new(async move {
+            counter.fetch_add(1, Ordering::SeqCst);
+        }));
+    }
+    executor.run_until_complete();
+    assert_eq!(counter.load(Ordering::SeqCst), 10);
+}
+
+#[test_case]
+fn block_on_returns_output() {
+    let mut executor = Executor::new();
+    assert_eq!(executor.block_on(async { 6 * 7 }), 42);
+}
+
+#[test_case]
+fn spawner_adds_tasks() {
+    let counter = Arc::new(AtomicUsize::new(0));
+    let mut executor = Executor::new();
+    let spawner = executor.spawner();
+    let outer_counter = counter.clone();
+    executor.spawn(Task::new(async move {
+        let counter = outer_counter.clone();
+        spawner.spawn(Task::new(async move {
+            counter.fetch_add(1, Ordering::SeqCst);
+        }));
+        outer_counter.fetch_add(1, Ordering::SeqCst);
+    }));
+    executor.run_until_complete();
+    assert_eq!(counter.load(Ordering::SeqCst), 2);
+}
+
+#[test_case]
+fn sleep_waits_for_deadline() {
+    let mut executor = Executor::new();
+    let start = Instant::now();
+    executor.block_on(timer::sleep(Duration::from_millis(50)));
+    assert!(start.elapsed() >= Duration::from_millis(50));
+}
+
+#[test_case]
+fn sleepers_wake_in_deadline_order() {
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let mut executor = Executor::new();
+    for &millis in &[30u64, 10, 20] {
+        let order = order.clone();
+        executor.spawn(Task::new(async move {
+            timer::sleep(Duration::from_millis(millis)).await;
+            order.lock().push(millis);
+        }));
+    }
+    executor.run_until_complete();
+    assert_eq!(*order.lock(), [10, 20, 30]);
+}
+
+#[test_case]
+fn timeout_passes_through_fast_future() {
+    let mut executor = Executor::new();
+    let result = executor.block_on(timer::timeout(Duration::from_millis(100), async { 7 }));
+    assert_eq!(result, Ok(7));
+}
+
+#[test_case]
+fn timeout_cancels_slow_future() {
+    let mut executor = Executor::new();
+    let start = Instant::now();
+    let slow = timer::sleep(Duration::from_secs(10));
+    let result = executor.block_on(timer::timeout(Duration::from_millis(30), slow));
+    assert_eq!(result, Err(timer::Elapsed));
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
@@ -0,0 +1,275 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::boot_info::FrameBuffer;
+use bootloader::{entry_point, BootInfo};
+use core::convert::TryInto;
+use core::panic::PanicInfo;
+use core::time::Duration;
+use rust_os::elf::{self, loader, ElfError, ElfFile, Program};
+use rust_os::memory::{self, frames, BootInfoFrameAllocator, GlobalFrameAllocator};
+use rust_os::process;
+use rust_os::thread::{self, ThreadState};
+use rust_os::time::Instant;
+use x86_64::structures::paging::mapper::TranslateResult;
+use x86_64::structures::paging::{OffsetPageTable, PageTableFlags, Translate};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// Assembled from `tests/programs` by the build script.
+static EXIT_ELF: &[u8] = include_bytes!(concat!(env!("TEST_PROGRAMS_DIR"), "/exit.elf"));
+static ARGS_ELF: &[u8] = include_bytes!(concat!(env!("TEST_PROGRAMS_DIR"), "/args.elf"));
+
+/// The addresses of the symbols of `args.elf`.
+const ARGS_TEXT: u64 = 0x1000_0040_0000;
+const ARGS_RODATA: u64 = 0x1000_0040_1000;
+const ARGS_RESULT: u64 = 0x1000_0040_2000;
+const ARGS_BUFFER_END: u64 = 0x1000_0040_2008 + 8192;
+
+entry_point!(ktest_main);
+
+fn ktest_main(boot_info: &'static mut BootInfo) -> ! {
+    use rust_os::allocator;
+
+    if let Some(fb) = boot_info.framebuffer.as_mut() {
+        init(fb);
+    }
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset.into_option().unwrap());
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_regions) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    thread::init();
+    frames::init(frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+pub fn init(fb: &'static mut FrameBuffer) {
+    let fb_info = fb.info();
+
+    unsafe {
+        rust_os::vga_buffer::init_global_writer(fb.buffer_mut(), fb_info);
+    }
+
+    rust_os::init();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+fn load(data: &[u8], args: &[&str], env: &[&str]) -> Result<Program, ElfError> {
+    let file = ElfFile::parse(data)?;
+    elf::load(&file, args, env, &mut GlobalFrameAllocator)
+}
+
+fn flags(program: &mut Program, address: u64) -> PageTableFlags {
+    match program
+        .address_space
+        .mapper()
+        .translate(VirtAddr::new(address))
+    {
+        TranslateResult::Mapped { flags, .. } => flags,
+        _ => panic!("{:#x} is not mapped", address),
+    }
+}
+
+fn read_u64(program: &Program, address: u64) -> u64 {
+    let mut bytes = [0; 8];
+    program
+        .address_space
+        .read(VirtAddr::new(address), &mut bytes)
+        .unwrap();
+    u64::from_le_bytes(bytes)
+}
+
+fn read_str(program: &Program, address: u64) -> alloc::string::String {
+    let mut bytes = alloc::vec::Vec::new();
+    let mut byte = [0];
+    for offset in 0.. {
+        program
+            .address_space
+            .read(VirtAddr::new(address + offset), &mut byte)
+            .unwrap();
+        if byte[0] == 0 {
+            break;
+        }
+        bytes.push(byte[0]);
+    }
+    alloc::string::String::from_utf8(bytes).unwrap()
+}
+
+/// Runs `program` on a new thread and waits until it exits, failing the
+/// test after a second.
+fn run(program: Program) {
+    let id = thread::spawn(move || program.run()).id();
+    let start = Instant::now();
+    while thread::state(id).map_or(false, |state| state != ThreadState::Exited) {
+        assert!(
+            start.elapsed() < Duration::from_secs(1),
+            "timed out waiting for the program"
+        );
+        thread::yield_now();
+    }
+}
+
+#[test_case]
+fn parses_program_headers() {
+    let file = ElfFile::parse(ARGS_ELF).unwrap();
+    assert_eq!(file.entry().as_u64(), ARGS_TEXT);
+    let loads: alloc::vec::Vec<_> = file
+        .program_headers()
+        .filter(|header| header.is_load())
+        .collect();
+    assert_eq!(loads.len(), 3);
+    assert!(loads[0].is_executable() && !loads[0].is_writable());
+    assert!(!loads[1].is_executable() && !loads[1].is_writable());
+    assert!(loads[2].is_writable() && !loads[2].is_executable());
+    assert!(loads[2].memory_size > loads[2].file_size);
+}
+
+#[test_case]
+fn rejects_bad_headers() {
+    let mut data: alloc::vec::Vec<u8> = ARGS_ELF.into();
+    // ET_DYN
+    data[16] = 3;
+    assert_eq!(ElfFile::parse(&data).unwrap_err(), ElfError::NotExecutable);
+
+    let mut data: alloc::vec::Vec<u8> = ARGS_ELF.into();
+    // EM_386
+    data[18] = 3;
+    assert_eq!(ElfFile::parse(&data).unwrap_err(), ElfError::WrongMachine);
+
+    let mut data: alloc::vec::Vec<u8> = ARGS_ELF.into();
+    // move the entry point into the data segment
+    data[24..32].copy_from_slice(&ARGS_RESULT.to_le_bytes());
+    assert_eq!(ElfFile::parse(&data).unwrap_err(), ElfError::BadEntryPoint);
+
+    // the program header table runs past the end
+    assert_eq!(
+        ElfFile::parse(&ARGS_ELF[..200]).unwrap_err(),
+        ElfError::Truncated
+    );
+}
+
+#[test_case]
+fn rejects_segments_outside_the_program_area() {
+    let mut data: alloc::vec::Vec<u8> = ARGS_ELF.into();
+    let phoff = u64::from_le_bytes(data[32..40].try_into().unwrap()) as usize;
+    // the virtual address of the first program header, the text segment
+    let vaddr = phoff + 16;
+    data[vaddr..vaddr + 8].copy_from_slice(&0x4444_4444_0000u64.to_le_bytes());
+    assert_eq!(
+        ElfFile::parse(&data).unwrap_err(),
+        ElfError::SegmentOutsideUserSpace
+    );
+
+    // where processes map shared memory
+    data[vaddr..vaddr + 8].copy_from_slice(&loader::PROGRAM_END.to_le_bytes());
+    assert_eq!(
+        ElfFile::parse(&data).unwrap_err(),
+        ElfError::SegmentOutsideUserSpace
+    );
+    // into their heap
+    data[vaddr..vaddr + 8].copy_from_slice(&process::HEAP_START.to_le_bytes());
+    assert_eq!(
+        ElfFile::parse(&data).unwrap_err(),
+        ElfError::SegmentOutsideUserSpace
+    );
+}
+
+#[test_case]
+fn maps_segments_with_their_permissions() {
+    let mut program = load(ARGS_ELF, &["args"], &[]).unwrap();
+    let user = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+
+    let text = flags(&mut program, ARGS_TEXT);
+    assert!(text.contains(user));
+    assert!(!text.intersects(PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE));
+    let rodata = flags(&mut program, ARGS_RODATA);
+    assert!(rodata.contains(user | PageTableFlags::NO_EXECUTE));
+    assert!(!rodata.contains(PageTableFlags::WRITABLE));
+    let data = flags(&mut program, ARGS_RESULT);
+    assert!(data.contains(user | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE));
+
+    assert_eq!(read_u64(&program, ARGS_RESULT), 0xdead_beef);
+    // .bss, which is not in the file
+    assert_eq!(read_u64(&program, ARGS_BUFFER_END - 8), 0);
+    assert!(flags(&mut program, ARGS_BUFFER_END - 8).contains(PageTableFlags::WRITABLE));
+}
+
+#[test_case]
+fn sets_up_the_stack() {
+    let program = load(ARGS_ELF, &["args", "hello"], &["HOME=/"]).unwrap();
+    let sp = program.stack_pointer.as_u64();
+    assert_eq!(sp % 16, 0);
+    assert!(sp < loader::STACK_TOP && sp >= loader::STACK_TOP - loader::STACK_SIZE);
+
+    assert_eq!(read_u64(&program, sp), 2);
+    assert_eq!(read_str(&program, read_u64(&program, sp + 8)), "args");
+    assert_eq!(read_str(&program, read_u64(&program, sp + 16)), "hello");
+    assert_eq!(read_u64(&program, sp + 24), 0);
+    assert_eq!(read_str(&program, read_u64(&program, sp + 32)), "HOME=/");
+    assert_eq!(read_u64(&program, sp + 40), 0);
+
+    let mut entry = None;
+    let mut address = sp + 48;
+    loop {
+        let (kind, value) = (read_u64(&program, address), read_u64(&program, address + 8));
+        if kind == loader::AT_NULL {
+            break;
+        }
+        if kind == loader::AT_ENTRY {
+            entry = Some(value);
+        }
+        address += 16;
+    }
+    assert_eq!(entry, Some(ARGS_TEXT));
+}
+
+#[test_case]
+fn rejects_too_long_arguments() {
+    let long = "x".repeat(loader::STACK_SIZE as usize);
+    let allocated = frames::allocated();
+    assert_eq!(
+        load(ARGS_ELF, &[&long], &[]).err(),
+        Some(ElfError::ArgumentsTooLong)
+    );
+    // the half-loaded address space is freed again
+    assert_eq!(frames::allocated(), allocated);
+}
+
+#[test_case]
+fn runs_program_to_exit() {
+    let program = load(EXIT_ELF, &["exit"], &[]).unwrap();
+    run(program);
+}
+
+#[test_case]
+fn program_sees_its_arguments() {
+    let program = load(ARGS_ELF, &["args", "one", "two"], &[]).unwrap();
+    let level_4_frame = program.address_space.level_4_frame();
+    run(program);
+
+    // the address space outlives the thread, so look at what the program
+    // left in memory through its page tables
+    let mapper = unsafe {
+        OffsetPageTable::new(
+            &mut *memory::phys_to_virt(level_4_frame.start_address()).as_mut_ptr(),
+            memory::phys_to_virt(PhysAddr::new(0)),
+        )
+    };
+    let read = |address: u64| {
+        let phys = mapper.translate_addr(VirtAddr::new(address)).unwrap();
+        unsafe { *memory::phys_to_virt(phys).as_ptr::<u64>() }
+    };
+    assert_eq!(read(ARGS_RESULT), 3);
+    assert_eq!(read(ARGS_BUFFER_END - 8), 0x42);
+}
@@ -0,0 +1,250 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use bootloader::boot_info::FrameBuffer;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_os::ipc::{
+    self, BrokenPipe, ChannelError, Message, CHANNEL_CAPACITY, MAX_MESSAGE_SIZE, PIPE_CAPACITY,
+};
+use rust_os::process::Object;
+use rust_os::thread::{self, ThreadId, ThreadState};
+
+entry_point!(ktest_main);
+
+fn ktest_main(boot_info: &'static mut BootInfo) -> ! {
+    use rust_os::allocator;
+    use rust_os::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    if let Some(fb) = boot_info.framebuffer.as_mut() {
+        init(fb);
+    }
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset.into_option().unwrap());
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_regions) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    thread::init();
+
+    test_main();
+    loop {}
+}
+
+pub fn init(fb: &'static mut FrameBuffer) {
+    let fb_info = fb.info();
+
+    unsafe {
+        rust_os::vga_buffer::init_global_writer(fb.buffer_mut(), fb_info);
+    }
+
+    rust_os::init();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+/// Yields until the given thread sleeps.
+fn wait_until_blocked(id: ThreadId) {
+    while thread::state(id) != Some(ThreadState::Blocked) {
+        thread::yield_now();
+    }
+}
+
+#[test_case]
+fn pipe_returns_buffered_bytes() {
+    let (reader, writer) = ipc::pipe().unwrap();
+    assert_eq!(writer.write(b"hello"), Ok(5));
+    let mut buf = [0; 3];
+    assert_eq!(reader.read(&mut buf), 3);
+    assert_eq!(&buf, b"hel");
+    assert_eq!(reader.read(&mut buf), 2);
+    assert_eq!(&buf[..2], b"lo");
+}
+
+#[test_case]
+fn pipe_read_blocks_while_empty() {
+    let (reader, writer) = ipc::pipe().unwrap();
+    let handle = thread::spawn(move || {
+        let mut buf = [0; 8];
+        let len = reader.read(&mut buf);
+        buf[..len].to_vec()
+    });
+    wait_until_blocked(handle.id());
+
+    writer.write(b"wake").unwrap();
+    assert_eq!(handle.join(), b"wake");
+}
+
+#[test_case]
+fn pipe_write_blocks_while_full() {
+    let (reader, writer) = ipc::pipe().unwrap();
+    let data: Vec<u8> = (0..PIPE_CAPACITY + 100).map(|i| i as u8).collect();
+    let expected = data.clone();
+    let handle = thread::spawn(move || writer.write(&data));
+    wait_until_blocked(handle.id());
+
+    let mut received = vec![0; PIPE_CAPACITY + 100];
+    let mut len = 0;
+    while len < received.len() {
+        len += reader.read(&mut received[len..]);
+    }
+    assert_eq!(handle.join(), Ok(PIPE_CAPACITY + 100));
+    assert_eq!(received, expected);
+}
+
+#[test_case]
+fn pipe_reports_end_of_file_after_buffered_bytes() {
+    let (reader, writer) = ipc::pipe().unwrap();
+    let other_writer = writer.clone();
+    writer.write(b"x").unwrap();
+    drop(writer);
+    drop(other_writer);
+    let mut buf = [0; 4];
+    assert_eq!(reader.read(&mut buf), 1);
+    assert_eq!(reader.read(&mut buf), 0);
+}
+
+#[test_case]
+fn closing_writer_wakes_blocked_reader() {
+    let (reader, writer) = ipc::pipe().unwrap();
+    let handle = thread::spawn(move || reader.read(&mut [0; 4]));
+    wait_until_blocked(handle.id());
+
+    drop(writer);
+    assert_eq!(handle.join(), 0);
+}
+
+#[test_case]
+fn closing_reader_breaks_pipe() {
+    let (reader, writer) = ipc::pipe().unwrap();
+    writer.write(&[0; PIPE_CAPACITY]).unwrap();
+    // blocked on the full pipe
+    let handle = thread::spawn(move || writer.write(b"more"));
+    wait_until_blocked(handle.id());
+
+    drop(reader);
+    assert_eq!(handle.join(), Err(BrokenPipe));
+}
+
+#[test_case]
+fn channel_delivers_messages_in_both_directions() {
+    let (first, second) = ipc::channel();
+    first
+        .send(Message::new(b"ping".to_vec(), Vec::new()))
+        .unwrap();
+    assert_eq!(second.recv().unwrap().data, b"ping");
+    second
+        .send(Message::new(b"pong".to_vec(), Vec::new()))
+        .unwrap();
+    assert_eq!(first.recv().unwrap().data, b"pong");
+}
+
+#[test_case]
+fn channel_carries_objects() {
+    let (first, second) = ipc::channel();
+    let (reader, writer) = ipc::pipe().unwrap();
+    first
+        .send(Message::new(Vec::new(), vec![Object::PipeWriter(writer)]))
+        .unwrap();
+    let message = second.recv().unwrap();
+    let writer = match message.objects.as_slice() {
+        [Object::PipeWriter(writer)] => writer,
+        objects => panic!("unexpected objects {:?}", objects),
+    };
+    writer.write(b"via channel").unwrap();
+    let mut buf = [0; 11];
+    assert_eq!(reader.read(&mut buf), 11);
+    assert_eq!(&buf, b"via channel");
+}
+
+#[test_case]
+fn channel_recv_blocks_while_empty() {
+    let (first, second) = ipc::channel();
+    let handle = thread::spawn(move || second.recv().map(|message| message.data));
+    wait_until_blocked(handle.id());
+
+    first
+        .send(Message::new(b"late".to_vec(), Vec::new()))
+        .unwrap();
+    assert_eq!(handle.join(), Ok(b"late".to_vec()));
+}
+
+#[test_case]
+fn channel_send_blocks_while_full() {
+    let (first, second) = ipc::channel();
+    for i in 0..CHANNEL_CAPACITY {
+        first.send(Message::new(vec![i as u8], Vec::new())).unwrap();
+    }
+    let handle = thread::spawn(move || {
+        first
+            .send(Message::new(vec![CHANNEL_CAPACITY as u8], Vec::new()))
+            .is_ok()
+    });
+    wait_until_blocked(handle.id());
+
+    for i in 0..=CHANNEL_CAPACITY {
+        assert_eq!(second.recv().unwrap().data, [i as u8]);
+    }
+    assert!(handle.join());
+}
+
+#[test_case]
+fn channel_rejects_too_large_messages() {
+    let (first, second) = ipc::channel();
+    let err = first
+        .send(Message::new(vec![0; MAX_MESSAGE_SIZE + 1], Vec::new()))
+        .unwrap_err();
+    assert_eq!(err.error, ChannelError::TooLarge);
+    assert_eq!(err.message.data.len(), MAX_MESSAGE_SIZE + 1);
+
+    first.send(Message::new(vec![0; 8], Vec::new())).unwrap();
+    // a message that does not fit stays queued
+    assert_eq!(second.recv_within(4, 0), Err(ChannelError::TooLarge));
+    assert_eq!(second.recv_within(8, 0).unwrap().data.len(), 8);
+}
+
+#[test_case]
+fn channel_reports_closed_peer() {
+    let (first, second) = ipc::channel();
+    first
+        .send(Message::new(b"last".to_vec(), Vec::new()))
+        .unwrap();
+    drop(first);
+    // queued messages come first
+    assert_eq!(second.recv().unwrap().data, b"last");
+    assert_eq!(second.recv(), Err(ChannelError::PeerClosed));
+    let err = second.send(Message::default()).unwrap_err();
+    assert_eq!(err.error, ChannelError::PeerClosed);
+}
+
+#[test_case]
+fn closing_endpoint_wakes_blocked_receiver() {
+    let (first, second) = ipc::channel();
+    let handle = thread::spawn(move || second.recv());
+    wait_until_blocked(handle.id());
+
+    drop(first);
+    assert_eq!(handle.join(), Err(ChannelError::PeerClosed));
+}
+
+#[test_case]
+fn closing_endpoint_drops_queued_objects() {
+    let (first, second) = ipc::channel();
+    let (reader, writer) = ipc::pipe().unwrap();
+    first
+        .send(Message::new(Vec::new(), vec![Object::PipeWriter(writer)]))
+        .unwrap();
+    // the only write end is queued for `second`
+    drop(second);
+    assert_eq!(reader.read(&mut [0; 4]), 0);
+}
@@ -0,0 +1,113 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::boot_info::FrameBuffer;
+use bootloader::{entry_point, BootInfo};
+use core::arch::asm;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use rust_os::interrupts::irq::{self, IrqError, IrqReturn};
+use x86_64::structures::idt::InterruptStackFrame;
+
+/// IRQ 10 has no device in QEMU's default machine; its vector is 32 + 10.
+const TEST_IRQ: u8 = 10;
+
+/// Raises the vector of `TEST_IRQ` in software.
+fn raise_test_irq() {
+    unsafe { asm!("int 42") };
+}
+
+entry_point!(ktest_main);
+
+fn ktest_main(boot_info: &'static mut BootInfo) -> ! {
+    use rust_os::allocator;
+    use rust_os::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    if let Some(fb) = boot_info.framebuffer.as_mut() {
+        init(fb);
+    }
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset.into_option().unwrap());
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_regions) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+pub fn init(fb: &'static mut FrameBuffer) {
+    let fb_info = fb.info();
+
+    unsafe {
+        rust_os::vga_buffer::init_global_writer(fb.buffer_mut(), fb_info);
+    }
+
+    rust_os::init();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+static FIRST: AtomicUsize = AtomicUsize::new(0);
+static SECOND: AtomicUsize = AtomicUsize::new(0);
+
+fn count_first(_stack_frame: &InterruptStackFrame) -> IrqReturn {
+    FIRST.fetch_add(1, Ordering::SeqCst);
+    IrqReturn::Handled
+}
+
+fn count_second(_stack_frame: &InterruptStackFrame) -> IrqReturn {
+    SECOND.fetch_add(1, Ordering::SeqCst);
+    IrqReturn::Handled
+}
+
+#[test_case]
+fn shared_line_runs_every_handler() {
+    FIRST.store(0, Ordering::SeqCst);
+    SECOND.store(0, Ordering::SeqCst);
+    let first = irq::request_shared_irq(TEST_IRQ, count_first).unwrap();
+    let second = irq::request_shared_irq(TEST_IRQ, count_second).unwrap();
+    assert_eq!(first.vector(), second.vector());
+
+    raise_test_irq();
+    assert_eq!(FIRST.load(Ordering::SeqCst), 1);
+    assert_eq!(SECOND.load(Ordering::SeqCst), 1);
+
+    irq::free_irq(first);
+    raise_test_irq();
+    assert_eq!(FIRST.load(Ordering::SeqCst), 1);
+    assert_eq!(SECOND.load(Ordering::SeqCst), 2);
+
+    irq::free_irq(second);
+}
+
+#[test_case]
+fn exclusive_line_cannot_be_shared() {
+    let handle = irq::request_irq(TEST_IRQ, count_first).unwrap();
+    assert_eq!(
+        irq::request_shared_irq(TEST_IRQ, count_second),
+        Err(IrqError::Busy)
+    );
+    assert_eq!(
+        irq::request_irq(TEST_IRQ, count_second),
+        Err(IrqError::Busy)
+    );
+    irq::free_irq(handle);
+
+    // freeing the last handler releases the line
+    let handle = irq::request_irq(TEST_IRQ, count_second).unwrap();
+    irq::free_irq(handle);
+}
+
+#[test_case]
+fn reserved_and_invalid_lines_are_rejected() {
+    assert_eq!(irq::request_irq(0, count_first), Err(IrqError::Reserved));
+    assert_eq!(irq::request_irq(16, count_first), Err(IrqError::InvalidIrq));
+}
@@ -0,0 +1,151 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_os::memory::frames;
+use rust_os::process::{self, HandleTable, Object, Pid, ProcessState, MAX_HANDLES};
+use rust_os::syscall::{Errno, STDOUT};
+use rust_os::test_support::{self, wait_for};
+use rust_os::thread;
+
+/// Assembled from `tests/programs` by the build script.
+static EXIT_ELF: &[u8] = include_bytes!(concat!(env!("TEST_PROGRAMS_DIR"), "/exit.elf"));
+static ARGS_ELF: &[u8] = include_bytes!(concat!(env!("TEST_PROGRAMS_DIR"), "/args.elf"));
+static GETPID_ELF: &[u8] = include_bytes!(concat!(env!("TEST_PROGRAMS_DIR"), "/getpid.elf"));
+static FAULT_ELF: &[u8] = include_bytes!(concat!(env!("TEST_PROGRAMS_DIR"), "/fault.elf"));
+static SPAWN_ELF: &[u8] = include_bytes!(concat!(env!("TEST_PROGRAMS_DIR"), "/spawn.elf"));
+
+entry_point!(ktest_main);
+
+fn ktest_main(boot_info: &'static mut BootInfo) -> ! {
+    let (_, frame_allocator) = test_support::init(boot_info);
+    thread::init();
+    frames::init(frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn wait_returns_exit_code() {
+    let pid = process::spawn(EXIT_ELF, &["exit"], &[]).unwrap();
+    assert_eq!(process::name(pid).as_deref(), Some("exit"));
+    assert_eq!(process::wait(Some(pid)), Some((pid, 7)));
+    assert_eq!(process::state(pid), None);
+}
+
+#[test_case]
+fn process_gets_its_arguments() {
+    let pid = process::spawn(ARGS_ELF, &["args", "one", "two"], &["KEY=value"]).unwrap();
+    assert_eq!(process::wait(Some(pid)), Some((pid, 3)));
+}
+
+#[test_case]
+fn getpid_returns_pid() {
+    let pid = process::spawn(GETPID_ELF, &["getpid"], &[]).unwrap();
+    let (_, code) = process::wait(Some(pid)).unwrap();
+    assert_eq!(code as u64, pid.as_u64());
+}
+
+#[test_case]
+fn exited_process_stays_zombie_until_waited_for() {
+    let pid = process::spawn(EXIT_ELF, &["exit"], &[]).unwrap();
+    wait_for(|| process::state(pid) != Some(ProcessState::Running));
+    assert_eq!(process::state(pid), Some(ProcessState::Zombie(7)));
+    assert_eq!(process::wait(Some(pid)), Some((pid, 7)));
+}
+
+#[test_case]
+fn wait_for_any_child() {
+    let first = process::spawn(EXIT_ELF, &["exit"], &[]).unwrap();
+    let second = process::spawn(GETPID_ELF, &["getpid"], &[]).unwrap();
+    let mut exited = [
+        process::wait(None).unwrap().0,
+        process::wait(None).unwrap().0,
+    ];
+    exited.sort();
+    assert_eq!(exited, [first, second]);
+    assert_eq!(process::wait(None), None);
+}
+
+#[test_case]
+fn wait_for_unknown_process_fails() {
+    assert_eq!(process::wait(Some(Pid::from_u64(u64::MAX))), None);
+}
+
+#[test_case]
+fn faulting_process_is_killed() {
+    let pid = process::spawn(FAULT_ELF, &["fault"], &[]).unwrap();
+    assert_eq!(process::wait(Some(pid)), Some((pid, process::EXIT_KILLED)));
+
+    // the kernel keeps running processes
+    let pid = process::spawn(EXIT_ELF, &["exit"], &[]).unwrap();
+    assert_eq!(process::wait(Some(pid)), Some((pid, 7)));
+}
+
+#[test_case]
+fn spawn_starts_registered_program() {
+    process::register_program("exit", EXIT_ELF);
+    let pid = process::spawn(SPAWN_ELF, &["spawn", "exit"], &[]).unwrap();
+    assert_eq!(process::wait(Some(pid)), Some((pid, 7)));
+}
+
+#[test_case]
+fn spawn_fails_for_unknown_program() {
+    let pid = process::spawn(SPAWN_ELF, &["spawn", "missing"], &[]).unwrap();
+    let code = -(Errno::ENOENT as i32);
+    assert_eq!(process::wait(Some(pid)), Some((pid, code)));
+}
+
+#[test_case]
+fn spawn_fails_for_invalid_executable() {
+    process::register_program("garbage", b"not an executable");
+    let pid = process::spawn(SPAWN_ELF, &["spawn", "garbage"], &[]).unwrap();
+    let code = -(Errno::ENOEXEC as i32);
+    assert_eq!(process::wait(Some(pid)), Some((pid, code)));
+}
+
+#[test_case]
+fn exit_frees_memory() {
+    let allocated = frames::allocated();
+    let pid = process::spawn(ARGS_ELF, &["args"], &[]).unwrap();
+    assert!(frames::allocated() > allocated);
+    process::wait(Some(pid)).unwrap();
+    assert_eq!(frames::allocated(), allocated);
+}
+
+#[test_case]
+fn handle_table_uses_lowest_free_handle() {
+    let mut table = HandleTable::standard();
+    assert_eq!(table.insert(Object::Console), Ok(0));
+    assert_eq!(table.insert(Object::Console), Ok(3));
+    assert_eq!(table.remove(STDOUT), Some(Object::Console));
+    assert_eq!(table.insert(Object::Console), Ok(STDOUT));
+    assert_eq!(table.get(4), None);
+}
+
+#[test_case]
+fn handle_table_is_limited() {
+    let mut table = HandleTable::standard();
+    assert_eq!(
+        table.insert_all(vec![Object::Console; MAX_HANDLES]),
+        Err(vec![Object::Console; MAX_HANDLES])
+    );
+    assert_eq!(table.free(), MAX_HANDLES - 2);
+    while table.free() > 0 {
+        table.insert(Object::Console).unwrap();
+    }
+    assert_eq!(table.insert(Object::Console), Err(Object::Console));
+}
@@ -0,0 +1,184 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::ptr;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use rust_os::apic::{self, Destination};
+use rust_os::interrupts::irq::{self, IrqReturn};
+use rust_os::memory::{self, BootInfoFrameAllocator};
+use rust_os::test_support::{self, wait_for};
+use rust_os::{percpu, smp};
+use spin::Mutex;
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::structures::paging::{
+    FrameAllocator, Mapper, OffsetPageTable, Page, PageTableFlags, PhysFrame,
+};
+use x86_64::VirtAddr;
+
+/// The page table and frame allocator, for tests that change mappings.
+static MEMORY: Mutex<Option<Memory>> = Mutex::new(None);
+
+struct Memory {
+    mapper: OffsetPageTable<'static>,
+    frame_allocator: BootInfoFrameAllocator,
+}
+
+// only the test thread on the bootstrap processor uses it
+unsafe impl Send for Memory {}
+
+entry_point!(ktest_main);
+
+fn ktest_main(boot_info: &'static mut BootInfo) -> ! {
+    use rust_os::acpi;
+    use x86_64::PhysAddr;
+
+    let rsdp_addr = boot_info.rsdp_addr.into_option().expect("no RSDP");
+    let (mut mapper, mut frame_allocator) = test_support::init(boot_info);
+
+    unsafe { acpi::init(PhysAddr::new(rsdp_addr)) }.expect("ACPI initialization failed");
+    smp::init(&mut mapper, &mut frame_allocator).expect("SMP initialization failed");
+    *MEMORY.lock() = Some(Memory {
+        mapper,
+        frame_allocator,
+    });
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn all_cpus_come_online() {
+    // the test runner starts QEMU with `-smp 4`
+    assert_eq!(smp::cpus().len(), 4);
+    assert!(smp::cpus().iter().all(|cpu| cpu.is_online()));
+    assert_eq!(smp::online_cpus(), 4);
+}
+
+#[test_case]
+fn bootstrap_processor_comes_first() {
+    let cpus = smp::cpus();
+    assert!(cpus[0].is_bootstrap());
+    assert_eq!(cpus[0].apic_id(), apic::id());
+    assert!(cpus[1..].iter().all(|cpu| !cpu.is_bootstrap()));
+}
+
+#[test_case]
+fn apic_ids_are_unique() {
+    let mut ids: Vec<u8> = smp::cpus().iter().map(|cpu| cpu.apic_id()).collect();
+    ids.sort_unstable();
+    ids.dedup();
+    assert_eq!(ids.len(), smp::cpus().len());
+}
+
+static IPI_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+fn count_ipi(_stack_frame: &InterruptStackFrame) -> IrqReturn {
+    IPI_COUNT.fetch_add(1, Ordering::SeqCst);
+    IrqReturn::Handled
+}
+
+#[test_case]
+fn self_ipi_runs_handler() {
+    IPI_COUNT.store(0, Ordering::SeqCst);
+    let handle = irq::request_vector(count_ipi).unwrap();
+    apic::send_ipi(Destination::Myself, handle.vector());
+    wait_for(|| IPI_COUNT.load(Ordering::SeqCst) == 1);
+    irq::free_irq(handle);
+}
+
+#[test_case]
+fn broadcast_ipi_reaches_other_cpus() {
+    IPI_COUNT.store(0, Ordering::SeqCst);
+    let handle = irq::request_vector(count_ipi).unwrap();
+    apic::send_ipi(Destination::AllExcludingSelf, handle.vector());
+    wait_for(|| IPI_COUNT.load(Ordering::SeqCst) == smp::online_cpus() - 1);
+    irq::free_irq(handle);
+}
+
+#[test_case]
+fn nmi_is_delivered() {
+    use rust_os::interrupts::stats;
+
+    let before = stats::count(2);
+    apic::send_nmi(Destination::Myself);
+    wait_for(|| stats::count(2) == before + 1);
+}
+
+const TEST_PAGE: u64 = 0x_5555_0000_0000;
+
+/// The value each CPU last read from `TEST_PAGE`.
+static SEEN: [AtomicU64; smp::MAX_CPUS] = {
+    const ZERO: AtomicU64 = AtomicU64::new(0);
+    [ZERO; smp::MAX_CPUS]
+};
+
+fn read_test_page(_stack_frame: &InterruptStackFrame) -> IrqReturn {
+    let value = unsafe { ptr::read_volatile(TEST_PAGE as *const u64) };
+    SEEN[percpu::cpu_index()].store(value, Ordering::SeqCst);
+    IPI_COUNT.fetch_add(1, Ordering::SeqCst);
+    IrqReturn::Handled
+}
+
+/// Lets every other CPU read `TEST_PAGE` and returns what they saw.
+fn read_on_other_cpus(vector: u8) -> Vec<u64> {
+    IPI_COUNT.store(0, Ordering::SeqCst);
+    apic::send_ipi(Destination::AllExcludingSelf, vector);
+    wait_for(|| IPI_COUNT.load(Ordering::SeqCst) == smp::online_cpus() - 1);
+    (1..smp::cpus().len())
+        .map(|index| SEEN[index].load(Ordering::SeqCst))
+        .collect()
+}
+
+fn map_test_page(
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut BootInfoFrameAllocator,
+    value: u64,
+) -> PhysFrame {
+    let frame = frame_allocator.allocate_frame().unwrap();
+    unsafe { *memory::phys_to_virt(frame.start_address()).as_mut_ptr::<u64>() = value };
+    let page = Page::containing_address(VirtAddr::new(TEST_PAGE));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    unsafe { mapper.map_to(page, frame, flags, frame_allocator) }
+        .unwrap()
+        .flush();
+    frame
+}
+
+#[test_case]
+fn remapped_page_is_seen_by_all_cpus() {
+    let mut memory = MEMORY.lock();
+    let Memory {
+        mapper,
+        frame_allocator,
+    } = memory.as_mut().unwrap();
+    let handle = irq::request_vector(read_test_page).unwrap();
+    let page = Page::containing_address(VirtAddr::new(TEST_PAGE));
+
+    map_test_page(mapper, frame_allocator, 1);
+    assert!(read_on_other_cpus(handle.vector())
+        .iter()
+        .all(|&value| value == 1));
+
+    // without a shootdown, the other CPUs would still read the old frame
+    memory::unmap(mapper, page).unwrap();
+    map_test_page(mapper, frame_allocator, 2);
+    assert!(read_on_other_cpus(handle.vector())
+        .iter()
+        .all(|&value| value == 2));
+
+    memory::unmap(mapper, page).unwrap();
+    irq::free_irq(handle);
+}
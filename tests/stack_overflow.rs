@@ -35,6 +35,7 @@ entry_point!(ktest_main);
 #[allow(unused_variables, unreachable_code)]
 fn ktest_main(boot_info: &'static mut BootInfo) -> ! {
     serial_print!("stack_overflow::stack_overflow...\t");
+    rust_os::percpu::init();
     rust_os::gdt::init();
     init_test_idt();
     stack_overflow();
@@ -0,0 +1,308 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bootloader::boot_info::FrameBuffer;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use rust_os::sync::{channel, Condvar, Event, Mutex, RecvError, RwLock, Semaphore};
+use rust_os::thread::{self, ThreadId, ThreadState};
+
+entry_point!(ktest_main);
+
+fn ktest_main(boot_info: &'static mut BootInfo) -> ! {
+    use rust_os::allocator;
+    use rust_os::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    if let Some(fb) = boot_info.framebuffer.as_mut() {
+        init(fb);
+    }
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset.into_option().unwrap());
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_regions) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    thread::init();
+
+    test_main();
+    loop {}
+}
+
+pub fn init(fb: &'static mut FrameBuffer) {
+    let fb_info = fb.info();
+
+    unsafe {
+        rust_os::vga_buffer::init_global_writer(fb.buffer_mut(), fb_info);
+    }
+
+    rust_os::init();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+/// Yields until the given thread sleeps, so that waiters queue up in a known order.
+fn wait_until_blocked(id: ThreadId) {
+    while thread::state(id) != Some(ThreadState::Blocked) {
+        thread::yield_now();
+    }
+}
+
+#[test_case]
+fn mutex_hands_over_in_fifo_order() {
+    let mutex = Arc::new(Mutex::new(Vec::new()));
+    let guard = mutex.lock();
+    let handles: Vec<_> = (0..4)
+        .map(|i| {
+            let mutex = mutex.clone();
+            let handle = thread::spawn(move || mutex.lock().push(i));
+            wait_until_blocked(handle.id());
+            handle
+        })
+        .collect();
+    drop(guard);
+    for handle in handles {
+        handle.join();
+    }
+    assert_eq!(*mutex.lock(), [0, 1, 2, 3]);
+}
+
+#[test_case]
+fn mutex_protects_counter() {
+    let counter = Arc::new(Mutex::new(0));
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let counter = counter.clone();
+            thread::spawn(move || {
+                for _ in 0..100 {
+                    let mut guard = counter.lock();
+                    let value = *guard;
+                    // invite a switch while holding the lock
+                    thread::yield_now();
+                    *guard = value + 1;
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join();
+    }
+    assert_eq!(*counter.lock(), 400);
+}
+
+#[test_case]
+fn rwlock_admits_readers_together() {
+    let lock = Arc::new(RwLock::new(0));
+    let holding = Arc::new(AtomicUsize::new(0));
+    let write_guard = lock.write();
+    let readers: Vec<_> = (0..3)
+        .map(|_| {
+            let (lock, holding) = (lock.clone(), holding.clone());
+            let handle = thread::spawn(move || {
+                let guard = lock.read();
+                holding.fetch_add(1, Ordering::SeqCst);
+                // only returns if all readers hold the lock at the same time
+                while holding.load(Ordering::SeqCst) < 3 {
+                    thread::yield_now();
+                }
+                *guard
+            });
+            wait_until_blocked(handle.id());
+            handle
+        })
+        .collect();
+    drop(write_guard);
+    for handle in readers {
+        assert_eq!(handle.join(), 0);
+    }
+}
+
+#[test_case]
+fn rwlock_queues_readers_behind_writer() {
+    let lock = Arc::new(RwLock::new(Vec::new()));
+    let read_guard = lock.read();
+
+    let writer_lock = lock.clone();
+    let writer = thread::spawn(move || writer_lock.write().push("writer"));
+    wait_until_blocked(writer.id());
+
+    // a waiting writer keeps new readers out
+    assert!(lock.try_read().is_none());
+    let reader_lock = lock.clone();
+    let reader = thread::spawn(move || reader_lock.read().len());
+    wait_until_blocked(reader.id());
+
+    drop(read_guard);
+    writer.join();
+    assert_eq!(reader.join(), 1);
+}
+
+#[test_case]
+fn semaphore_limits_concurrency() {
+    let semaphore = Arc::new(Semaphore::new(2));
+    let active = Arc::new(AtomicUsize::new(0));
+    let max_active = Arc::new(AtomicUsize::new(0));
+    let handles: Vec<_> = (0..6)
+        .map(|_| {
+            let (semaphore, active, max_active) =
+                (semaphore.clone(), active.clone(), max_active.clone());
+            thread::spawn(move || {
+                semaphore.acquire();
+                let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                max_active.fetch_max(now, Ordering::SeqCst);
+                for _ in 0..3 {
+                    thread::yield_now();
+                }
+                active.fetch_sub(1, Ordering::SeqCst);
+                semaphore.release();
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join();
+    }
+    assert_eq!(max_active.load(Ordering::SeqCst), 2);
+    assert_eq!(semaphore.available_permits(), 2);
+}
+
+#[test_case]
+fn semaphore_wakes_waiters_in_fifo_order() {
+    let semaphore = Arc::new(Semaphore::new(0));
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let handles: Vec<_> = (0..3)
+        .map(|i| {
+            let (semaphore, order) = (semaphore.clone(), order.clone());
+            let handle = thread::spawn(move || {
+                semaphore.acquire();
+                order.lock().push(i);
+            });
+            wait_until_blocked(handle.id());
+            handle
+        })
+        .collect();
+    for _ in 0..3 {
+        semaphore.release();
+    }
+    for handle in handles {
+        handle.join();
+    }
+    assert_eq!(*order.lock(), [0, 1, 2]);
+}
+
+#[test_case]
+fn condvar_wakes_waiter() {
+    let pair = Arc::new((Mutex::new(false), Condvar::new()));
+    let thread_pair = pair.clone();
+    let waiter = thread::spawn(move || {
+        let (ready, condvar) = &*thread_pair;
+        let guard = condvar.wait_while(ready.lock(), |ready| !*ready);
+        *guard
+    });
+    wait_until_blocked(waiter.id());
+
+    let (ready, condvar) = &*pair;
+    *ready.lock() = true;
+    condvar.notify_one();
+    assert!(waiter.join());
+}
+
+#[test_case]
+fn condvar_notify_all_wakes_everyone() {
+    let pair = Arc::new((Mutex::new(0), Condvar::new()));
+    let handles: Vec<_> = (0..3)
+        .map(|_| {
+            let pair = pair.clone();
+            let handle = thread::spawn(move || {
+                let (generation, condvar) = &*pair;
+                let _guard = condvar.wait_while(generation.lock(), |g| *g == 0);
+            });
+            wait_until_blocked(handle.id());
+            handle
+        })
+        .collect();
+    let (generation, condvar) = &*pair;
+    *generation.lock() += 1;
+    condvar.notify_all();
+    for handle in handles {
+        handle.join();
+    }
+}
+
+#[test_case]
+fn event_releases_all_waiters() {
+    let event = Arc::new(Event::new());
+    let handles: Vec<_> = (0..3)
+        .map(|_| {
+            let event = event.clone();
+            let handle = thread::spawn(move || event.wait());
+            wait_until_blocked(handle.id());
+            handle
+        })
+        .collect();
+    event.set();
+    for handle in handles {
+        handle.join();
+    }
+    // a set event stays set
+    event.wait();
+    assert!(event.is_set());
+}
+
+#[test_case]
+fn channel_delivers_all_messages() {
+    let (sender, receiver) = channel(2);
+    let producers: Vec<_> = (0..3)
+        .map(|p| {
+            let sender = sender.clone();
+            thread::spawn(move || {
+                for i in 0..10 {
+                    sender.send(p * 100 + i).unwrap();
+                }
+            })
+        })
+        .collect();
+    drop(sender);
+    let consumers: Vec<_> = (0..2)
+        .map(|_| {
+            let receiver = receiver.clone();
+            thread::spawn(move || {
+                let mut sum = 0;
+                while let Ok(value) = receiver.recv() {
+                    sum += value;
+                }
+                sum
+            })
+        })
+        .collect();
+    drop(receiver);
+
+    for producer in producers {
+        producer.join();
+    }
+    let total: u64 = consumers.into_iter().map(|c| c.join()).sum();
+    assert_eq!(total, (0..3).map(|p| p * 1000 + 45).sum());
+}
+
+#[test_case]
+fn channel_blocks_sender_when_full() {
+    let (sender, receiver) = channel(1);
+    sender.send(1).unwrap();
+    let handle = thread::spawn(move || sender.send(2).unwrap());
+    wait_until_blocked(handle.id());
+
+    assert_eq!(receiver.recv(), Ok(1));
+    handle.join();
+    assert_eq!(receiver.recv(), Ok(2));
+    // the sender is gone
+    assert_eq!(receiver.recv(), Err(RecvError));
+}
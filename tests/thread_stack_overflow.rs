@@ -0,0 +1,93 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use lazy_static::lazy_static;
+use rust_os::{exit_qemu, serial_print, serial_println, thread, QemuExitCode};
+use volatile::Volatile;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        // the faulting thread has no stack left for the exception frame
+        unsafe {
+            idt.page_fault
+                .set_handler_fn(test_page_fault_handler)
+                .set_stack_index(rust_os::gdt::DOUBLE_FAULT_IST_INDEX);
+        }
+        idt
+    };
+}
+
+/// An address near the top of the overflowing thread's stack.
+static STACK_TOP: AtomicU64 = AtomicU64::new(0);
+
+entry_point!(ktest_main);
+
+fn ktest_main(boot_info: &'static mut BootInfo) -> ! {
+    use rust_os::allocator;
+    use rust_os::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    serial_print!("thread_stack_overflow::thread_stack_overflow...\t");
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset.into_option().unwrap());
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_regions) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    rust_os::init();
+    // no interrupt must end up in the test IDT, also not once the thread
+    // enables interrupts
+    x86_64::instructions::interrupts::disable();
+    for irq in 0..rust_os::interrupts::irq::PIC_IRQ_COUNT {
+        rust_os::interrupts::mask_irq(irq);
+    }
+    TEST_IDT.load();
+    thread::init();
+
+    thread::spawn(|| {
+        let marker = 0u8;
+        STACK_TOP.store(&marker as *const u8 as u64, Ordering::SeqCst);
+        stack_overflow();
+    })
+    .join();
+    panic!("Execution continued after stack overflow");
+}
+
+#[allow(unconditional_recursion)]
+fn stack_overflow() {
+    stack_overflow(); // for each recursion, the return address is pushed
+    let value = 42;
+    Volatile::new(&value).read();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+extern "x86-interrupt" fn test_page_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    _error_code: PageFaultErrorCode,
+) {
+    use x86_64::registers::control::Cr2;
+
+    // the fault must hit the guard page right below the stack, not memory
+    // further down that the thread ran over
+    let fault = Cr2::read().as_u64();
+    let top = STACK_TOP.load(Ordering::SeqCst);
+    if fault < top && top - fault <= (thread::STACK_SIZE + 4096) as u64 {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]");
+        serial_println!("fault at {:#x}, stack top at {:#x}", fault, top);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    loop {}
+}
@@ -0,0 +1,188 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bootloader::boot_info::FrameBuffer;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+use rust_os::thread::{self, Builder, Priority, ThreadState};
+use spin::Mutex;
+
+entry_point!(ktest_main);
+
+fn ktest_main(boot_info: &'static mut BootInfo) -> ! {
+    use rust_os::allocator;
+    use rust_os::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    if let Some(fb) = boot_info.framebuffer.as_mut() {
+        init(fb);
+    }
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset.into_option().unwrap());
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_regions) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    thread::init();
+
+    test_main();
+    loop {}
+}
+
+pub fn init(fb: &'static mut FrameBuffer) {
+    let fb_info = fb.info();
+
+    unsafe {
+        rust_os::vga_buffer::init_global_writer(fb.buffer_mut(), fb_info);
+    }
+
+    rust_os::init();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn join_returns_result() {
+    let handle = thread::spawn(|| 6 * 7);
+    assert_eq!(handle.join(), 42);
+}
+
+#[test_case]
+fn current_is_the_spawned_thread() {
+    let handle = thread::spawn(thread::current);
+    let id = handle.id();
+    assert_eq!(handle.join(), id);
+    assert_ne!(thread::current(), id);
+}
+
+#[test_case]
+fn yielding_threads_interleave() {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let handles: Vec<_> = ['a', 'b']
+        .iter()
+        .map(|&name| {
+            let log = log.clone();
+            thread::spawn(move || {
+                for _ in 0..5 {
+                    log.lock().push(name);
+                    thread::yield_now();
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join();
+    }
+
+    let log = log.lock();
+    assert_eq!(log.len(), 10);
+    let first_b = log.iter().position(|&c| c == 'b').unwrap();
+    let last_a = log.iter().rposition(|&c| c == 'a').unwrap();
+    assert!(first_b < last_a, "threads did not interleave: {:?}", *log);
+}
+
+#[test_case]
+fn timer_preempts_busy_thread() {
+    // the spinning thread never yields, so the second thread can only set the
+    // flag if the timer interrupt switches away from the first one
+    let flag = Arc::new(AtomicBool::new(false));
+    let spinner_flag = flag.clone();
+    let spinner = thread::spawn(move || {
+        while !spinner_flag.load(Ordering::SeqCst) {
+            core::hint::spin_loop();
+        }
+    });
+    let setter = thread::spawn(move || flag.store(true, Ordering::SeqCst));
+    spinner.join();
+    setter.join();
+}
+
+#[test_case]
+fn park_blocks_until_unpark() {
+    let woken = Arc::new(AtomicBool::new(false));
+    let thread_woken = woken.clone();
+    let handle = thread::spawn(move || {
+        while !thread_woken.load(Ordering::SeqCst) {
+            thread::park();
+        }
+    });
+    let id = handle.id();
+    while thread::state(id) != Some(ThreadState::Blocked) {
+        thread::yield_now();
+    }
+
+    woken.store(true, Ordering::SeqCst);
+    thread::unpark(id);
+    handle.join();
+    assert_eq!(thread::state(id), None);
+}
+
+#[test_case]
+fn higher_priority_runs_first() {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let spawn = |priority| {
+        let log = log.clone();
+        Builder::new()
+            .priority(priority)
+            .spawn(move || log.lock().push(priority))
+    };
+    let handles = alloc::vec![
+        spawn(Priority::Low),
+        spawn(Priority::Normal),
+        spawn(Priority::High),
+    ];
+    // lower our own priority so that all of them run before we continue
+    let main = thread::current();
+    thread::set_priority(main, Priority::Low);
+    thread::yield_now();
+    thread::set_priority(main, Priority::Normal);
+    for handle in handles {
+        handle.join();
+    }
+
+    assert_eq!(
+        *log.lock(),
+        [Priority::High, Priority::Normal, Priority::Low]
+    );
+}
+
+#[test_case]
+fn cpu_time_is_accounted() {
+    let handle = Builder::new().name("busy").spawn(|| {
+        let start = rust_os::time::ticks();
+        // spin through a few time slices to get preempted
+        while rust_os::time::ticks() < start + 20 {
+            core::hint::spin_loop();
+        }
+        thread::current()
+    });
+    let id = handle.id();
+    while thread::state(id) != Some(ThreadState::Exited) {
+        thread::yield_now();
+    }
+
+    let info = thread::stats::threads()
+        .into_iter()
+        .find(|info| info.id == id)
+        .unwrap();
+    assert_eq!(info.name, "busy");
+    assert!(info.cpu_time.as_millis() >= 100);
+    assert!(info.involuntary_switches > 0);
+    assert!(info.voluntary_switches > 0);
+
+    let mut table = String::new();
+    thread::stats::write_table(&mut table).unwrap();
+    assert!(table.contains("busy"));
+    assert_eq!(handle.join(), id);
+}
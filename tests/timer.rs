@@ -0,0 +1,125 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bootloader::boot_info::FrameBuffer;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use rust_os::sync::{Event, Mutex};
+use rust_os::thread;
+use rust_os::time::{self, Instant};
+use rust_os::timer;
+
+entry_point!(ktest_main);
+
+fn ktest_main(boot_info: &'static mut BootInfo) -> ! {
+    use rust_os::allocator;
+    use rust_os::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    if let Some(fb) = boot_info.framebuffer.as_mut() {
+        init(fb);
+    }
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset.into_option().unwrap());
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_regions) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    thread::init();
+    timer::init();
+
+    test_main();
+    loop {}
+}
+
+pub fn init(fb: &'static mut FrameBuffer) {
+    let fb_info = fb.info();
+
+    unsafe {
+        rust_os::vga_buffer::init_global_writer(fb.buffer_mut(), fb_info);
+    }
+
+    rust_os::init();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn oneshot_fires_after_delay() {
+    let event = Arc::new(Event::new());
+    let timer_event = event.clone();
+    let start = Instant::now();
+    let _handle = timer::add_oneshot(Duration::from_millis(50), move || timer_event.set());
+    event.wait();
+    assert!(start.elapsed() >= Duration::from_millis(50));
+}
+
+#[test_case]
+fn oneshots_fire_in_deadline_order() {
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let done = Arc::new(Event::new());
+    let handles: Vec<_> = [30u64, 10, 20]
+        .iter()
+        .map(|&millis| {
+            let (order, done) = (order.clone(), done.clone());
+            timer::add_oneshot(Duration::from_millis(millis), move || {
+                let mut order = order.lock();
+                order.push(millis);
+                if order.len() == 3 {
+                    done.set();
+                }
+            })
+        })
+        .collect();
+    done.wait();
+    assert_eq!(*order.lock(), [10, 20, 30]);
+    assert!(handles.iter().all(|handle| !handle.is_active()));
+}
+
+#[test_case]
+fn cancelled_oneshot_does_not_fire() {
+    let fired = Arc::new(AtomicUsize::new(0));
+    let timer_fired = fired.clone();
+    let handle = timer::add_oneshot(Duration::from_millis(20), move || {
+        timer_fired.fetch_add(1, Ordering::SeqCst);
+    });
+    assert!(handle.cancel());
+    assert!(!handle.cancel());
+
+    let start = time::ticks();
+    while time::ticks() < start + 5 {
+        thread::yield_now();
+    }
+    assert_eq!(fired.load(Ordering::SeqCst), 0);
+}
+
+#[test_case]
+fn periodic_fires_until_cancelled() {
+    let count = Arc::new(AtomicUsize::new(0));
+    let timer_count = count.clone();
+    let handle = timer::add_periodic(Duration::from_millis(10), move || {
+        timer_count.fetch_add(1, Ordering::SeqCst);
+    });
+    while count.load(Ordering::SeqCst) < 3 {
+        thread::yield_now();
+    }
+    assert!(handle.cancel());
+    let after_cancel = count.load(Ordering::SeqCst);
+
+    let start = time::ticks();
+    while time::ticks() < start + 5 {
+        thread::yield_now();
+    }
+    assert_eq!(count.load(Ordering::SeqCst), after_cancel);
+}
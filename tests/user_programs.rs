@@ -0,0 +1,169 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_os::ipc::{self, Message};
+use rust_os::memory::{frames, SharedMemory};
+use rust_os::process::{self, Object, Pid};
+use rust_os::test_support::{self, wait_for};
+use rust_os::thread;
+
+/// The example programs of the `user` crate, built by `build.rs` with the
+/// `user-programs` feature.
+static HELLO: &[u8] = include_bytes!(concat!(env!("USER_PROGRAMS_DIR"), "/hello"));
+static ARGS: &[u8] = include_bytes!(concat!(env!("USER_PROGRAMS_DIR"), "/args"));
+static HEAP: &[u8] = include_bytes!(concat!(env!("USER_PROGRAMS_DIR"), "/heap"));
+static IPC: &[u8] = include_bytes!(concat!(env!("USER_PROGRAMS_DIR"), "/ipc"));
+static SHM: &[u8] = include_bytes!(concat!(env!("USER_PROGRAMS_DIR"), "/shm"));
+static PANIC: &[u8] = include_bytes!(concat!(env!("USER_PROGRAMS_DIR"), "/panic"));
+static SPAWN: &[u8] = include_bytes!(concat!(env!("USER_PROGRAMS_DIR"), "/spawn"));
+
+/// The exit code of the runtime's panic handler.
+const PANIC_EXIT_CODE: i32 = 101;
+
+entry_point!(ktest_main);
+
+fn ktest_main(boot_info: &'static mut BootInfo) -> ! {
+    let (_, frame_allocator) = test_support::init(boot_info);
+    thread::init();
+    frames::init(frame_allocator);
+    // for the `spawn` program
+    process::register_program("hello", HELLO);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+/// Runs `program` to its exit and returns the exit code.
+fn run(program: &[u8], args: &[&str], env: &[&str]) -> i32 {
+    let pid = process::spawn(program, args, env).unwrap();
+    let (_, code) = process::wait(Some(pid)).unwrap();
+    code
+}
+
+#[test_case]
+fn hello_exits_with_zero() {
+    assert_eq!(run(HELLO, &["hello"], &[]), 0);
+}
+
+#[test_case]
+fn args_sees_its_arguments() {
+    assert_eq!(run(ARGS, &["args", "one", "two"], &["KEY=value"]), 3);
+}
+
+#[test_case]
+fn heap_grows_and_is_freed() {
+    let allocated = frames::allocated();
+    assert_eq!(run(HEAP, &["heap"], &[]), 0);
+    assert_eq!(frames::allocated(), allocated);
+}
+
+#[test_case]
+fn process_spawns_and_waits_for_child() {
+    let allocated = frames::allocated();
+    assert_eq!(run(SPAWN, &["spawn", "wait"], &[]), 0);
+    assert_eq!(frames::allocated(), allocated);
+}
+
+#[test_case]
+fn orphan_is_removed_when_it_exits() {
+    let child = Pid::from_u64(run(SPAWN, &["spawn", "orphan"], &[]) as u64);
+    // only its parent could have waited for it
+    assert_eq!(process::wait(Some(child)), None);
+    wait_for(|| process::state(child).is_none());
+}
+
+#[test_case]
+fn panic_exits_with_error_code() {
+    assert_eq!(run(PANIC, &["panic"], &[]), PANIC_EXIT_CODE);
+}
+
+#[test_case]
+fn ipc_works_through_system_calls() {
+    assert_eq!(run(IPC, &["ipc"], &[]), 0);
+}
+
+/// Runs the `shm` program in `mode` and sends it `memory`.
+fn run_shm(memory: &SharedMemory, mode: &str) -> i32 {
+    let (kernel_end, process_end) = ipc::channel();
+    let pid =
+        process::spawn_with_handles(SHM, &["shm", mode], &[], vec![Object::Channel(process_end)])
+            .unwrap();
+    let message = Message::new(Vec::new(), vec![Object::SharedMemory(memory.clone())]);
+    kernel_end.send(message).unwrap();
+    let (_, code) = process::wait(Some(pid)).unwrap();
+    code
+}
+
+#[test_case]
+fn shared_memory_is_freed_with_last_clone() {
+    let allocated = frames::allocated();
+    let memory = SharedMemory::new(5000).unwrap();
+    assert_eq!(memory.size(), 8192);
+    assert_eq!(frames::allocated(), allocated + 2);
+
+    let clone = memory.clone();
+    // across the page boundary
+    clone.write(4090, b"shared bytes");
+    let mut buffer = [1; 12];
+    memory.read(4090, &mut buffer);
+    assert_eq!(&buffer, b"shared bytes");
+    memory.read(0, &mut buffer);
+    assert_eq!(buffer, [0; 12]);
+
+    drop(memory);
+    assert_eq!(frames::allocated(), allocated + 2);
+    drop(clone);
+    assert_eq!(frames::allocated(), allocated);
+}
+
+#[test_case]
+fn shared_memory_is_shared_between_processes() {
+    let allocated = frames::allocated();
+    let memory = SharedMemory::new(4096).unwrap();
+    memory.write(0, b"from the kernel");
+    assert_eq!(run_shm(&memory, "write"), 0);
+    assert_eq!(run_shm(&memory, "read"), 0);
+
+    let mut buffer = [0; 15];
+    memory.read(64, &mut buffer);
+    assert_eq!(&buffer, b"from user space");
+    drop(memory);
+    assert_eq!(frames::allocated(), allocated);
+}
+
+#[test_case]
+fn huge_shared_memory_is_out_of_memory() {
+    assert!(SharedMemory::new(usize::MAX).is_none());
+    let allocated = frames::allocated();
+    let memory = SharedMemory::new(4096).unwrap();
+    assert_eq!(run_shm(&memory, "huge"), 0);
+    drop(memory);
+    assert_eq!(frames::allocated(), allocated);
+}
+
+#[test_case]
+fn read_only_shared_memory_is_not_writable() {
+    let allocated = frames::allocated();
+    let memory = SharedMemory::new(4096).unwrap();
+    // the process is killed with the memory still mapped
+    assert_eq!(run_shm(&memory, "read-only"), process::EXIT_KILLED);
+    let mut buffer = [1; 1];
+    memory.read(0, &mut buffer);
+    assert_eq!(buffer, [0]);
+    drop(memory);
+    assert_eq!(frames::allocated(), allocated);
+}
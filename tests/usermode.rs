@@ -0,0 +1,435 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::ptr;
+use core::time::Duration;
+use rust_os::memory;
+use rust_os::memory::frames::{self, GlobalFrameAllocator};
+use rust_os::syscall::{self, Errno};
+use rust_os::test_support::{self, wait_for};
+use rust_os::thread::{self, ThreadId, ThreadState};
+use rust_os::time::Instant;
+use rust_os::usermode::{self, USER_SPACE_START};
+use spin::Mutex;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{OffsetPageTable, Page, PageTableFlags, Size4KiB, Translate};
+use x86_64::VirtAddr;
+
+/// The page table, for mapping the test programs.
+static MEMORY: Mutex<Option<Memory>> = Mutex::new(None);
+
+struct Memory {
+    mapper: OffsetPageTable<'static>,
+}
+
+// only the test thread uses it
+unsafe impl Send for Memory {}
+
+entry_point!(ktest_main);
+
+fn ktest_main(boot_info: &'static mut BootInfo) -> ! {
+    let (mapper, frame_allocator) = test_support::init(boot_info);
+    frames::init(frame_allocator);
+    thread::init();
+    rust_os::timer::init();
+    *MEMORY.lock() = Some(Memory { mapper });
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+/// Each test program gets a code page, a data page and a stack page at
+/// `USER_SPACE_START + slot * SLOT_SIZE`.
+const SLOT_SIZE: u64 = 0x10000;
+
+struct Program {
+    code: VirtAddr,
+    data: VirtAddr,
+    stack_top: VirtAddr,
+}
+
+/// Maps the pages of the program in the given slot.
+fn map_program(slot: u64) -> Program {
+    let mut memory = MEMORY.lock();
+    let Memory { mapper } = memory.as_mut().unwrap();
+    let base = VirtAddr::new(USER_SPACE_START + slot * SLOT_SIZE);
+    let program = Program {
+        code: base,
+        data: base + 0x1000u64,
+        stack_top: base + 0x3000u64,
+    };
+    let data_flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    for &(start, flags) in &[
+        (program.code, PageTableFlags::empty()),
+        (program.data, data_flags),
+        (program.stack_top - 0x1000u64, data_flags),
+    ] {
+        let page = Page::<Size4KiB>::containing_address(start);
+        usermode::map_user_pages(
+            mapper,
+            &mut GlobalFrameAllocator,
+            Page::range(page, page + 1),
+            flags,
+        )
+        .expect("mapping user page failed");
+    }
+    program
+}
+
+/// Copies `code` to the start of the code page, which is read-only, through
+/// the mapping of the physical memory.
+fn write_code(program: &Program, code: &[u8]) {
+    let frame = MEMORY
+        .lock()
+        .as_ref()
+        .unwrap()
+        .mapper
+        .translate_addr(program.code)
+        .unwrap();
+    unsafe {
+        ptr::copy_nonoverlapping(
+            code.as_ptr(),
+            memory::phys_to_virt(frame).as_mut_ptr(),
+            code.len(),
+        );
+    }
+}
+
+fn spawn(program: &Program) -> ThreadId {
+    let (entry, stack_top) = (program.code, program.stack_top);
+    thread::spawn(move || unsafe { usermode::enter(entry, stack_top) }).id()
+}
+
+fn has_exited(id: ThreadId) -> bool {
+    thread::state(id).map_or(true, |state| state == ThreadState::Exited)
+}
+
+fn read_data(program: &Program, index: usize) -> u64 {
+    unsafe { ptr::read_volatile(program.data.as_ptr::<u64>().add(index)) }
+}
+
+/// Assembles the few instructions the test programs need.
+#[derive(Default)]
+struct Code(Vec<u8>);
+
+const RAX: u8 = 0;
+const RDX: u8 = 2;
+const RBX: u8 = 3;
+const RSI: u8 = 6;
+const RDI: u8 = 7;
+
+impl Code {
+    fn bytes(mut self, bytes: &[u8]) -> Self {
+        self.0.extend_from_slice(bytes);
+        self
+    }
+
+    /// `mov reg, imm64`
+    fn mov(self, register: u8, value: u64) -> Self {
+        self.bytes(&[0x48, 0xB8 + register])
+            .bytes(&value.to_le_bytes())
+    }
+
+    /// Stores rax at `address`, clobbering rbx.
+    fn store_rax(self, address: VirtAddr) -> Self {
+        self.mov(RBX, address.as_u64())
+            // mov [rbx], rax
+            .bytes(&[0x48, 0x89, 0x03])
+    }
+
+    fn syscall(self, number: u64, args: &[u64]) -> Self {
+        let mut code = self.mov(RAX, number);
+        for (&register, &arg) in [RDI, RSI, RDX].iter().zip(args) {
+            code = code.mov(register, arg);
+        }
+        code.bytes(&[0x0F, 0x05])
+    }
+
+    fn hlt(self) -> Self {
+        self.bytes(&[0xF4])
+    }
+}
+
+#[test_case]
+fn privileged_instruction_kills_program() {
+    // hlt
+    let program = map_program(0);
+    write_code(&program, &Code::default().hlt().0);
+    let id = spawn(&program);
+    wait_for(|| has_exited(id));
+}
+
+#[test_case]
+fn interrupts_return_to_user_mode() {
+    let program = map_program(1);
+    let code = Code::default()
+        .mov(RAX, program.data.as_u64())
+        .bytes(&[
+            0x48, 0xFF, 0x00, // loop: inc qword [rax]
+            0x48, 0x83, 0x78, 0x08, 0x00, // cmp qword [rax + 8], 0
+            0x74, 0xF6, // je loop
+        ])
+        .hlt();
+    write_code(&program, &code.0);
+
+    let id = spawn(&program);
+    // the counter keeps growing across timer interrupts and thread switches
+    let ticks = rust_os::time::ticks();
+    wait_for(|| rust_os::time::ticks() > ticks + 2);
+    let count = read_data(&program, 0);
+    assert!(count > 0);
+    wait_for(|| read_data(&program, 0) > count);
+    assert!(!has_exited(id));
+
+    // ending the loop runs into the `hlt`
+    unsafe { ptr::write_volatile(program.data.as_mut_ptr::<u64>().add(1), 1) };
+    wait_for(|| has_exited(id));
+}
+
+#[test_case]
+fn kernel_memory_is_not_accessible() {
+    let secret = Box::new(42u64);
+    let code = Code::default()
+        .mov(RAX, &*secret as *const u64 as u64)
+        // mov qword [rax], 0
+        .bytes(&[0x48, 0xC7, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    let program = map_program(2);
+    write_code(&program, &code.0);
+
+    let id = spawn(&program);
+    wait_for(|| has_exited(id));
+    assert_eq!(*secret, 42);
+}
+
+#[test_case]
+fn kernel_survives_faulting_programs() {
+    let handle = thread::spawn(|| 6 * 7);
+    assert_eq!(handle.join(), 42);
+}
+
+#[test_case]
+fn syscall_returns_to_user_mode() {
+    let program = map_program(3);
+    let code = Code::default()
+        .syscall(syscall::GETPID, &[])
+        .store_rax(program.data)
+        .syscall(syscall::YIELD, &[])
+        .store_rax(program.data + 8u64)
+        .syscall(syscall::EXIT, &[0]);
+    write_code(&program, &code.0);
+
+    let id = spawn(&program);
+    wait_for(|| has_exited(id));
+    // the thread does not belong to a process
+    assert_eq!(
+        Errno::from_return_value(read_data(&program, 0)),
+        Some(Errno::ESRCH)
+    );
+    assert_eq!(read_data(&program, 1), 0);
+}
+
+#[test_case]
+fn unknown_syscall_fails() {
+    let program = map_program(4);
+    let code = Code::default()
+        .syscall(1000, &[])
+        .store_rax(program.data)
+        .syscall(syscall::EXIT, &[0]);
+    write_code(&program, &code.0);
+
+    let id = spawn(&program);
+    wait_for(|| has_exited(id));
+    assert_eq!(
+        Errno::from_return_value(read_data(&program, 0)),
+        Some(Errno::ENOSYS)
+    );
+}
+
+#[test_case]
+fn write_checks_buffer() {
+    let program = map_program(5);
+    let message = b"hello from user mode\n";
+    let kernel_buffer = message.as_ptr() as u64;
+    let user_buffer = program.data + 0x100u64;
+    unsafe {
+        ptr::copy_nonoverlapping(message.as_ptr(), user_buffer.as_mut_ptr(), message.len());
+    }
+    let length = message.len() as u64;
+    let code = Code::default()
+        .syscall(
+            syscall::WRITE,
+            &[u64::from(syscall::STDOUT), user_buffer.as_u64(), length],
+        )
+        .store_rax(program.data)
+        .syscall(
+            syscall::WRITE,
+            &[u64::from(syscall::STDOUT), kernel_buffer, length],
+        )
+        .store_rax(program.data + 8u64)
+        .syscall(syscall::WRITE, &[42, user_buffer.as_u64(), length])
+        .store_rax(program.data + 16u64)
+        .syscall(syscall::EXIT, &[0]);
+    write_code(&program, &code.0);
+
+    let id = spawn(&program);
+    wait_for(|| has_exited(id));
+    assert_eq!(read_data(&program, 0), length);
+    assert_eq!(
+        Errno::from_return_value(read_data(&program, 1)),
+        Some(Errno::EFAULT)
+    );
+    assert_eq!(
+        Errno::from_return_value(read_data(&program, 2)),
+        Some(Errno::EBADF)
+    );
+}
+
+#[test_case]
+fn sleep_blocks_for_the_duration() {
+    let program = map_program(6);
+    let code = Code::default()
+        .syscall(syscall::SLEEP, &[20_000_000])
+        .syscall(syscall::EXIT, &[0]);
+    write_code(&program, &code.0);
+
+    let start = Instant::now();
+    let id = spawn(&program);
+    wait_for(|| has_exited(id));
+    assert!(start.elapsed() >= Duration::from_millis(20));
+}
+
+#[test_case]
+fn copy_from_user_recovers_from_faults() {
+    let program = map_program(7);
+    let stack_end = program.stack_top;
+    unsafe { ptr::write_volatile(program.data.as_mut_ptr::<u64>(), 0x1122_3344) };
+
+    let mut buffer = [0; 8];
+    assert_eq!(usermode::copy_from_user(&mut buffer, program.data), Ok(()));
+    assert_eq!(u64::from_le_bytes(buffer), 0x1122_3344);
+    // the page after the stack is not mapped
+    assert_eq!(
+        usermode::copy_from_user(&mut buffer, stack_end),
+        Err(Errno::EFAULT)
+    );
+    assert_eq!(
+        usermode::copy_from_user(&mut buffer, stack_end - 4u64),
+        Err(Errno::EFAULT)
+    );
+}
+
+#[test_case]
+fn copy_to_user_recovers_from_faults() {
+    let program = map_program(8);
+    assert_eq!(usermode::copy_to_user(program.data, &[1, 2, 3]), Ok(()));
+    assert_eq!(read_data(&program, 0), 0x03_02_01);
+    // the code page is read-only
+    assert_eq!(
+        usermode::copy_to_user(program.code, &[1, 2, 3]),
+        Err(Errno::EFAULT)
+    );
+    assert_eq!(
+        usermode::copy_to_user(program.stack_top, &[1, 2, 3]),
+        Err(Errno::EFAULT)
+    );
+}
+
+#[test_case]
+fn strncpy_from_user_stops_at_null_byte() {
+    let program = map_program(9);
+    let string = program.stack_top - 6u64;
+    unsafe { ptr::copy_nonoverlapping(b"hi\0rest".as_ptr(), program.data.as_mut_ptr(), 7) };
+    unsafe { ptr::copy_nonoverlapping(b"abcdef".as_ptr(), string.as_mut_ptr(), 6) };
+
+    let mut buffer = [0xFF; 8];
+    assert_eq!(
+        usermode::strncpy_from_user(&mut buffer, program.data),
+        Ok(2)
+    );
+    assert_eq!(&buffer[..4], b"hi\0\xFF");
+    // too long for the buffer
+    assert_eq!(usermode::strncpy_from_user(&mut buffer[..3], string), Ok(3));
+    assert_eq!(&buffer[..3], b"abc");
+    // runs into the unmapped page
+    assert_eq!(
+        usermode::strncpy_from_user(&mut buffer, string),
+        Err(Errno::EFAULT)
+    );
+}
+
+#[test_case]
+fn write_stops_at_unmapped_page() {
+    let program = map_program(10);
+    let end = program.stack_top;
+    unsafe { ptr::copy_nonoverlapping(b"abcd".as_ptr(), (end - 4u64).as_mut_ptr(), 4) };
+    let code = Code::default()
+        .syscall(
+            syscall::WRITE,
+            &[u64::from(syscall::STDOUT), end.as_u64() - 4, 8],
+        )
+        .store_rax(program.data)
+        .syscall(
+            syscall::WRITE,
+            &[u64::from(syscall::STDOUT), end.as_u64(), 8],
+        )
+        .store_rax(program.data + 8u64)
+        .syscall(syscall::EXIT, &[0]);
+    write_code(&program, &code.0);
+
+    let id = spawn(&program);
+    wait_for(|| has_exited(id));
+    assert_eq!(read_data(&program, 0), 4);
+    assert_eq!(
+        Errno::from_return_value(read_data(&program, 1)),
+        Some(Errno::EFAULT)
+    );
+}
+
+#[test_case]
+fn system_calls_do_not_write_read_only_memory() {
+    let program = map_program(11);
+    // behind the code, on the read-only code page
+    let handles = program.code + 0x800u64;
+    let code = Code::default()
+        .syscall(syscall::PIPE, &[handles.as_u64()])
+        .store_rax(program.data)
+        .syscall(syscall::EXIT, &[0]);
+    write_code(&program, &code.0);
+
+    let id = spawn(&program);
+    wait_for(|| has_exited(id));
+    assert_eq!(
+        Errno::from_return_value(read_data(&program, 0)),
+        Some(Errno::EFAULT)
+    );
+    assert_eq!(unsafe { ptr::read_volatile(handles.as_ptr::<u64>()) }, 0);
+}
+
+#[test_case]
+fn failed_mapping_returns_the_frame() {
+    let program = map_program(12);
+    let page = Page::<Size4KiB>::containing_address(program.data);
+    let allocated = frames::allocated();
+    let result = usermode::map_user_pages(
+        &mut MEMORY.lock().as_mut().unwrap().mapper,
+        &mut GlobalFrameAllocator,
+        Page::range(page, page + 1),
+        PageTableFlags::WRITABLE,
+    );
+    assert!(matches!(result, Err(MapToError::PageAlreadyMapped(_))));
+    assert_eq!(frames::allocated(), allocated);
+}
@@ -0,0 +1,118 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::boot_info::FrameBuffer;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use rust_os::sync::Event;
+use rust_os::thread;
+use rust_os::workqueue::{self, WorkError};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+entry_point!(ktest_main);
+
+fn ktest_main(boot_info: &'static mut BootInfo) -> ! {
+    use rust_os::allocator;
+    use rust_os::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    if let Some(fb) = boot_info.framebuffer.as_mut() {
+        init(fb);
+    }
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset.into_option().unwrap());
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_regions) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    thread::init();
+    workqueue::init();
+
+    test_main();
+    loop {}
+}
+
+pub fn init(fb: &'static mut FrameBuffer) {
+    let fb_info = fb.info();
+
+    unsafe {
+        rust_os::vga_buffer::init_global_writer(fb.buffer_mut(), fb_info);
+    }
+
+    rust_os::init();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+static DONE: Event = Event::new();
+static ORDER: Mutex<Vec<u64>> = Mutex::new(Vec::new());
+static SUM: AtomicU64 = AtomicU64::new(0);
+
+fn record(arg: u64) {
+    ORDER.lock().push(arg);
+}
+
+fn add(arg: u64) {
+    SUM.fetch_add(arg, Ordering::SeqCst);
+}
+
+fn signal_done(_: u64) {
+    DONE.set();
+}
+
+#[test_case]
+fn work_runs_in_queue_order() {
+    ORDER.lock().clear();
+    interrupts::without_interrupts(|| {
+        for i in 0..5 {
+            workqueue::schedule(record, i).unwrap();
+        }
+        workqueue::schedule(signal_done, 0).unwrap();
+    });
+    DONE.wait();
+    assert_eq!(*ORDER.lock(), [0, 1, 2, 3, 4]);
+}
+
+#[test_case]
+fn full_queue_drops_work() {
+    let before = workqueue::stats();
+    SUM.store(0, Ordering::SeqCst);
+    // with interrupts disabled the worker cannot drain the queue meanwhile
+    let result = interrupts::without_interrupts(|| {
+        for _ in 0..workqueue::QUEUE_SIZE {
+            workqueue::schedule(add, 1).unwrap();
+        }
+        workqueue::schedule(add, 1)
+    });
+    assert_eq!(result, Err(WorkError::QueueFull));
+
+    while workqueue::stats().depth > 0 {
+        thread::yield_now();
+    }
+    let after = workqueue::stats();
+    assert_eq!(after.dropped, before.dropped + 1);
+    assert_eq!(after.max_depth, workqueue::QUEUE_SIZE);
+    assert!(after.max_latency >= after.average_latency);
+}
+
+#[test_case]
+fn stats_count_executed_items() {
+    let before = workqueue::stats();
+    workqueue::schedule(add, 2).unwrap();
+    while workqueue::stats().executed < before.executed + 1 {
+        thread::yield_now();
+    }
+    let after = workqueue::stats();
+    assert_eq!(after.queued, before.queued + 1);
+    assert_eq!(after.depth, 0);
+}
@@ -0,0 +1,9 @@
+use std::env;
+use std::path::PathBuf;
+
+fn main() {
+    let manifest_dir = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap());
+    let script = manifest_dir.join("link.ld");
+    println!("cargo:rustc-link-arg-bins=--script={}", script.display());
+    println!("cargo:rerun-if-changed={}", script.display());
+}
@@ -0,0 +1,22 @@
+//! Prints its arguments and environment and exits with the number of
+//! arguments.
+
+#![no_std]
+#![no_main]
+
+use user::{entry, env, println};
+
+entry!(main);
+
+fn main() -> i32 {
+    for (index, arg) in env::args().enumerate() {
+        println!("argv[{}] = {}", index, arg);
+    }
+    for var in env::vars() {
+        println!("{}", var);
+    }
+    if env::args().next().is_none() {
+        return -1;
+    }
+    env::args().count() as i32
+}
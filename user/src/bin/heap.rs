@@ -0,0 +1,53 @@
+//! Exercises the heap and exits with 0 if all allocations behave.
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use user::syscall::{self, Errno};
+use user::{entry, println};
+
+entry!(main);
+
+fn main() -> i32 {
+    // far more than there is memory; a failed `sbrk` leaves the heap as it was
+    let end = syscall::sbrk(0).unwrap();
+    assert_eq!(syscall::sbrk(1 << 40), Err(Errno::ENOMEM));
+    assert_eq!(syscall::sbrk(0), Ok(end));
+
+    let boxed = Box::new(41);
+    assert_eq!(*boxed + 1, 42);
+
+    // grows the heap several times
+    let mut vec = Vec::new();
+    for i in 0..100_000u64 {
+        vec.push(i);
+    }
+    assert_eq!(vec.iter().sum::<u64>(), 99_999 * 100_000 / 2);
+
+    let mut string = String::new();
+    for word in ["user", "space", "heap"].iter() {
+        string.push_str(word);
+    }
+    assert_eq!(string, "userspaceheap");
+
+    let mut map = BTreeMap::new();
+    for i in 0..1000 {
+        map.insert(i, i * i);
+    }
+    assert_eq!(map.get(&30), Some(&900));
+
+    // freed memory is reused
+    for _ in 0..1000 {
+        let chunk: Vec<u8> = Vec::with_capacity(4096);
+        drop(chunk);
+    }
+
+    println!("heap works");
+    0
+}
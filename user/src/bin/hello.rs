@@ -0,0 +1,13 @@
+//! Prints a greeting and exits with 0.
+
+#![no_std]
+#![no_main]
+
+use user::{entry, println, process};
+
+entry!(main);
+
+fn main() -> i32 {
+    println!("Hello from process {}!", process::id());
+    0
+}
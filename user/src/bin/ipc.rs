@@ -0,0 +1,55 @@
+//! Talks to itself through a pipe and a channel and exits with 0 if
+//! everything arrives.
+
+#![no_std]
+#![no_main]
+
+use user::syscall::{self, Errno};
+use user::{entry, println};
+
+entry!(main);
+
+fn main() -> i32 {
+    let (reader, writer) = syscall::pipe().unwrap();
+    assert_eq!(syscall::write(writer, b"through the pipe"), Ok(16));
+    let mut buffer = [0; 32];
+    assert_eq!(syscall::read(reader, &mut buffer), Ok(16));
+    assert_eq!(&buffer[..16], b"through the pipe");
+
+    // the write end moves through the channel
+    let (first, second) = syscall::channel().unwrap();
+    syscall::send(first, b"writer", &[writer]).unwrap();
+    assert_eq!(syscall::write(writer, b"gone"), Err(Errno::EBADF));
+    let mut handles = [0; 4];
+    let (len, count) = syscall::recv(second, &mut buffer, &mut handles).unwrap();
+    assert_eq!(&buffer[..len], b"writer");
+    assert_eq!(count, 1);
+    let writer = handles[0];
+    assert_eq!(syscall::write(writer, b"moved"), Ok(5));
+    assert_eq!(syscall::read(reader, &mut buffer), Ok(5));
+
+    // a message that does not fit stays queued
+    syscall::send(first, b"too long", &[]).unwrap();
+    assert_eq!(
+        syscall::recv(second, &mut buffer[..4], &mut []),
+        Err(Errno::EMSGSIZE)
+    );
+    assert_eq!(syscall::recv(second, &mut buffer, &mut []), Ok((8, 0)));
+
+    // an endpoint cannot travel through its own channel
+    assert_eq!(syscall::send(first, b"", &[second]), Err(Errno::EINVAL));
+
+    // closing all write ends is the end of file
+    syscall::close(writer).unwrap();
+    assert_eq!(syscall::read(reader, &mut buffer), Ok(0));
+
+    syscall::close(first).unwrap();
+    assert_eq!(
+        syscall::recv(second, &mut buffer, &mut []),
+        Err(Errno::EPIPE)
+    );
+    assert_eq!(syscall::send(second, b"", &[]), Err(Errno::EPIPE));
+
+    println!("ipc works");
+    0
+}
@@ -0,0 +1,12 @@
+//! Panics, which exits with `PANIC_EXIT_CODE`.
+
+#![no_std]
+#![no_main]
+
+use user::entry;
+
+entry!(main);
+
+fn main() -> i32 {
+    panic!("panic from user space");
+}
@@ -0,0 +1,52 @@
+//! Receives a shared memory object over the channel with handle 0 and
+//! checks or changes its contents, depending on its argument:
+//!
+//! - `write` expects "from the kernel" at offset 0 and writes "from user
+//!   space" at offset 64
+//! - `read` expects "from user space" at offset 64
+//! - `read-only` maps the memory read-only and writes to it, which kills the
+//!   process
+//! - `huge` leaves it alone and fails to create objects larger than the
+//!   memory
+
+#![no_std]
+#![no_main]
+
+use core::{ptr, slice};
+use user::syscall::{self, Errno, SHM_WRITE};
+use user::{entry, env};
+
+entry!(main);
+
+const CHANNEL: u32 = 0;
+
+fn main() -> i32 {
+    let mode = env::args().nth(1).unwrap_or("");
+    let mut handles = [0; 1];
+    assert_eq!(syscall::recv(CHANNEL, &mut [], &mut handles), Ok((0, 1)));
+    let memory = handles[0];
+    if mode == "huge" {
+        assert_eq!(syscall::shm_create(1 << 46), Err(Errno::ENOMEM));
+        assert_eq!(syscall::shm_create(usize::MAX), Err(Errno::ENOMEM));
+        return 0;
+    }
+    let flags = if mode == "read-only" { 0 } else { SHM_WRITE };
+    let address = syscall::shm_map(memory, flags).unwrap();
+    // the mapping outlives the handle
+    syscall::close(memory).unwrap();
+
+    {
+        let bytes = unsafe { slice::from_raw_parts_mut(address, 4096) };
+        match mode {
+            "write" => {
+                assert_eq!(&bytes[..15], b"from the kernel");
+                bytes[64..79].copy_from_slice(b"from user space");
+            }
+            "read" => assert_eq!(&bytes[64..79], b"from user space"),
+            "read-only" => unsafe { ptr::write_volatile(address, 1) },
+            _ => return 2,
+        }
+    }
+    unsafe { syscall::shm_unmap(address).unwrap() };
+    0
+}
@@ -0,0 +1,28 @@
+//! Spawns `hello` as a child, depending on its argument:
+//!
+//! - `wait` waits for it and for the errors of missing children and
+//!   programs, and exits with 0
+//! - `orphan` leaves it running and exits with its PID
+
+#![no_std]
+#![no_main]
+
+use user::syscall::Errno;
+use user::{entry, env, process};
+
+entry!(main);
+
+fn main() -> i32 {
+    let mode = env::args().nth(1).unwrap_or("");
+    let child = process::spawn("hello").unwrap();
+    match mode {
+        "wait" => {
+            assert_eq!(process::wait_any(), Ok((child, 0)));
+            assert_eq!(process::wait(child), Err(Errno::ECHILD));
+            assert_eq!(process::spawn("missing"), Err(Errno::ENOENT));
+            0
+        }
+        "orphan" => child as i32,
+        _ => 2,
+    }
+}
@@ -0,0 +1,74 @@
+//! The arguments and the environment of the program.
+//!
+//! The kernel passes them on the initial stack like the System V ABI: `argc`,
+//! then `argc` pointers to the arguments and a null pointer, then pointers to
+//! the `KEY=value` environment strings and a null pointer. All strings are
+//! null-terminated and stay on the stack for the lifetime of the program.
+
+use core::sync::atomic::{AtomicPtr, Ordering};
+use core::{ptr, slice, str};
+
+static ARGV: AtomicPtr<*const u8> = AtomicPtr::new(ptr::null_mut());
+static ENVP: AtomicPtr<*const u8> = AtomicPtr::new(ptr::null_mut());
+
+/// Remembers the arguments and environment on the initial stack at `stack`.
+///
+/// This function is unsafe because `stack` must point to the initial stack
+/// set up by the kernel.
+pub(crate) unsafe fn init(stack: *const u64) {
+    let argc = *stack as usize;
+    let argv = stack.add(1) as *mut *const u8;
+    ARGV.store(argv, Ordering::Relaxed);
+    ENVP.store(argv.add(argc + 1), Ordering::Relaxed);
+}
+
+/// Returns the null-terminated string at `ptr`. Strings that are not UTF-8
+/// come out as empty strings.
+unsafe fn c_str(ptr: *const u8) -> &'static str {
+    let mut len = 0;
+    while *ptr.add(len) != 0 {
+        len += 1;
+    }
+    str::from_utf8(slice::from_raw_parts(ptr, len)).unwrap_or("")
+}
+
+/// An iterator over a null-terminated array of string pointers.
+#[derive(Clone)]
+pub struct Strings {
+    next: *const *const u8,
+}
+
+impl Iterator for Strings {
+    type Item = &'static str;
+
+    fn next(&mut self) -> Option<&'static str> {
+        if self.next.is_null() {
+            return None;
+        }
+        let string = unsafe { *self.next };
+        if string.is_null() {
+            return None;
+        }
+        self.next = unsafe { self.next.add(1) };
+        Some(unsafe { c_str(string) })
+    }
+}
+
+/// Returns the arguments of the program, starting with its name.
+pub fn args() -> Strings {
+    Strings {
+        next: ARGV.load(Ordering::Relaxed),
+    }
+}
+
+/// Returns the environment of the program as `KEY=value` strings.
+pub fn vars() -> Strings {
+    Strings {
+        next: ENVP.load(Ordering::Relaxed),
+    }
+}
+
+/// Returns the value of the environment variable `key`.
+pub fn var(key: &str) -> Option<&'static str> {
+    vars().find_map(|var| var.strip_prefix(key)?.strip_prefix('='))
+}
@@ -0,0 +1,181 @@
+//! The heap of the program, a linked list allocator like the kernel's.
+//!
+//! The heap starts out empty. When no free region fits an allocation, it grows
+//! by at least `GROW_SIZE` bytes through the `sbrk` system call.
+
+use crate::syscall;
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::{mem, ptr};
+
+const GROW_SIZE: usize = 64 * 1024;
+
+#[global_allocator]
+static ALLOCATOR: Heap = Heap {
+    locked: AtomicBool::new(false),
+    allocator: UnsafeCell::new(LinkedListAllocator {
+        head: ListNode::new(0),
+    }),
+};
+
+struct ListNode {
+    size: usize,
+    next: Option<&'static mut ListNode>,
+}
+
+impl ListNode {
+    const fn new(size: usize) -> Self {
+        ListNode { size, next: None }
+    }
+
+    fn start_addr(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    fn end_addr(&self) -> usize {
+        self.start_addr() + self.size
+    }
+}
+
+struct LinkedListAllocator {
+    head: ListNode,
+}
+
+impl LinkedListAllocator {
+    /// Adds the given memory region to the front of the list.
+    unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
+        debug_assert!(size >= mem::size_of::<ListNode>());
+        debug_assert_eq!(align_up(addr, mem::align_of::<ListNode>()), addr);
+
+        let mut node = ListNode::new(size);
+        node.next = self.head.next.take();
+        let node_ptr = addr as *mut ListNode;
+        node_ptr.write(node);
+        self.head.next = Some(&mut *node_ptr);
+    }
+
+    /// Looks for a free region with the given size and alignment and removes
+    /// it from the list.
+    ///
+    /// Returns the list node and the start address of the allocation.
+    fn find_region(&mut self, size: usize, align: usize) -> Option<(&'static mut ListNode, usize)> {
+        let mut current = &mut self.head;
+        while let Some(ref mut region) = current.next {
+            if let Ok(alloc_start) = Self::alloc_from_region(region, size, align) {
+                let next = region.next.take();
+                let ret = Some((current.next.take().unwrap(), alloc_start));
+                current.next = next;
+                return ret;
+            } else {
+                current = current.next.as_mut().unwrap();
+            }
+        }
+        None
+    }
+
+    fn alloc_from_region(region: &ListNode, size: usize, align: usize) -> Result<usize, ()> {
+        let alloc_start = align_up(region.start_addr(), align);
+        let alloc_end = alloc_start.checked_add(size).ok_or(())?;
+        if alloc_end > region.end_addr() {
+            return Err(());
+        }
+        let remaining_size = region.end_addr() - alloc_end;
+        if remaining_size > 0 && remaining_size < mem::size_of::<ListNode>() {
+            return Err(());
+        }
+        Ok(alloc_start)
+    }
+
+    /// Adjusts the layout so that the allocated region can also hold a
+    /// `ListNode`, and returns the size and alignment.
+    fn size_align(layout: Layout) -> (usize, usize) {
+        let layout = layout
+            .align_to(mem::align_of::<ListNode>())
+            .expect("adjusting alignment failed")
+            .pad_to_align();
+        let size = layout.size().max(mem::size_of::<ListNode>());
+        (size, layout.align())
+    }
+
+    /// Grows the heap so that an allocation of `size` bytes with `align` fits
+    /// into the new region. Returns `false` if the kernel has no memory left.
+    unsafe fn grow(&mut self, size: usize, align: usize) -> bool {
+        let increment = match size.checked_add(align) {
+            Some(needed) => needed.max(GROW_SIZE),
+            None => return false,
+        };
+        match syscall::sbrk(increment) {
+            Ok(start) => {
+                // `sbrk` rounds up to whole pages
+                let end = align_up(start + increment, 4096);
+                self.add_free_region(start, end - start);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let (size, align) = Self::size_align(layout);
+        let (region, alloc_start) = match self.find_region(size, align) {
+            Some(found) => found,
+            None if self.grow(size, align) => match self.find_region(size, align) {
+                Some(found) => found,
+                None => return ptr::null_mut(),
+            },
+            None => return ptr::null_mut(),
+        };
+        let alloc_end = alloc_start + size;
+        let remaining_size = region.end_addr() - alloc_end;
+        if remaining_size > 0 {
+            self.add_free_region(alloc_end, remaining_size);
+        }
+        alloc_start as *mut u8
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        // freed regions are not merged, like in the kernel
+        let (size, _) = Self::size_align(layout);
+        self.add_free_region(ptr as usize, size);
+    }
+}
+
+/// The allocator behind a spin lock, which `GlobalAlloc` needs to be shared.
+/// Programs have a single thread, so it is never contended.
+struct Heap {
+    locked: AtomicBool,
+    allocator: UnsafeCell<LinkedListAllocator>,
+}
+
+unsafe impl Sync for Heap {}
+
+impl Heap {
+    fn with<T>(&self, f: impl FnOnce(&mut LinkedListAllocator) -> T) -> T {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        let result = f(unsafe { &mut *self.allocator.get() });
+        self.locked.store(false, Ordering::Release);
+        result
+    }
+}
+
+unsafe impl GlobalAlloc for Heap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.with(|allocator| allocator.alloc(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.with(|allocator| allocator.dealloc(ptr, layout))
+    }
+}
+
+/// Aligns `addr` upwards to `align`, which must be a power of two.
+fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
@@ -0,0 +1,75 @@
+//! Printing to the standard output and error output.
+
+use crate::syscall::{self, Errno};
+use core::fmt;
+
+/// The standard output, handle `syscall::STDOUT`.
+pub struct Stdout;
+
+/// The standard error output, handle `syscall::STDERR`.
+pub struct Stderr;
+
+/// Writes all of `bytes` to `handle`, repeating the `write` system call after
+/// partial writes.
+pub fn write_all(handle: u32, mut bytes: &[u8]) -> Result<(), Errno> {
+    while !bytes.is_empty() {
+        match syscall::write(handle, bytes)? {
+            0 => return Err(Errno::EFAULT),
+            written => bytes = &bytes[written..],
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Write for Stdout {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        write_all(syscall::STDOUT, s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+impl fmt::Write for Stderr {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        write_all(syscall::STDERR, s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+/// Prints to the standard output.
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::io::_print(format_args!($($arg)*)));
+}
+
+/// Prints to the standard output, appending a newline.
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+/// Prints to the standard error output.
+#[macro_export]
+macro_rules! eprint {
+    ($($arg:tt)*) => ($crate::io::_eprint(format_args!($($arg)*)));
+}
+
+/// Prints to the standard error output, appending a newline.
+#[macro_export]
+macro_rules! eprintln {
+    () => ($crate::eprint!("\n"));
+    ($($arg:tt)*) => ($crate::eprint!("{}\n", format_args!($($arg)*)));
+}
+
+/// Prints the given formatted string to the standard output.
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    use core::fmt::Write;
+    // like the kernel's `print!`, there is nowhere to report errors to
+    let _ = Stdout.write_fmt(args);
+}
+
+/// Prints the given formatted string to the standard error output.
+#[doc(hidden)]
+pub fn _eprint(args: fmt::Arguments) {
+    use core::fmt::Write;
+    let _ = Stderr.write_fmt(args);
+}
@@ -0,0 +1,71 @@
+//! The runtime of user programs.
+//!
+//! A program links this crate, defines `fn main() -> i32` and registers it
+//! with `entry!`. The runtime's `_start` picks up the arguments and the
+//! environment from the initial stack (see `env`), runs `main` and exits with
+//! its return value. A panic prints its message to the standard error output
+//! and exits with `PANIC_EXIT_CODE`.
+//!
+//! `alloc` works on a heap that grows through the `sbrk` system call.
+
+#![no_std]
+#![feature(alloc_error_handler)]
+
+extern crate alloc;
+
+pub mod env;
+mod heap;
+pub mod io;
+pub mod process;
+pub mod syscall;
+
+use core::arch::global_asm;
+use core::panic::PanicInfo;
+
+/// The exit code after a panic, like in Rust's standard library.
+pub const PANIC_EXIT_CODE: i32 = 101;
+
+/// Makes `$main`, a `fn() -> i32`, the main function of the program.
+#[macro_export]
+macro_rules! entry {
+    ($main:path) => {
+        #[export_name = "__user_main"]
+        pub fn __user_main() -> i32 {
+            // check the signature
+            let main: fn() -> i32 = $main;
+            main()
+        }
+    };
+}
+
+extern "Rust" {
+    fn __user_main() -> i32;
+}
+
+// The kernel enters with the stack pointer at `argc` and 16-byte aligned, so
+// it is still aligned for the call.
+global_asm!(
+    ".global _start",
+    "_start:",
+    "mov rdi, rsp",
+    "call __user_start",
+    "ud2",
+);
+
+#[no_mangle]
+unsafe extern "C" fn __user_start(stack: *const u64) -> ! {
+    env::init(stack);
+    let code = __user_main();
+    process::exit(code)
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    eprintln!("{}", info);
+    process::exit(PANIC_EXIT_CODE)
+}
+
+#[alloc_error_handler]
+fn alloc_error_handler(layout: alloc::alloc::Layout) -> ! {
+    panic!("allocation error: {:?}", layout)
+}
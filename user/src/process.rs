@@ -0,0 +1,20 @@
+//! The current process and its children.
+
+use crate::syscall::{self, Errno};
+
+pub use crate::syscall::{exit, spawn, yield_now};
+
+/// Returns the PID of the current process.
+pub fn id() -> u64 {
+    syscall::getpid().expect("getpid failed")
+}
+
+/// Waits for the child `pid` to exit and returns its exit code.
+pub fn wait(pid: u64) -> Result<i32, Errno> {
+    syscall::wait(pid).map(|(_, code)| code)
+}
+
+/// Waits for any child to exit and returns its PID and exit code.
+pub fn wait_any() -> Result<(u64, i32), Errno> {
+    syscall::wait(syscall::ANY_CHILD)
+}
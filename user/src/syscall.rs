@@ -0,0 +1,226 @@
+//! Raw system calls.
+//!
+//! The numbers and the calling convention match the kernel's `syscall`
+//! module: the number goes into `rax`, the arguments into `rdi`, `rsi`,
+//! `rdx`, `r10`, `r8` and `r9`, and a result between -4095 and -1 is a
+//! negated error number.
+
+use core::arch::asm;
+use core::fmt;
+
+pub const EXIT: u64 = 0;
+pub const WRITE: u64 = 1;
+pub const GETPID: u64 = 2;
+pub const YIELD: u64 = 3;
+pub const SLEEP: u64 = 4;
+pub const WAIT: u64 = 5;
+pub const SBRK: u64 = 6;
+pub const READ: u64 = 7;
+pub const CLOSE: u64 = 8;
+pub const PIPE: u64 = 9;
+pub const CHANNEL: u64 = 10;
+pub const SEND: u64 = 11;
+pub const RECV: u64 = 12;
+pub const SHM_CREATE: u64 = 13;
+pub const SHM_MAP: u64 = 14;
+pub const SHM_UNMAP: u64 = 15;
+pub const SPAWN: u64 = 16;
+
+/// The `flags` of `shm_map`; shared memory is always readable.
+pub const SHM_WRITE: u64 = 1 << 1;
+pub const SHM_EXEC: u64 = 1 << 2;
+
+/// The `pid` argument of `wait` that waits for any child.
+pub const ANY_CHILD: u64 = u64::MAX;
+
+pub const STDOUT: u32 = 1;
+pub const STDERR: u32 = 2;
+
+/// An error number returned by a system call, with the values of Linux.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Errno(pub u16);
+
+impl Errno {
+    pub const ENOENT: Errno = Errno(2);
+    pub const ESRCH: Errno = Errno(3);
+    pub const ENOEXEC: Errno = Errno(8);
+    pub const EBADF: Errno = Errno(9);
+    pub const ECHILD: Errno = Errno(10);
+    pub const ENOMEM: Errno = Errno(12);
+    pub const EFAULT: Errno = Errno(14);
+    pub const EINVAL: Errno = Errno(22);
+    pub const EMFILE: Errno = Errno(24);
+    pub const EPIPE: Errno = Errno(32);
+    pub const ENOSYS: Errno = Errno(38);
+    pub const EMSGSIZE: Errno = Errno(90);
+}
+
+impl fmt::Display for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "error {}", self.0)
+    }
+}
+
+/// Makes system call `number` with up to six arguments.
+///
+/// This function is unsafe because the arguments may tell the kernel to
+/// read or write any memory of the program.
+pub unsafe fn syscall(number: u64, args: &[u64]) -> Result<u64, Errno> {
+    let arg = |index: usize| args.get(index).copied().unwrap_or(0);
+    let result: u64;
+    asm!(
+        "syscall",
+        inlateout("rax") number => result,
+        in("rdi") arg(0),
+        in("rsi") arg(1),
+        in("rdx") arg(2),
+        in("r10") arg(3),
+        in("r8") arg(4),
+        in("r9") arg(5),
+        lateout("rcx") _,
+        lateout("r11") _,
+        options(nostack)
+    );
+    if result > -4096i64 as u64 {
+        Err(Errno(result.wrapping_neg() as u16))
+    } else {
+        Ok(result)
+    }
+}
+
+pub fn exit(code: i32) -> ! {
+    unsafe {
+        let _ = syscall(EXIT, &[code as u32 as u64]);
+    }
+    unreachable!("exit returned")
+}
+
+/// Writes the bytes of `buffer` that come before the first unmapped page and
+/// returns how many that are.
+pub fn write(handle: u32, buffer: &[u8]) -> Result<usize, Errno> {
+    let args = [
+        u64::from(handle),
+        buffer.as_ptr() as u64,
+        buffer.len() as u64,
+    ];
+    unsafe { syscall(WRITE, &args).map(|written| written as usize) }
+}
+
+pub fn getpid() -> Result<u64, Errno> {
+    unsafe { syscall(GETPID, &[]) }
+}
+
+pub fn yield_now() {
+    unsafe {
+        let _ = syscall(YIELD, &[]);
+    }
+}
+
+pub fn sleep(nanoseconds: u64) {
+    unsafe {
+        let _ = syscall(SLEEP, &[nanoseconds]);
+    }
+}
+
+/// Starts the program the kernel registered under `name` as a child and
+/// returns its PID.
+pub fn spawn(name: &str) -> Result<u64, Errno> {
+    // the kernel expects a null-terminated name
+    let mut buffer = [0u8; 64];
+    let bytes = name.as_bytes();
+    if bytes.len() >= buffer.len() {
+        return Err(Errno::ENOENT);
+    }
+    buffer[..bytes.len()].copy_from_slice(bytes);
+    unsafe { syscall(SPAWN, &[buffer.as_ptr() as u64]) }
+}
+
+/// Waits for the child `pid` or, with `ANY_CHILD`, for any child to exit and
+/// returns its PID and exit code.
+pub fn wait(pid: u64) -> Result<(u64, i32), Errno> {
+    let mut code = 0i32;
+    let pid = unsafe { syscall(WAIT, &[pid, &mut code as *mut i32 as u64])? };
+    Ok((pid, code))
+}
+
+/// Grows the heap by `increment` bytes, rounded up to whole pages, and
+/// returns the address of the new memory.
+pub fn sbrk(increment: usize) -> Result<usize, Errno> {
+    unsafe { syscall(SBRK, &[increment as u64]).map(|address| address as usize) }
+}
+
+/// Reads up to `buffer.len()` bytes from the read end of a pipe and returns
+/// how many were read, 0 at the end of file.
+pub fn read(handle: u32, buffer: &mut [u8]) -> Result<usize, Errno> {
+    let args = [
+        u64::from(handle),
+        buffer.as_mut_ptr() as u64,
+        buffer.len() as u64,
+    ];
+    unsafe { syscall(READ, &args).map(|read| read as usize) }
+}
+
+pub fn close(handle: u32) -> Result<(), Errno> {
+    unsafe { syscall(CLOSE, &[u64::from(handle)]).map(|_| ()) }
+}
+
+/// Creates a pipe and returns the handles of its read and its write end.
+pub fn pipe() -> Result<(u32, u32), Errno> {
+    let mut handles = [0u32; 2];
+    unsafe { syscall(PIPE, &[handles.as_mut_ptr() as u64])? };
+    Ok((handles[0], handles[1]))
+}
+
+/// Creates a channel and returns the handles of its two endpoints.
+pub fn channel() -> Result<(u32, u32), Errno> {
+    let mut handles = [0u32; 2];
+    unsafe { syscall(CHANNEL, &[handles.as_mut_ptr() as u64])? };
+    Ok((handles[0], handles[1]))
+}
+
+/// Sends `data` and the objects of `handles` over a channel. The handles are
+/// closed once the message is sent.
+pub fn send(handle: u32, data: &[u8], handles: &[u32]) -> Result<(), Errno> {
+    let args = [
+        u64::from(handle),
+        data.as_ptr() as u64,
+        data.len() as u64,
+        handles.as_ptr() as u64,
+        handles.len() as u64,
+    ];
+    unsafe { syscall(SEND, &args).map(|_| ()) }
+}
+
+/// Receives a message from a channel into `buffer` and `handles` and returns
+/// the number of bytes and of handles received.
+pub fn recv(handle: u32, buffer: &mut [u8], handles: &mut [u32]) -> Result<(usize, usize), Errno> {
+    let mut count = handles.len() as u64;
+    let args = [
+        u64::from(handle),
+        buffer.as_mut_ptr() as u64,
+        buffer.len() as u64,
+        handles.as_mut_ptr() as u64,
+        &mut count as *mut u64 as u64,
+    ];
+    let len = unsafe { syscall(RECV, &args)? };
+    Ok((len as usize, count as usize))
+}
+
+/// Creates a zeroed shared memory object of `size` bytes, rounded up to whole
+/// pages, and returns its handle.
+pub fn shm_create(size: usize) -> Result<u32, Errno> {
+    unsafe { syscall(SHM_CREATE, &[size as u64]).map(|handle| handle as u32) }
+}
+
+/// Maps a shared memory object with the given `SHM_*` flags and returns its
+/// address.
+pub fn shm_map(handle: u32, flags: u64) -> Result<*mut u8, Errno> {
+    unsafe { syscall(SHM_MAP, &[u64::from(handle), flags]).map(|address| address as *mut u8) }
+}
+
+/// Unmaps the shared memory mapped at `address`.
+///
+/// This function is unsafe because no references to the memory may be left.
+pub unsafe fn shm_unmap(address: *mut u8) -> Result<(), Errno> {
+    syscall(SHM_UNMAP, &[address as u64]).map(|_| ())
+}